@@ -0,0 +1,261 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::log_sinks::get_host_name;
+use crate::notifications::{
+    default_events, get_host, send_with_retries, Notification, NotificationEvent,
+};
+use crate::unending_process::LogConfig;
+
+const CHAT_RETRIES: u32 = 3;
+const DEFAULT_NTFY_SERVER: &str = "https://ntfy.sh";
+
+static MATRIX_TRANSACTION_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct ChatConfig {
+    pub service: ChatService,
+    //The incoming webhook url for slack and discord, the homeserver for matrix and the topic url for ntfy
+    pub url: String,
+    //Only used by matrix, like !abcdefg:matrix.org
+    pub room_id: Option<String>,
+    //The access token of the matrix account, or of a protected ntfy topic
+    pub token: Option<String>,
+    #[serde(default = "default_events")]
+    pub events: Vec<NotificationEvent>,
+}
+impl ChatConfig {
+    pub fn new(service: ChatService, url: String) -> Self {
+        ChatConfig {
+            service,
+            url,
+            room_id: None,
+            token: None,
+            events: default_events(),
+        }
+    }
+}
+#[derive(Deserialize, Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatService {
+    Slack,
+    Discord,
+    Matrix,
+    Ntfy,
+}
+pub const CHAT_SERVICES: &[ChatService] = &[
+    ChatService::Slack,
+    ChatService::Discord,
+    ChatService::Matrix,
+    ChatService::Ntfy,
+];
+impl ChatService {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChatService::Slack => "Slack",
+            ChatService::Discord => "Discord",
+            ChatService::Matrix => "Matrix",
+            ChatService::Ntfy => "ntfy",
+        }
+    }
+    pub fn url_prompt(self) -> &'static str {
+        match self {
+            ChatService::Slack => "The url of the slack incoming webhook",
+            ChatService::Discord => "The url of the discord webhook",
+            ChatService::Matrix => "The url of the matrix homeserver, like https://matrix.org",
+            ChatService::Ntfy => "The url of the ntfy topic, like https://ntfy.sh/my_topic",
+        }
+    }
+}
+impl FromStr for ChatService {
+    type Err = ();
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.to_lowercase().as_str() {
+            "slack" => Ok(ChatService::Slack),
+            "discord" => Ok(ChatService::Discord),
+            "matrix" => Ok(ChatService::Matrix),
+            "ntfy" => Ok(ChatService::Ntfy),
+            _ => Err(()),
+        }
+    }
+}
+pub fn send_chat(
+    chat: &ChatConfig,
+    notification: &Notification,
+    log_config: &LogConfig,
+) -> Result<(), String> {
+    let host = get_host(&chat.url);
+    match chat.service {
+        ChatService::Slack | ChatService::Discord => {
+            let body = match chat.service {
+                ChatService::Slack => get_slack_body(notification),
+                _ => get_discord_body(notification),
+            };
+            send_with_retries(host, notification, CHAT_RETRIES, log_config, |agent| {
+                agent.post(&chat.url).send_json(&body).map_err(Box::new)
+            })
+        }
+        ChatService::Matrix => {
+            let room_id = match &chat.room_id {
+                Some(room_id) => room_id,
+                None => return Err("matrix needs a room_id".to_string()),
+            };
+            let token = match &chat.token {
+                Some(token) => token,
+                None => return Err("matrix needs an access token".to_string()),
+            };
+            let body = get_matrix_body(notification);
+            //Every message needs its own transaction id, otherwise the homeserver treats it as a repeat of the last one
+            let url = format!(
+                "{}/_matrix/client/v3/rooms/{}/send/m.room.message/cf_dns_sync.{}.{}",
+                chat.url.trim_end_matches('/'),
+                encode_path_segment(room_id),
+                notification.time.timestamp_millis(),
+                MATRIX_TRANSACTION_COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            send_with_retries(host, notification, CHAT_RETRIES, log_config, |agent| {
+                agent
+                    .put(&url)
+                    .set("Authorization", &format!("Bearer {token}"))
+                    .send_json(&body)
+                    .map_err(Box::new)
+            })
+        }
+        ChatService::Ntfy => {
+            let url = get_ntfy_url(&chat.url);
+            let (tags, priority) = match notification.event {
+                NotificationEvent::IpChanged => ("globe_with_meridians", "default"),
+                NotificationEvent::RecordUpdated => ("white_check_mark", "low"),
+                NotificationEvent::SyncFailed => ("warning", "high"),
+                NotificationEvent::Recovered => ("white_check_mark", "default"),
+            };
+            let title = format!("{} ({})", notification.title(), get_host_name());
+            send_with_retries(
+                get_host(&url),
+                notification,
+                CHAT_RETRIES,
+                log_config,
+                |agent| {
+                    let mut request = agent
+                        .post(&url)
+                        .set("Title", &title)
+                        .set("Tags", tags)
+                        .set("Priority", priority);
+                    if let Some(token) = &chat.token {
+                        request = request.set("Authorization", &format!("Bearer {token}"));
+                    }
+                    request.send_string(&notification.text()).map_err(Box::new)
+                },
+            )
+        }
+    }
+}
+//A topic name alone is sent to ntfy.sh
+pub(crate) fn get_ntfy_url(url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("{DEFAULT_NTFY_SERVER}/{url}")
+    }
+}
+pub(crate) fn get_slack_body(notification: &Notification) -> Value {
+    let mut text = format!(
+        "*{}*\n{}",
+        escape_slack(&notification.title()),
+        escape_slack(&notification.message)
+    );
+    for (name, value) in notification.get_fields() {
+        text.push_str(&format!("\n*{name}:* {}", escape_slack(&value)));
+    }
+    if let Some(error) = &notification.error {
+        text.push_str(&format!("\n```{}```", escape_slack(error)));
+    }
+    json!({
+        "text": format!("{}: {}", notification.title(), notification.message),
+        "blocks": [
+            { "type": "section", "text": { "type": "mrkdwn", "text": text } },
+            {
+                "type": "context",
+                "elements": [{ "type": "mrkdwn", "text": format!("cf_dns_sync on {}", escape_slack(get_host_name())) }]
+            }
+        ]
+    })
+}
+pub(crate) fn get_discord_body(notification: &Notification) -> Value {
+    let color = match notification.event {
+        NotificationEvent::IpChanged => 0x3498db,
+        NotificationEvent::RecordUpdated | NotificationEvent::Recovered => 0x2ecc71,
+        NotificationEvent::SyncFailed => 0xe74c3c,
+    };
+    let mut fields: Vec<Value> = notification
+        .get_fields()
+        .into_iter()
+        .filter(|(name, _)| *name != "Time")
+        .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+        .collect();
+    if let Some(error) = &notification.error {
+        //Discord rejects field values longer than 1024 characters
+        let error: String = error.chars().take(1000).collect();
+        fields.push(json!({ "name": "Errors", "value": format!("```{error}```") }));
+    }
+    json!({
+        "username": "cf_dns_sync",
+        "embeds": [{
+            "title": notification.title(),
+            "description": notification.message,
+            "color": color,
+            "fields": fields,
+            "footer": { "text": format!("cf_dns_sync on {}", get_host_name()) },
+            "timestamp": notification.time.to_rfc3339(),
+        }]
+    })
+}
+//m.notice is the message type meant for bots, clients don't notify as loudly for it
+fn get_matrix_body(notification: &Notification) -> Value {
+    let mut html = format!(
+        "<strong>{}</strong><br>{}",
+        escape_html(&notification.title()),
+        escape_html(&notification.message)
+    );
+    for (name, value) in notification.get_fields() {
+        html.push_str(&format!("<br><em>{name}:</em> {}", escape_html(&value)));
+    }
+    if let Some(error) = &notification.error {
+        html.push_str(&format!("<pre>{}</pre>", escape_html(error)));
+    }
+    html.push_str(&format!(
+        "<br><sub>cf_dns_sync on {}</sub>",
+        escape_html(get_host_name())
+    ));
+    json!({
+        "msgtype": "m.notice",
+        "body": format!("{}\n{}", notification.title(), notification.text()),
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    })
+}
+//Slack only needs these three escaped in mrkdwn
+fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+fn escape_html(text: &str) -> String {
+    escape_slack(text).replace('"', "&quot;")
+}
+//Room ids contain ! and :, which have to be percent encoded in the url path
+pub(crate) fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::unending_process::format_err;
+
+pub const CONFIG_FILE_NAMES: &[&str] = &["config.json", "config.toml", "config.yaml", "config.yml"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+impl ConfigFormat {
+    //The format is chosen by the file extension, anything unknown is treated as json
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        let extension = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => extension.to_lowercase(),
+            None => return ConfigFormat::Json,
+        };
+        match extension.as_str() {
+            "toml" => ConfigFormat::Toml,
+            "yaml" | "yml" => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+        }
+    }
+}
+pub fn parse<T: DeserializeOwned>(contents: &str, format: ConfigFormat) -> Result<T, String> {
+    match format {
+        ConfigFormat::Json => serde_json::from_str(contents).map_err(|err| err.to_string()),
+        ConfigFormat::Toml => toml::from_str(contents).map_err(|err| err.to_string()),
+        ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|err| err.to_string()),
+    }
+}
+//Converts the config into the text of the given format. For toml, the previous contents of the file are
+//updated in place, so that comments and formatting written by hand survive the save. For yaml, the comments
+//of the previous contents are put back next to the same top-level keys.
+pub fn serialize<T: Serialize>(
+    config: &T,
+    format: ConfigFormat,
+    previous_contents: Option<&str>,
+) -> Result<String, String> {
+    match format {
+        ConfigFormat::Json => {
+            let string = serde_json::to_string(config).map_err(format_err)?;
+            let string = jsonformat::format(&string, jsonformat::Indentation::Tab);
+            Ok(string.replace("\\/", "/"))
+        }
+        ConfigFormat::Toml => {
+            let string = toml::to_string_pretty(config).map_err(format_err)?;
+            let new_document: DocumentMut = string.parse().map_err(format_err)?;
+            let mut document: DocumentMut = match previous_contents.map(|contents| contents.parse())
+            {
+                Some(Ok(document)) => document,
+                _ => return Ok(string),
+            };
+            merge_toml_tables(document.as_table_mut(), new_document.as_table());
+            Ok(document.to_string())
+        }
+        ConfigFormat::Yaml => {
+            let string = serde_yaml::to_string(config).map_err(format_err)?;
+            match previous_contents {
+                Some(previous_contents) => Ok(keep_yaml_comments(previous_contents, &string)),
+                None => Ok(string),
+            }
+        }
+    }
+}
+//Compares the meaning of two config files regardless of their formatting and comments
+pub fn is_same_content<T: Serialize>(config: &T, contents: &str, format: ConfigFormat) -> bool {
+    let new_value = match serde_json::to_value(config) {
+        Ok(value) => strip_nulls(value),
+        Err(_) => return false,
+    };
+    match parse::<Value>(contents, format) {
+        Ok(old_value) => strip_nulls(old_value) == new_value,
+        Err(_) => false,
+    }
+}
+//Toml has no null, so missing values and nulls are treated the same when comparing
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, strip_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(array) => Value::Array(array.into_iter().map(strip_nulls).collect()),
+        value => value,
+    }
+}
+fn merge_toml_tables(old: &mut Table, new: &Table) {
+    let removed_keys: Vec<String> = old
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in removed_keys {
+        old.remove(&key);
+    }
+    for (key, new_item) in new.iter() {
+        match old.get_mut(key) {
+            Some(old_item) => merge_toml_items(old_item, new_item),
+            None => {
+                old.insert(key, new_item.clone());
+            }
+        }
+    }
+}
+fn merge_toml_items(old: &mut Item, new: &Item) {
+    match (old, new) {
+        (Item::Table(old_table), Item::Table(new_table)) => merge_toml_tables(old_table, new_table),
+        (Item::ArrayOfTables(old_array), Item::ArrayOfTables(new_array)) => {
+            //DNS records can come back in a different order, so tables are matched by their id when they have one
+            let mut merged = ArrayOfTables::new();
+            for (index, new_table) in new_array.iter().enumerate() {
+                let old_table = match new_table.get("id").and_then(|id| id.as_str()) {
+                    Some(id) => old_array
+                        .iter()
+                        .find(|table| table.get("id").and_then(|id| id.as_str()) == Some(id)),
+                    None => old_array.get(index),
+                };
+                match old_table {
+                    Some(old_table) => {
+                        let mut table = old_table.clone();
+                        merge_toml_tables(&mut table, new_table);
+                        merged.push(table);
+                    }
+                    None => merged.push(new_table.clone()),
+                }
+            }
+            *old_array = merged;
+        }
+        (Item::Value(old_value), Item::Value(new_value)) => {
+            if !is_same_toml_value(old_value, new_value) {
+                let decor = old_value.decor().clone();
+                *old_value = new_value.clone();
+                *old_value.decor_mut() = decor;
+            }
+        }
+        (old, new) => *old = new.clone(),
+    }
+}
+fn is_same_toml_value(old: &toml_edit::Value, new: &toml_edit::Value) -> bool {
+    match (old, new) {
+        (toml_edit::Value::String(old), toml_edit::Value::String(new)) => {
+            old.value() == new.value()
+        }
+        (toml_edit::Value::Integer(old), toml_edit::Value::Integer(new)) => {
+            old.value() == new.value()
+        }
+        (toml_edit::Value::Boolean(old), toml_edit::Value::Boolean(new)) => {
+            old.value() == new.value()
+        }
+        (old, new) => {
+            old.clone().decorated("", "").to_string() == new.clone().decorated("", "").to_string()
+        }
+    }
+}
+//Serde_yaml can't write comments, so the comments above top-level keys, and after their value when it is a plain
+//scalar like `seconds_to_wait_per_restart: 300 # five minutes`, are put back next to the same key. Comments inside
+//sections and sequences are dropped, since matching nested lines would need a real yaml parser.
+fn keep_yaml_comments(previous_contents: &str, new_contents: &str) -> String {
+    let mut comments_above: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut comments_after: HashMap<&str, &str> = HashMap::new();
+    let mut pending: Vec<&str> = vec![];
+    for line in previous_contents.lines() {
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("---") {
+            pending.push(line);
+            continue;
+        }
+        match split_top_level_key(line) {
+            Some((key, value)) => {
+                if let Some(comment) = get_value_comment(value) {
+                    comments_after.insert(key, comment);
+                }
+                if !pending.is_empty() {
+                    comments_above.insert(key, std::mem::take(&mut pending));
+                }
+            }
+            //Comments inside a section belong to nested lines, which aren't matched
+            None => pending.clear(),
+        }
+    }
+    let mut lines: Vec<String> = vec![];
+    for line in new_contents.lines() {
+        let (key, value) = match split_top_level_key(line) {
+            Some(key_and_value) => key_and_value,
+            None => {
+                lines.push(line.to_string());
+                continue;
+            }
+        };
+        for comment in comments_above.remove(key).unwrap_or_default() {
+            lines.push(comment.to_string());
+        }
+        match comments_after.get(key) {
+            Some(comment) if is_plain_value(value) && !value.contains('#') => {
+                lines.push(format!("{line} {comment}"))
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    //Comments after the last top-level key stay at the end of the file
+    lines.extend(pending.iter().map(|comment| comment.to_string()));
+    lines.join("\n")
+}
+//Only unindented keys made of letters, digits, _ and -, like every key of the config
+fn split_top_level_key(line: &str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    let (key, value) = (&line[..colon], &line[colon + 1..]);
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '-');
+    match is_key && (value.is_empty() || value.starts_with(' ')) {
+        true => Some((key, value)),
+        false => None,
+    }
+}
+//Quoted, flow, block and tagged values may contain a # that isn't a comment, so only plain values are looked at
+fn is_plain_value(value: &str) -> bool {
+    !value
+        .trim_start()
+        .starts_with(['"', '\'', '[', '{', '|', '>', '&', '*', '!'])
+}
+fn get_value_comment(value: &str) -> Option<&str> {
+    let trimmed = value.trim_start();
+    if trimmed.starts_with('#') {
+        return Some(trimmed);
+    }
+    if !is_plain_value(value) {
+        return None;
+    }
+    value.find(" #").map(|start| &value[start + 1..])
+}
@@ -0,0 +1,449 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::dashboard::is_same_secret;
+use crate::state::State;
+use crate::unending_process::{
+    create_parent_folder, format_err, get_config_folder_path, log_to_file_and_console,
+    read_incomplete_config_file, Config, DNSRecord, IncompleteConfig, LogConfig, LogType,
+};
+
+const CLIENT_TIMEOUT_SECONDS: u64 = 5;
+//Waiting while paused is woken up by the commands, this only bounds a single wait
+const PAUSED_WAIT_SECONDS: u64 = 3600;
+//Answered over tcp without a token, since they don't change anything
+const READ_ONLY_COMMANDS: &[&str] = &["status", "records"];
+
+static CONTROL: OnceLock<(Mutex<Control>, Condvar)> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct ControlConfig {
+    //The unix socket, control.sock next to the config file
+    #[serde(default = "default_socket")]
+    pub socket: bool,
+    //Like 127.0.0.1:9470. Only loopback addresses are allowed, since any user of the machine can connect.
+    pub listen: Option<String>,
+    //Needed by every request sent to listen. Without one, listen only answers the read only commands.
+    pub token: Option<String>,
+}
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            socket: default_socket(),
+            listen: None,
+            token: None,
+        }
+    }
+}
+fn default_socket() -> bool {
+    true
+}
+#[derive(Default)]
+struct Control {
+    paused: bool,
+    syncing: bool,
+    sync_requested: bool,
+    reload_requested: bool,
+    //Set through the api, kept until the daemon restarts
+    interval_override: Option<u32>,
+    interval_seconds: u32,
+    records: Vec<DNSRecord>,
+}
+pub enum Wake {
+    Sync,
+    Reload,
+}
+//The socket is only open to the user running the daemon, while anyone on the machine can connect over tcp
+#[derive(Clone)]
+enum Client {
+    Socket,
+    Tcp { token: Option<String> },
+}
+fn lock_control() -> (MutexGuard<'static, Control>, &'static Condvar) {
+    let (control, condvar) =
+        CONTROL.get_or_init(|| (Mutex::new(Control::default()), Condvar::new()));
+    let control = match control.lock() {
+        Ok(control) => control,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    (control, condvar)
+}
+//Applies the interval set through the api to a freshly loaded config, and remembers its records for the api
+pub fn apply(config: &mut Config) {
+    let (mut control, _) = lock_control();
+    if let Some(seconds) = control.interval_override {
+        config.seconds_to_wait_per_restart = seconds;
+    }
+    control.interval_seconds = config.seconds_to_wait_per_restart;
+    control.records = config.dns_config.clone();
+}
+//The interval of the config file counts again, like after a restart
+pub fn clear_interval_override() {
+    lock_control().0.interval_override = None;
+}
+//Waits until the interval since start passed while not paused, or until a command asks for a sync or a reload
+pub fn wait(start: Instant, seconds: u32) -> Wake {
+    let (mut control, condvar) = lock_control();
+    control.syncing = false;
+    loop {
+        if control.sync_requested {
+            control.sync_requested = false;
+            control.syncing = true;
+            return Wake::Sync;
+        }
+        if control.reload_requested {
+            control.reload_requested = false;
+            return Wake::Reload;
+        }
+        let interval = Duration::from_secs(control.interval_override.unwrap_or(seconds) as u64);
+        let elapsed = start.elapsed();
+        if !control.paused && elapsed >= interval {
+            control.syncing = true;
+            return Wake::Sync;
+        }
+        let timeout = match control.paused {
+            true => Duration::from_secs(PAUSED_WAIT_SECONDS),
+            false => interval - elapsed,
+        };
+        control = match condvar.wait_timeout(control, timeout) {
+            Ok((control, _)) => control,
+            Err(poisoned) => poisoned.into_inner().0,
+        };
+    }
+}
+//None when the address isn't a host and port
+pub fn get_loopback(listen: &str) -> Option<bool> {
+    match listen.to_socket_addrs() {
+        Ok(mut addresses) => Some(addresses.all(|address| address.ip().is_loopback())),
+        Err(_) => None,
+    }
+}
+fn get_socket_path() -> Result<PathBuf, ()> {
+    Ok(get_config_folder_path()?.join("control.sock"))
+}
+pub fn start(config: &Config) {
+    //The first sync starts right after startup, without waiting
+    lock_control().0.syncing = true;
+    #[cfg(unix)]
+    if config.control.socket {
+        start_socket(&config.log_config);
+    }
+    if let Some(listen) = &config.control.listen {
+        let token = config
+            .control
+            .token
+            .clone()
+            .filter(|token| !token.is_empty());
+        start_tcp(listen, token, &config.log_config);
+    }
+}
+#[cfg(unix)]
+fn start_socket(log_config: &LogConfig) {
+    let socket_path = match get_socket_path() {
+        Ok(socket_path) => socket_path,
+        Err(()) => {
+            log_to_file_and_console(
+                "Couldn't get the control socket path",
+                LogType::Warn,
+                log_config,
+            );
+            return;
+        }
+    };
+    if create_parent_folder(&socket_path).is_err() {
+        return;
+    }
+    let listener = match bind_private_socket(&socket_path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log_to_file_and_console(
+                &format!(
+                    "Couldn't create the control socket {}{}",
+                    socket_path.display(),
+                    format_err(err)
+                ),
+                LogType::Warn,
+                log_config,
+            );
+            return;
+        }
+    };
+    log_to_file_and_console(
+        &format!("Listening for commands on {}", socket_path.display()),
+        LogType::Debug,
+        log_config,
+    );
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            //Every client gets its own thread, so one that doesn't send anything can't hold up the others
+            std::thread::spawn(move || {
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT_SECONDS)));
+                if let Ok(writer) = stream.try_clone() {
+                    handle_connection(BufReader::new(stream), writer, &Client::Socket);
+                }
+            });
+        }
+    });
+}
+//Anyone who can connect can pause the daemon, so only the user running it may. The socket is
+//created in a folder only that user can enter and moved into place once its permissions are set.
+#[cfg(unix)]
+fn bind_private_socket(
+    socket_path: &std::path::Path,
+) -> std::io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+    let private_folder_path = socket_path.with_file_name(format!(".control.{}", process::id()));
+    let _ = std::fs::remove_dir_all(&private_folder_path);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_folder_path)?;
+    let private_path = private_folder_path.join("control.sock");
+    let result = UnixListener::bind(&private_path).and_then(|listener| {
+        std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(0o600))?;
+        //A socket left behind by a daemon that didn't stop cleanly is replaced
+        std::fs::rename(&private_path, socket_path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&private_folder_path);
+    result
+}
+fn start_tcp(listen: &str, token: Option<String>, log_config: &LogConfig) {
+    if get_loopback(listen) != Some(true) {
+        log_to_file_and_console(
+            &format!("The control api isn't served on {listen}, only loopback addresses like 127.0.0.1:9470 are allowed"),
+            LogType::Error,
+            log_config,
+        );
+        return;
+    }
+    let listener = match TcpListener::bind(listen) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log_to_file_and_console(
+                &format!(
+                    "Couldn't serve the control api on {listen}{}",
+                    format_err(err)
+                ),
+                LogType::Error,
+                log_config,
+            );
+            return;
+        }
+    };
+    if token.is_none() {
+        log_to_file_and_console(
+            &format!(
+                "Only status and records are answered on {listen}, since control.token isn't set"
+            ),
+            LogType::Warn,
+            log_config,
+        );
+    }
+    log_to_file_and_console(
+        &format!("Listening for commands on {listen}"),
+        LogType::Debug,
+        log_config,
+    );
+    let client = Client::Tcp { token };
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            //Every client gets its own thread, so one that doesn't send anything can't hold up the others
+            let client = client.clone();
+            std::thread::spawn(move || {
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT_SECONDS)));
+                if let Ok(writer) = stream.try_clone() {
+                    handle_connection(BufReader::new(stream), writer, &client);
+                }
+            });
+        }
+    });
+}
+//Every line is a json request, answered with one json line
+fn handle_connection(reader: impl BufRead, mut writer: impl Write, client: &Client) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => match is_allowed(&request, client) {
+                Ok(()) => handle_request(&request),
+                Err(error) => json!({ "ok": false, "error": error }),
+            },
+            Err(err) => json!({ "ok": false, "error": format!("the request isn't json ({err})") }),
+        };
+        if writeln!(writer, "{response}").is_err() {
+            return;
+        }
+    }
+}
+fn is_allowed(request: &Value, client: &Client) -> Result<(), &'static str> {
+    match client {
+        Client::Socket => Ok(()),
+        Client::Tcp { token: Some(token) } => match request["token"].as_str() {
+            Some(given) if is_same_secret(given.as_bytes(), token.as_bytes()) => Ok(()),
+            _ => Err("the request has no token or a wrong one"),
+        },
+        Client::Tcp { token: None } => {
+            match READ_ONLY_COMMANDS.contains(&request["command"].as_str().unwrap_or_default()) {
+                true => Ok(()),
+                false => Err("only status and records are answered over tcp without control.token"),
+            }
+        }
+    }
+}
+pub fn handle_request(request: &Value) -> Value {
+    let (mut control, condvar) = lock_control();
+    let response = match request["command"].as_str() {
+        Some("status") => json!({
+            "ok": true,
+            "paused": control.paused,
+            "syncing": control.syncing,
+            "interval_seconds": control.interval_seconds,
+            "interval_overridden": control.interval_override.is_some(),
+            "state": State::load(),
+        }),
+        Some("records") => {
+            let state = State::load();
+            let records: Vec<Value> = control
+                .records
+                .iter()
+                .map(|record| {
+                    let record_state = state.records.get(&record.id);
+                    json!({
+                        "name": record.name,
+                        "id": record.id,
+                        "type": record.record_type,
+                        "content": record.content,
+                        "proxied": record.proxy_status,
+                        "ttl": record.ttl,
+                        "sync": record.sync == Some(true),
+                        "last_success": record_state.and_then(|record_state| record_state.last_success),
+                        "last_failure": record_state.and_then(|record_state| record_state.last_failure),
+                        "last_error": record_state.and_then(|record_state| record_state.last_error.clone()),
+                    })
+                })
+                .collect();
+            json!({ "ok": true, "records": records })
+        }
+        Some("sync_now") => {
+            control.sync_requested = true;
+            json!({ "ok": true, "message": "A sync was started" })
+        }
+        Some("pause") => {
+            control.paused = true;
+            json!({ "ok": true, "message": "Syncing is paused" })
+        }
+        Some("resume") => {
+            control.paused = false;
+            json!({ "ok": true, "message": "Syncing was resumed" })
+        }
+        Some("set_interval") => match request["seconds"].as_u64() {
+            Some(seconds) if seconds > 0 && seconds <= u32::MAX as u64 => {
+                control.interval_override = Some(seconds as u32);
+                control.interval_seconds = seconds as u32;
+                json!({ "ok": true, "message": format!("The interval is {seconds} seconds until the daemon restarts") })
+            }
+            _ => json!({ "ok": false, "error": "set_interval needs seconds, a number above 0" }),
+        },
+        Some("reload") => {
+            control.reload_requested = true;
+            json!({ "ok": true, "message": "The config file is being reloaded" })
+        }
+        Some(command) => {
+            json!({ "ok": false, "error": format!("there is no command called {command}") })
+        }
+        None => json!({ "ok": false, "error": "the request has no command" }),
+    };
+    condvar.notify_all();
+    response
+}
+//Sends a request to the running daemon, through the socket or else the tcp address of the config
+pub fn send_request(request: &Value) -> Result<Value, String> {
+    let mut errors = vec![];
+    #[cfg(unix)]
+    match get_socket_path() {
+        Ok(socket_path) => match std::os::unix::net::UnixStream::connect(&socket_path) {
+            Ok(stream) => return exchange(stream.try_clone(), stream, request),
+            Err(err) => errors.push(format!("{} ({err})", socket_path.display())),
+        },
+        Err(()) => errors.push("the control socket path couldn't be found".to_string()),
+    }
+    let control = get_client_config().control;
+    if let Some(listen) = control.listen {
+        let mut request = request.clone();
+        if let Some(token) = control.token.filter(|token| !token.is_empty()) {
+            request["token"] = Value::String(token);
+        }
+        let address = match listen
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+        {
+            Some(address) => address,
+            None => return Err(format!("{listen} isn't a valid address")),
+        };
+        match TcpStream::connect_timeout(&address, Duration::from_secs(CLIENT_TIMEOUT_SECONDS)) {
+            Ok(stream) => return exchange(stream.try_clone(), stream, &request),
+            Err(err) => errors.push(format!("{listen} ({err})")),
+        }
+    }
+    if errors.is_empty() {
+        return Err("there is no control socket or address to connect to".to_string());
+    }
+    Err(format!("couldn't connect to {}", errors.join(" or ")))
+}
+//Takes the stream twice, once to write and once to read
+fn exchange<S: std::io::Read + Write>(
+    writer: std::io::Result<S>,
+    reader: S,
+    request: &Value,
+) -> Result<Value, String> {
+    let mut writer = writer.map_err(|err| format!("couldn't use the connection ({err})"))?;
+    if let Err(err) = writeln!(writer, "{request}") {
+        return Err(format!("couldn't send the request ({err})"));
+    }
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(reader).read_line(&mut line) {
+        return Err(format!("couldn't read the answer ({err})"));
+    }
+    serde_json::from_str(&line).map_err(|err| format!("the answer isn't json ({err})"))
+}
+//Never exits, since the tui sends requests while it owns the terminal
+fn get_client_config() -> IncompleteConfig {
+    match read_incomplete_config_file() {
+        Ok((config, _)) => config,
+        Err(_) => IncompleteConfig::from_defaults(),
+    }
+}
+//The sync-now, pause and resume commands
+pub fn run_command(command: &str) -> ! {
+    match send_request(&json!({ "command": command })) {
+        Ok(response) if response["ok"] == true => {
+            println!("{}", response["message"].as_str().unwrap_or("Done"));
+            process::exit(0);
+        }
+        Ok(response) => {
+            println!(
+                "The daemon refused: {}",
+                response["error"].as_str().unwrap_or("no reason given")
+            );
+            process::exit(1);
+        }
+        Err(err) => {
+            println!("Couldn't reach the daemon, is it running? {err}");
+            process::exit(1);
+        }
+    }
+}
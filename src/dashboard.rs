@@ -0,0 +1,227 @@
+use std::io::{Cursor, Read};
+
+use base64::Engine;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::control;
+use crate::http_server::{json_response, text_response};
+use crate::logs;
+use crate::state;
+use crate::unending_process::{
+    log_to_file_and_console, read_config_file, try_lock_config_file, Config, LogConfig, LogType,
+};
+
+const PAGE: &str = include_str!("dashboard.html");
+const HISTORY_ENTRIES: usize = 20;
+const LOG_LINES: usize = 100;
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+//How long an edit waits for someone else to finish writing the config file
+const CONFIG_LOCK_TIMEOUT_SECONDS: u64 = 5;
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct DashboardConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    //A port of its own by default, since the metrics and health checks don't ask for the password
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    //Asked for by the browser, with any user name. The dashboard isn't served without one.
+    pub password: Option<String>,
+}
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        DashboardConfig {
+            enabled: false,
+            listen: default_listen(),
+            password: None,
+        }
+    }
+}
+fn default_listen() -> String {
+    "127.0.0.1:9471".to_string()
+}
+pub fn is_dashboard_path(path: &str) -> bool {
+    path == "/" || path.starts_with("/api/")
+}
+pub fn handle(
+    request: &mut Request,
+    path: &str,
+    password: &str,
+    environment_overrides: bool,
+    log_config: &LogConfig,
+) -> Response<Cursor<Vec<u8>>> {
+    if !is_authorized(request, password) {
+        let authenticate = Header::from_bytes("WWW-Authenticate", "Basic realm=\"cf_dns_sync\"")
+            .expect("the authenticate header is valid");
+        return text_response(401, "The password is wrong".to_string(), "text/plain")
+            .with_header(authenticate);
+    }
+    if *request.method() == Method::Post && !is_json(request) {
+        //Forms of other sites can't send json, so they can't make the browser change anything
+        return error_response(415, "Changes must be sent as application/json");
+    }
+    match (request.method(), path) {
+        (Method::Get, "/") => text_response(200, PAGE.to_string(), "text/html; charset=utf-8"),
+        (Method::Get, "/api/dashboard") => {
+            json_response(200, get_dashboard(environment_overrides, log_config))
+        }
+        (Method::Post, "/api/sync-now") => command_response("sync_now"),
+        (Method::Post, "/api/pause") => command_response("pause"),
+        (Method::Post, "/api/resume") => command_response("resume"),
+        (Method::Post, "/api/records") => {
+            let body = read_body(request);
+            let (id, sync) = match (body["id"].as_str(), body["sync"].as_bool()) {
+                (Some(id), Some(sync)) => (id.to_string(), sync),
+                _ => {
+                    return error_response(400, "Send the id of the record and sync, true or false")
+                }
+            };
+            edit_config(
+                environment_overrides,
+                "the DNS records list",
+                |config| match config.dns_config.iter_mut().find(|record| record.id == id) {
+                    Some(record) => {
+                        record.sync = Some(sync);
+                        Ok(())
+                    }
+                    None => Err(format!("There is no record with the id {id}")),
+                },
+            )
+        }
+        (Method::Post, "/api/interval") => {
+            let seconds = match read_body(request)["seconds"].as_u64() {
+                Some(seconds) if seconds > 0 && seconds <= u32::MAX as u64 => seconds as u32,
+                _ => return error_response(400, "Send seconds, a number above 0"),
+            };
+            edit_config(
+                environment_overrides,
+                "seconds to wait per restart",
+                |config| {
+                    //The interval saved in the config file replaces one set through the control api
+                    control::clear_interval_override();
+                    config.seconds_to_wait_per_restart = seconds;
+                    Ok(())
+                },
+            )
+        }
+        _ => text_response(404, "Not found".to_string(), "text/plain"),
+    }
+}
+fn is_authorized(request: &Request, password: &str) -> bool {
+    let header = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"));
+    let encoded = match header.and_then(|header| header.value.as_str().strip_prefix("Basic ")) {
+        Some(encoded) => encoded.trim(),
+        None => return false,
+    };
+    let decoded = match base64::engine::general_purpose::STANDARD.decode(encoded) {
+        Ok(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+        Err(_) => return false,
+    };
+    let given = decoded
+        .split_once(':')
+        .map(|(_, given)| given)
+        .unwrap_or_default();
+    is_same_secret(given.as_bytes(), password.as_bytes())
+}
+//Compares every byte, so the time taken doesn't tell how much of the password was right
+pub(crate) fn is_same_secret(given: &[u8], expected: &[u8]) -> bool {
+    if given.len() != expected.len() {
+        return false;
+    }
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+fn is_json(request: &Request) -> bool {
+    request.headers().iter().any(|header| {
+        header.field.equiv("Content-Type") && header.value.as_str().starts_with("application/json")
+    })
+}
+fn read_body(request: &mut Request) -> Value {
+    let mut body = String::new();
+    if request
+        .as_reader()
+        .take(MAX_BODY_BYTES)
+        .read_to_string(&mut body)
+        .is_err()
+    {
+        return Value::Null;
+    }
+    serde_json::from_str(&body).unwrap_or(Value::Null)
+}
+fn error_response(status: u16, error: &str) -> Response<Cursor<Vec<u8>>> {
+    json_response(status, json!({ "ok": false, "error": error }))
+}
+fn command_response(command: &str) -> Response<Cursor<Vec<u8>>> {
+    let response = control::handle_request(&json!({ "command": command }));
+    json_response(200, response)
+}
+fn get_dashboard(environment_overrides: bool, log_config: &LogConfig) -> Value {
+    let mut status = control::handle_request(&json!({ "command": "status" }));
+    let records = control::handle_request(&json!({ "command": "records" }));
+    let history: Vec<state::HistoryEntry> = state::read_recent_history(HISTORY_ENTRIES)
+        .into_iter()
+        .rev()
+        .collect();
+    json!({
+        "state": status["state"].take(),
+        "paused": status["paused"],
+        "syncing": status["syncing"],
+        "interval_seconds": status["interval_seconds"],
+        "interval_overridden": status["interval_overridden"],
+        "records": records["records"],
+        "history": history,
+        "logs": logs::tail_session(log_config, LOG_LINES),
+        "editable": !environment_overrides,
+    })
+}
+//Changes the config file like configure does, then has the daemon reload it
+fn edit_config(
+    environment_overrides: bool,
+    name: &str,
+    change: impl FnOnce(&mut Config) -> Result<(), String>,
+) -> Response<Cursor<Vec<u8>>> {
+    if environment_overrides {
+        return error_response(
+            409,
+            "CF_DNS_SYNC_* environment variables are overriding the config file, so it can't be edited",
+        );
+    }
+    let _config_file_lock =
+        match try_lock_config_file(std::time::Duration::from_secs(CONFIG_LOCK_TIMEOUT_SECONDS)) {
+            Ok(config_file_lock) => config_file_lock,
+            Err(()) => {
+                return error_response(
+                    503,
+                    "The config file is being written by another process, try again in a moment",
+                )
+            }
+        };
+    let (mut config, config_path) = match read_config_file() {
+        Ok(config) => config,
+        Err(err) => return error_response(500, &err),
+    };
+    if let Err(err) = change(&mut config) {
+        return error_response(400, &err);
+    }
+    if config.save_to_file(&config_path).is_err() {
+        log_to_file_and_console("Failed to save config", LogType::Error, &config.log_config);
+        return error_response(500, "The config file couldn't be saved");
+    }
+    log_to_file_and_console(
+        &format!("Saved {name} to the config file from the dashboard"),
+        LogType::Log,
+        &config.log_config,
+    );
+    //Shown right away, even when the daemon only reloads after the sync it is busy with
+    control::apply(&mut config);
+    control::handle_request(&json!({ "command": "reload" }));
+    json_response(200, json!({ "ok": true }))
+}
@@ -0,0 +1,173 @@
+use std::str::FromStr;
+
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::log_sinks::get_host_name;
+use crate::notifications::{default_events, Notification, NotificationEvent};
+use crate::unending_process::{log_event, LogConfig, LogDetails, LogType};
+
+const SMTP_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct EmailConfig {
+    pub host: String,
+    //The usual port of the security is used when there is none
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_events")]
+    pub events: Vec<NotificationEvent>,
+    //Short outages, like a router restarting, are only mailed about once they last this long
+    #[serde(default = "default_failure_minutes")]
+    pub failure_minutes: u32,
+    #[serde(default = "default_max_per_hour")]
+    pub max_per_hour: u32,
+}
+impl EmailConfig {
+    pub fn new(host: String, from: String, to: Vec<String>) -> Self {
+        EmailConfig {
+            host,
+            port: None,
+            security: SmtpSecurity::default(),
+            username: None,
+            password: None,
+            from,
+            to,
+            events: default_events(),
+            failure_minutes: default_failure_minutes(),
+            max_per_hour: default_max_per_hour(),
+        }
+    }
+    pub fn get_port(&self) -> u16 {
+        match self.port {
+            Some(port) => port,
+            None => self.security.default_port(),
+        }
+    }
+}
+fn default_failure_minutes() -> u32 {
+    30
+}
+fn default_max_per_hour() -> u32 {
+    4
+}
+#[derive(Deserialize, Debug, Clone, Copy, Serialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    //Plain connection upgraded with the STARTTLS command, usually on port 587
+    #[default]
+    StartTls,
+    //TLS from the start, usually on port 465
+    Tls,
+    //Only meant for relays on the local network or the same machine
+    None,
+}
+impl SmtpSecurity {
+    pub fn default_port(self) -> u16 {
+        match self {
+            SmtpSecurity::StartTls => 587,
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+impl FromStr for SmtpSecurity {
+    type Err = ();
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.to_lowercase().as_str() {
+            "starttls" => Ok(SmtpSecurity::StartTls),
+            "tls" => Ok(SmtpSecurity::Tls),
+            "none" => Ok(SmtpSecurity::None),
+            _ => Err(()),
+        }
+    }
+}
+pub fn send_email(
+    email: &EmailConfig,
+    notification: &Notification,
+    log_config: &LogConfig,
+) -> Result<(), String> {
+    let start = std::time::Instant::now();
+    let result = match build_message(email, notification) {
+        Ok(message) => match build_transport(email) {
+            Ok(transport) => transport.send(&message).map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(_) => {
+            log_event(
+                &format!(
+                    "Sent the {} email to {}",
+                    notification.event.name(),
+                    email.to.join(", ")
+                ),
+                LogType::Debug,
+                &LogDetails::event("notification_sent").duration(start.elapsed()),
+                log_config,
+            );
+            Ok(())
+        }
+        Err(err) => {
+            log_event(
+                &format!(
+                    "Couldn't send the {} email through {}, {err}",
+                    notification.event.name(),
+                    email.host
+                ),
+                LogType::Warn,
+                &LogDetails::event("notification_failed").duration(start.elapsed()),
+                log_config,
+            );
+            Err(err)
+        }
+    }
+}
+pub fn build_message(email: &EmailConfig, notification: &Notification) -> Result<Message, String> {
+    let from: Mailbox = match email.from.parse() {
+        Ok(from) => from,
+        Err(err) => return Err(format!("the sender {} is invalid ({err})", email.from)),
+    };
+    let mut builder = Message::builder()
+        .from(from)
+        .subject(format!(
+            "[cf_dns_sync on {}] {}",
+            get_host_name(),
+            notification.title()
+        ))
+        .header(ContentType::TEXT_PLAIN);
+    for to in email.to.iter() {
+        match to.parse::<Mailbox>() {
+            Ok(to) => builder = builder.to(to),
+            Err(err) => return Err(format!("the recipient {to} is invalid ({err})")),
+        }
+    }
+    builder
+        .body(notification.text())
+        .map_err(|err| err.to_string())
+}
+fn build_transport(email: &EmailConfig) -> Result<SmtpTransport, String> {
+    let mut builder = match email.security {
+        SmtpSecurity::StartTls => SmtpTransport::starttls_relay(&email.host),
+        SmtpSecurity::Tls => SmtpTransport::relay(&email.host),
+        SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(&email.host)),
+    }
+    .map_err(|err| err.to_string())?
+    .port(email.get_port())
+    .timeout(Some(std::time::Duration::from_secs(SMTP_TIMEOUT_SECONDS)));
+    if let Some(username) = &email.username {
+        builder = builder.credentials(Credentials::new(
+            username.clone(),
+            email.password.clone().unwrap_or_default(),
+        ));
+    }
+    Ok(builder.build())
+}
@@ -0,0 +1,432 @@
+use std::{path::Path, process, str::FromStr};
+
+use crate::chat::{ChatConfig, ChatService};
+use crate::email::EmailConfig;
+use crate::hooks::HookConfig;
+use crate::notifications::{QuietHoursConfig, WebhookConfig};
+use crate::unending_process::{
+    format_err, is_valid_timestamp_format, AuthenticationConfig, IncompleteConfig, LogTimezone,
+};
+
+const PREFIX: &str = "CF_DNS_SYNC_";
+
+//Every CF_DNS_SYNC_* variable that overrides a config value. CF_DNS_SYNC_CONFIG only selects the file, so it isn't listed here.
+pub const CONFIG_VARIABLES: &[&str] = &[
+    "INTERVAL",
+    "EMAIL",
+    "API_KEY",
+    "ZONE_ID",
+    "RECORDS",
+    "LOG_FOLDER",
+    "SEPARATE_LOGS_BY_SESSION",
+    "DISPLAY_DATE",
+    "DISPLAY_TIME",
+    "DISPLAY_LOG_TYPE",
+    "DISPLAY_MILLISECONDS",
+    "TIMESTAMP_FORMAT",
+    "TIMEZONE",
+    "LOG_CONSOLE_LEVEL",
+    "LOG_FILE_LEVEL",
+    "LOG_MAX_FILE_SIZE_KB",
+    "LOG_MAX_FILE_AGE_HOURS",
+    "LOG_COMPRESS",
+    "LOG_KEEP_SESSIONS",
+    "LOG_KEEP_DAYS",
+    "LOG_MAX_HISTORY_SIZE_KB",
+    "LOG_CONSOLE_FORMAT",
+    "LOG_FILE_FORMAT",
+    "LOG_TO_FILE",
+    "LOG_TO_SYSLOG",
+    "LOG_TO_JOURNALD",
+    "WEBHOOK_URL",
+    "SMTP_HOST",
+    "SMTP_PORT",
+    "SMTP_SECURITY",
+    "SMTP_USERNAME",
+    "SMTP_PASSWORD",
+    "SMTP_FROM",
+    "SMTP_TO",
+    "SLACK_URL",
+    "DISCORD_URL",
+    "NTFY_URL",
+    "QUIET_HOURS",
+    "PRE_UPDATE_HOOK",
+    "POST_UPDATE_HOOK",
+    "METRICS_ENABLED",
+    "METRICS_LISTEN",
+    "HEALTH_ENABLED",
+    "HEALTH_LISTEN",
+    "CONTROL_SOCKET",
+    "CONTROL_LISTEN",
+    "CONTROL_TOKEN",
+    "DASHBOARD_ENABLED",
+    "DASHBOARD_LISTEN",
+    "DASHBOARD_PASSWORD",
+    "MQTT_ENABLED",
+    "MQTT_HOST",
+    "MQTT_PORT",
+    "MQTT_USERNAME",
+    "MQTT_PASSWORD",
+    "MQTT_TOPIC_PREFIX",
+    "MQTT_DISCOVERY",
+];
+
+pub fn has_overrides() -> bool {
+    CONFIG_VARIABLES.iter().any(|name| is_set(name))
+}
+pub fn has_authentication() -> bool {
+    is_set("EMAIL") && is_set("API_KEY") && is_set("ZONE_ID")
+}
+//Layers the CF_DNS_SYNC_* variables over the values read from the config file (or the defaults if there is no file)
+pub fn apply_overrides(config: &mut IncompleteConfig) {
+    if let Err(errors) = try_apply_overrides(config) {
+        for error in errors {
+            println!("{error}");
+        }
+        process::exit(1);
+    }
+}
+//Like apply_overrides, but returns every invalid variable instead of exiting, for the dashboard, the tui and validate
+pub fn try_apply_overrides(config: &mut IncompleteConfig) -> Result<(), Vec<String>> {
+    if !has_overrides() {
+        return Ok(());
+    }
+    config.environment_overrides = true;
+    let mut errors = vec![];
+    apply_variables(config, &mut errors);
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+fn apply_variables(config: &mut IncompleteConfig, errors: &mut Vec<String>) {
+    if let Some(seconds) = get_parsed_var::<u32>("INTERVAL", errors) {
+        config.seconds_to_wait_per_restart = seconds;
+    }
+    let email = get_var("EMAIL", errors);
+    let api_key = get_var("API_KEY", errors);
+    let zone_id = get_var("ZONE_ID", errors);
+    match config.authentication.as_mut() {
+        Some(authentication) => {
+            if let Some(email) = email {
+                authentication.email = email;
+            }
+            if let Some(api_key) = api_key {
+                authentication.api_key = api_key;
+            }
+            if let Some(zone_id) = zone_id {
+                authentication.zone_id = zone_id;
+            }
+        }
+        None => match (email, api_key, zone_id) {
+            (Some(email), Some(api_key), Some(zone_id)) => {
+                config.authentication = Some(AuthenticationConfig {
+                    email,
+                    api_key,
+                    zone_id,
+                })
+            }
+            (None, None, None) => {}
+            _ => errors.push(format!("{PREFIX}EMAIL, {PREFIX}API_KEY and {PREFIX}ZONE_ID must all be set when the config file has no authentication.")),
+        },
+    }
+    if let Some(records) = get_var("RECORDS", errors) {
+        config.sync_record_names = Some(
+            records
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        );
+    }
+    let log_config = &mut config.log_config;
+    if let Some(log_folder_path) = get_var("LOG_FOLDER", errors) {
+        log_config.log_folder_path = log_folder_path;
+    }
+    if let Some(value) = get_parsed_var("SEPARATE_LOGS_BY_SESSION", errors) {
+        log_config.separate_logs_by_session = value;
+    }
+    //The display variables apply to the console and the log file alike, so they are read once for both
+    let date = get_parsed_var("DISPLAY_DATE", errors);
+    let time = get_parsed_var("DISPLAY_TIME", errors);
+    let log_type = get_parsed_var("DISPLAY_LOG_TYPE", errors);
+    let milliseconds = get_parsed_var("DISPLAY_MILLISECONDS", errors);
+    let timestamp_format = get_var("TIMESTAMP_FORMAT", errors).filter(|format| {
+        match is_valid_timestamp_format(format) {
+            true => true,
+            false => {
+                errors.push(format!(
+                    "{PREFIX}TIMESTAMP_FORMAT isn't a valid strftime format: {format}"
+                ));
+                false
+            }
+        }
+    });
+    let timezone =
+        get_var("TIMEZONE", errors).filter(|timezone| match LogTimezone::parse(timezone) {
+            Some(_) => true,
+            None => {
+                errors.push(format!(
+                    "{PREFIX}TIMEZONE isn't a known timezone: {timezone}"
+                ));
+                false
+            }
+        });
+    for sink in [&mut log_config.console, &mut log_config.file] {
+        let display = &mut sink.display;
+        if let Some(value) = date {
+            display.date = value;
+        }
+        if let Some(value) = time {
+            display.time = value;
+        }
+        if let Some(value) = log_type {
+            display.log_type = value;
+        }
+        if let Some(value) = milliseconds {
+            display.milliseconds = value;
+        }
+        if let Some(format) = &timestamp_format {
+            display.timestamp_format = Some(format.clone());
+        }
+        if let Some(timezone) = &timezone {
+            display.timezone = timezone.clone();
+        }
+    }
+    if let Some(value) = get_parsed_var("LOG_CONSOLE_LEVEL", errors) {
+        log_config.console.level = value;
+    }
+    if let Some(value) = get_parsed_var("LOG_FILE_LEVEL", errors) {
+        log_config.file.level = value;
+    }
+    //0 disables the limit, like in the configure menu
+    if let Some(value) = get_parsed_var::<u64>("LOG_MAX_FILE_SIZE_KB", errors) {
+        log_config.rotation.max_file_size_kb = Some(value).filter(|value| *value > 0);
+    }
+    if let Some(value) = get_parsed_var::<u64>("LOG_MAX_FILE_AGE_HOURS", errors) {
+        log_config.rotation.max_file_age_hours = Some(value).filter(|value| *value > 0);
+    }
+    if let Some(value) = get_parsed_var("LOG_COMPRESS", errors) {
+        log_config.rotation.compress = value;
+    }
+    if let Some(value) = get_parsed_var::<u32>("LOG_KEEP_SESSIONS", errors) {
+        log_config.rotation.keep_sessions = Some(value).filter(|value| *value > 0);
+    }
+    if let Some(value) = get_parsed_var::<u32>("LOG_KEEP_DAYS", errors) {
+        log_config.rotation.keep_days = Some(value).filter(|value| *value > 0);
+    }
+    if let Some(value) = get_parsed_var::<u64>("LOG_MAX_HISTORY_SIZE_KB", errors) {
+        log_config.rotation.max_history_size_kb = Some(value).filter(|value| *value > 0);
+    }
+    if let Some(value) = get_parsed_var("LOG_CONSOLE_FORMAT", errors) {
+        log_config.console.format = value;
+    }
+    if let Some(value) = get_parsed_var("LOG_FILE_FORMAT", errors) {
+        log_config.file.format = value;
+    }
+    if let Some(value) = get_parsed_var("LOG_TO_FILE", errors) {
+        log_config.outputs.file = value;
+    }
+    if let Some(value) = get_parsed_var("LOG_TO_SYSLOG", errors) {
+        log_config.outputs.syslog = value;
+    }
+    if let Some(value) = get_parsed_var("LOG_TO_JOURNALD", errors) {
+        log_config.outputs.journald = value;
+    }
+    //Adds a webhook with the default method, body, events and retries next to the ones of the config file
+    if let Some(url) = get_var("WEBHOOK_URL", errors) {
+        config.notifications.webhooks.push(WebhookConfig::new(url));
+    }
+    apply_email_overrides(config, errors);
+    //Matrix needs a room and a token as well, so it can only be set up in the config file
+    for (name, service) in [
+        ("SLACK_URL", ChatService::Slack),
+        ("DISCORD_URL", ChatService::Discord),
+        ("NTFY_URL", ChatService::Ntfy),
+    ] {
+        if let Some(url) = get_var(name, errors) {
+            config
+                .notifications
+                .chat
+                .push(ChatConfig::new(service, url));
+        }
+    }
+    if let Some(value) = get_parsed_var("METRICS_ENABLED", errors) {
+        config.metrics.enabled = value;
+    }
+    if let Some(listen) = get_var("METRICS_LISTEN", errors) {
+        config.metrics.listen = listen;
+    }
+    if let Some(value) = get_parsed_var("HEALTH_ENABLED", errors) {
+        config.health.enabled = value;
+    }
+    if let Some(listen) = get_var("HEALTH_LISTEN", errors) {
+        config.health.listen = listen;
+    }
+    if let Some(value) = get_parsed_var("CONTROL_SOCKET", errors) {
+        config.control.socket = value;
+    }
+    if let Some(listen) = get_var("CONTROL_LISTEN", errors) {
+        config.control.listen = Some(listen);
+    }
+    if let Some(token) = get_var("CONTROL_TOKEN", errors) {
+        config.control.token = Some(token);
+    }
+    if let Some(value) = get_parsed_var("DASHBOARD_ENABLED", errors) {
+        config.dashboard.enabled = value;
+    }
+    if let Some(listen) = get_var("DASHBOARD_LISTEN", errors) {
+        config.dashboard.listen = listen;
+    }
+    if let Some(password) = get_var("DASHBOARD_PASSWORD", errors) {
+        config.dashboard.password = Some(password);
+    }
+    if let Some(value) = get_parsed_var("MQTT_ENABLED", errors) {
+        config.mqtt.enabled = value;
+    }
+    if let Some(host) = get_var("MQTT_HOST", errors) {
+        config.mqtt.host = host;
+    }
+    if let Some(port) = get_parsed_var("MQTT_PORT", errors) {
+        config.mqtt.port = port;
+    }
+    if let Some(username) = get_var("MQTT_USERNAME", errors) {
+        config.mqtt.username = Some(username);
+    }
+    if let Some(password) = get_var("MQTT_PASSWORD", errors) {
+        config.mqtt.password = Some(password);
+    }
+    if let Some(topic_prefix) = get_var("MQTT_TOPIC_PREFIX", errors) {
+        config.mqtt.topic_prefix = topic_prefix;
+    }
+    if let Some(value) = get_parsed_var("MQTT_DISCOVERY", errors) {
+        config.mqtt.discovery = value;
+    }
+    //Adds a hook with the default timeout next to the ones of the config file
+    if let Some(command) = get_var("PRE_UPDATE_HOOK", errors) {
+        config.hooks.pre_update.push(HookConfig::new(command));
+    }
+    if let Some(command) = get_var("POST_UPDATE_HOOK", errors) {
+        config.hooks.post_update.push(HookConfig::new(command));
+    }
+    //Like 22:00-07:00, in the local time of the machine
+    if let Some(quiet_hours) = get_var("QUIET_HOURS", errors) {
+        let times = quiet_hours
+            .split_once('-')
+            .map(|(start, end)| (start.trim(), end.trim()));
+        match times {
+            Some((start, end))
+                if QuietHoursConfig::parse_time(start).is_some()
+                    && QuietHoursConfig::parse_time(end).is_some() =>
+            {
+                config.notifications.quiet_hours = Some(QuietHoursConfig {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                    timezone: "local".to_string(),
+                })
+            }
+            _ => errors.push(format!("{PREFIX}QUIET_HOURS has an invalid value: {quiet_hours}, it should look like 22:00-07:00")),
+        }
+    }
+}
+fn apply_email_overrides(config: &mut IncompleteConfig, errors: &mut Vec<String>) {
+    let host = get_var("SMTP_HOST", errors);
+    let from = get_var("SMTP_FROM", errors);
+    let to = get_var("SMTP_TO", errors).map(|to| {
+        to.split(',')
+            .map(|address| address.trim().to_string())
+            .filter(|address| !address.is_empty())
+            .collect::<Vec<String>>()
+    });
+    let email = match config.notifications.email.as_mut() {
+        Some(email) => {
+            if let Some(host) = host {
+                email.host = host;
+            }
+            if let Some(from) = from {
+                email.from = from;
+            }
+            if let Some(to) = to {
+                email.to = to;
+            }
+            email
+        }
+        None => match (host, from, to) {
+            (Some(host), Some(from), Some(to)) => config
+                .notifications
+                .email
+                .insert(EmailConfig::new(host, from, to)),
+            (None, None, None) => return,
+            _ => {
+                errors.push(format!("{PREFIX}SMTP_HOST, {PREFIX}SMTP_FROM and {PREFIX}SMTP_TO must all be set when the config file has no email settings."));
+                return;
+            }
+        },
+    };
+    if let Some(port) = get_parsed_var("SMTP_PORT", errors) {
+        email.port = Some(port);
+    }
+    if let Some(security) = get_parsed_var("SMTP_SECURITY", errors) {
+        email.security = security;
+    }
+    if let Some(username) = get_var("SMTP_USERNAME", errors) {
+        email.username = Some(username);
+    }
+    if let Some(password) = get_var("SMTP_PASSWORD", errors) {
+        email.password = Some(password);
+    }
+}
+//Whether CF_DNS_SYNC_{name} or CF_DNS_SYNC_{name}_FILE is set, without reading the file
+fn is_set(name: &str) -> bool {
+    let key = format!("{PREFIX}{name}");
+    [key.clone(), format!("{key}_FILE")]
+        .iter()
+        .any(|key| std::env::var(key).is_ok_and(|value| !value.is_empty()))
+}
+//Reads CF_DNS_SYNC_{name}, or the contents of the file in CF_DNS_SYNC_{name}_FILE (useful with docker secrets)
+fn get_var(name: &str, errors: &mut Vec<String>) -> Option<String> {
+    let key = format!("{PREFIX}{name}");
+    if let Ok(value) = std::env::var(&key) {
+        if !value.is_empty() {
+            return Some(value);
+        }
+    }
+    let file_key = format!("{key}_FILE");
+    let file_path = match std::env::var(&file_key) {
+        Ok(file_path) if !file_path.is_empty() => file_path,
+        _ => return None,
+    };
+    match std::fs::read_to_string(Path::new(&file_path)) {
+        Ok(value) => Some(value.trim().to_string()),
+        Err(err) => {
+            errors.push(format!(
+                "Couldn't read {file_key} ({file_path}){}",
+                format_err(err)
+            ));
+            None
+        }
+    }
+}
+fn get_parsed_var<T: FromStr>(name: &str, errors: &mut Vec<String>) -> Option<T> {
+    let value = get_var(name, errors)?;
+    match parse_value(&value) {
+        Some(parsed) => Some(parsed),
+        None => {
+            errors.push(format!("{PREFIX}{name} has an invalid value: {value}"));
+            None
+        }
+    }
+}
+fn parse_value<T: FromStr>(value: &str) -> Option<T> {
+    let value = value.trim();
+    //Accept the usual spellings of booleans found in container environments
+    let normalized = match value.to_lowercase().as_str() {
+        "1" | "yes" | "on" => "true".to_string(),
+        "0" | "no" | "off" => "false".to_string(),
+        other => other.to_string(),
+    };
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => normalized.parse().ok(),
+    }
+}
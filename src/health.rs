@@ -0,0 +1,169 @@
+use std::process;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::environment;
+use crate::unending_process::{format_err, get_incomplete_config, IncompleteConfig};
+
+//Cloudflare's codes for a wrong api key, email or zone id
+const CREDENTIAL_ERROR_CODES: &[&str] = &["6003", "6103", "7003", "9103", "9106", "9109", "10000"];
+//Added to the allowed age of the last sync, for the time the syncs themselves take
+const SLACK_SECONDS: i64 = 60;
+const HEALTHCHECK_TIMEOUT_SECONDS: u64 = 5;
+
+static HEALTH: OnceLock<Mutex<Health>> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct HealthConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    //The same address as the metrics by default, so both are served by one server
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    //readyz fails once the last successful sync is older than this many intervals
+    #[serde(default = "default_stale_after_intervals")]
+    pub stale_after_intervals: u32,
+}
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            enabled: false,
+            listen: default_listen(),
+            stale_after_intervals: default_stale_after_intervals(),
+        }
+    }
+}
+fn default_listen() -> String {
+    "127.0.0.1:9469".to_string()
+}
+fn default_stale_after_intervals() -> u32 {
+    3
+}
+#[derive(Default, Clone)]
+pub(crate) struct Health {
+    pub(crate) started_at: Option<DateTime<Utc>>,
+    pub(crate) last_success: Option<DateTime<Utc>>,
+    pub(crate) interval_seconds: u32,
+    //None until cloudflare answered once
+    pub(crate) credentials_valid: Option<bool>,
+}
+fn with_health<T>(change: impl FnOnce(&mut Health) -> T) -> T {
+    let health = HEALTH.get_or_init(|| {
+        Mutex::new(Health {
+            started_at: Some(Utc::now()),
+            ..Default::default()
+        })
+    });
+    let mut health = match health.lock() {
+        Ok(health) => health,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    change(&mut health)
+}
+//Called with every loaded config, since the interval can change while running
+pub fn set_interval(seconds: u32) {
+    with_health(|health| health.interval_seconds = seconds);
+}
+pub fn sync_succeeded() {
+    with_health(|health| health.last_success = Some(Utc::now()));
+}
+pub fn set_credentials_valid(valid: bool) {
+    with_health(|health| health.credentials_valid = Some(valid));
+}
+//Only errors about the credentials change anything, cloudflare being down doesn't make them invalid
+pub fn check_api_errors(status: Option<u16>, codes: &[String]) {
+    if is_credential_error(status, codes) {
+        set_credentials_valid(false);
+    }
+}
+pub(crate) fn is_credential_error(status: Option<u16>, codes: &[String]) -> bool {
+    matches!(status, Some(401) | Some(403))
+        || codes
+            .iter()
+            .any(|code| CREDENTIAL_ERROR_CODES.contains(&code.as_str()))
+}
+pub fn get_liveness() -> Value {
+    let started_at = with_health(|health| health.started_at);
+    json!({
+        "status": "alive",
+        "uptime_seconds": started_at.map(|started_at| (Utc::now() - started_at).num_seconds()),
+    })
+}
+//Returns if the daemon is ready, with the details for the response body
+pub fn get_readiness(health_config: &HealthConfig) -> (bool, Value) {
+    let health = with_health(|health| health.clone());
+    get_readiness_at(&health, health_config, Utc::now())
+}
+//Takes the current time, so a stale sync can be checked without waiting for it
+pub(crate) fn get_readiness_at(
+    health: &Health,
+    health_config: &HealthConfig,
+    now: DateTime<Utc>,
+) -> (bool, Value) {
+    let (last_success, credentials_valid) = (health.last_success, health.credentials_valid);
+    let max_age = Duration::seconds(
+        health.interval_seconds as i64 * health_config.stale_after_intervals as i64 + SLACK_SECONDS,
+    );
+    let reason = match (last_success, credentials_valid) {
+        (_, Some(false)) => Some("cloudflare rejected the credentials".to_string()),
+        (None, _) => Some("no sync has succeeded yet".to_string()),
+        (Some(last_success), _) if now - last_success > max_age => Some(format!(
+            "the last successful sync was more than {} seconds ago",
+            max_age.num_seconds()
+        )),
+        _ => None,
+    };
+    let body = json!({
+        "status": if reason.is_none() { "ready" } else { "not_ready" },
+        "reason": reason,
+        "last_success": last_success.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        "credentials_valid": credentials_valid,
+    });
+    (reason.is_none(), body)
+}
+//Exits with 0 when the running daemon answers that it is ready (or alive), for docker's HEALTHCHECK
+pub fn healthcheck(live: bool) -> ! {
+    let mut config = match get_incomplete_config() {
+        Ok((config, _, _)) => config,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => IncompleteConfig::from_defaults(),
+        Err(err) => {
+            println!("Couldn't read the config file{}", format_err(err));
+            process::exit(1);
+        }
+    };
+    environment::apply_overrides(&mut config);
+    if !config.health.enabled {
+        println!("The health endpoints are turned off. Set health.enabled to true, or CF_DNS_SYNC_HEALTH_ENABLED=true.");
+        process::exit(1);
+    }
+    let path = if live { "/healthz" } else { "/readyz" };
+    let url = format!("http://{}{path}", get_local_address(&config.health.listen));
+    let agent = ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(HEALTHCHECK_TIMEOUT_SECONDS))
+        .build();
+    match agent.get(&url).call() {
+        Ok(response) => {
+            println!("{}", response.into_string().unwrap_or_default());
+            process::exit(0);
+        }
+        Err(ureq::Error::Status(_, response)) => {
+            println!("{}", response.into_string().unwrap_or_default());
+            process::exit(1);
+        }
+        Err(ureq::Error::Transport(transport)) => {
+            println!("Couldn't reach {url} ({})", transport.kind());
+            process::exit(1);
+        }
+    }
+}
+//A server listening on every address is asked on the loopback address
+fn get_local_address(listen: &str) -> String {
+    match listen.rsplit_once(':') {
+        Some(("0.0.0.0", port)) => format!("127.0.0.1:{port}"),
+        Some(("[::]", port)) => format!("[::1]:{port}"),
+        _ => listen.to_string(),
+    }
+}
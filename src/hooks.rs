@@ -0,0 +1,251 @@
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::unending_process::{
+    log_event, log_to_file_and_console, DNSRecord, LogConfig, LogDetails, LogType,
+};
+
+#[derive(Deserialize, Debug, Clone, Serialize, Default)]
+pub struct HooksConfig {
+    //Run before a record is set to a new ip address
+    #[serde(default)]
+    pub pre_update: Vec<HookConfig>,
+    //Run after a record was set, or failed to be set
+    #[serde(default)]
+    pub post_update: Vec<HookConfig>,
+}
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct HookConfig {
+    //Run by sh -c, or by cmd /C on windows
+    pub command: String,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    //Only used by pre_update hooks. A failing hook keeps the record from being updated in this run.
+    #[serde(default = "default_veto")]
+    pub veto: bool,
+}
+impl HookConfig {
+    pub fn new(command: String) -> Self {
+        HookConfig {
+            command,
+            timeout_seconds: default_timeout_seconds(),
+            veto: default_veto(),
+        }
+    }
+}
+fn default_timeout_seconds() -> u64 {
+    30
+}
+fn default_veto() -> bool {
+    true
+}
+#[derive(Clone, Copy, PartialEq)]
+pub enum HookKind {
+    PreUpdate,
+    PostUpdate,
+}
+impl HookKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookKind::PreUpdate => "pre_update",
+            HookKind::PostUpdate => "post_update",
+        }
+    }
+}
+//Returns the reason when a hook vetoed the update
+pub fn run_pre_update_hooks(
+    hooks: &HooksConfig,
+    record: &DNSRecord,
+    new_ip: &str,
+    log_config: &LogConfig,
+) -> Result<(), String> {
+    for hook in hooks.pre_update.iter() {
+        let env = get_env(record, new_ip, None);
+        if let Err(err) = run_hook(hook, HookKind::PreUpdate, record, &env, log_config) {
+            if hook.veto {
+                return Err(format!("the pre_update hook {} {err}", hook.command));
+            }
+        }
+    }
+    Ok(())
+}
+pub fn run_post_update_hooks(
+    hooks: &HooksConfig,
+    record: &DNSRecord,
+    new_ip: &str,
+    success: bool,
+    log_config: &LogConfig,
+) {
+    let result = match success {
+        true => "success",
+        false => "failure",
+    };
+    for hook in hooks.post_update.iter() {
+        let env = get_env(record, new_ip, Some(result));
+        //A failure was already logged, and the update already happened
+        let _ = run_hook(hook, HookKind::PostUpdate, record, &env, log_config);
+    }
+}
+fn get_env<'a>(
+    record: &'a DNSRecord,
+    new_ip: &'a str,
+    result: Option<&'a str>,
+) -> Vec<(&'static str, &'a str)> {
+    let mut env = vec![
+        ("OLD_IP", record.content.as_str()),
+        ("NEW_IP", new_ip),
+        ("RECORD_NAME", record.name.as_str()),
+        ("RECORD_ID", record.id.as_str()),
+    ];
+    if let Some(result) = result {
+        env.push(("RESULT", result));
+    }
+    env
+}
+pub fn run_hook(
+    hook: &HookConfig,
+    kind: HookKind,
+    record: &DNSRecord,
+    env: &[(&str, &str)],
+    log_config: &LogConfig,
+) -> Result<(), String> {
+    let start = Instant::now();
+    let result = run_command(
+        &hook.command,
+        env,
+        Duration::from_secs(hook.timeout_seconds),
+    );
+    let output = match &result {
+        Ok(output) | Err((_, output)) => output.trim(),
+    };
+    if !output.is_empty() {
+        log_to_file_and_console(
+            &format!(
+                "Output of the {} hook {}:\n{output}",
+                kind.name(),
+                hook.command
+            ),
+            LogType::Debug,
+            log_config,
+        );
+    }
+    match result {
+        Ok(_) => {
+            log_event(
+                &format!(
+                    "Ran the {} hook {} for {}",
+                    kind.name(),
+                    hook.command,
+                    record.name
+                ),
+                LogType::Debug,
+                &LogDetails::event("hook_succeeded")
+                    .record(record)
+                    .duration(start.elapsed()),
+                log_config,
+            );
+            Ok(())
+        }
+        Err((err, _)) => {
+            log_event(
+                &format!(
+                    "The {} hook {} for {} {err}",
+                    kind.name(),
+                    hook.command,
+                    record.name
+                ),
+                LogType::Warn,
+                &LogDetails::event("hook_failed")
+                    .record(record)
+                    .duration(start.elapsed()),
+                log_config,
+            );
+            Err(err)
+        }
+    }
+}
+//Returns the combined output of the command, also when it failed
+fn run_command(
+    command: &str,
+    env: &[(&str, &str)],
+    timeout: Duration,
+) -> Result<String, (String, String)> {
+    let mut child = match shell_command(command)
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => return Err((format!("couldn't be started ({err})"), String::new())),
+    };
+    //Read on other threads, a command writing more than the pipe holds would otherwise never exit
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let status = match wait_with_timeout(&mut child, timeout) {
+        Some(status) => status,
+        //Processes started by the command may still hold the pipes open, so the output isn't waited for
+        None => {
+            return Err((
+                format!("timed out after {} seconds", timeout.as_secs()),
+                String::new(),
+            ))
+        }
+    };
+    let mut output = stdout.join().unwrap_or_default();
+    output.push_str(&stderr.join().unwrap_or_default());
+    match status {
+        Ok(status) if status.success() => Ok(output),
+        Ok(status) => match status.code() {
+            Some(code) => Err((format!("exited with {code}"), output)),
+            None => Err(("was stopped by a signal".to_string(), output)),
+        },
+        Err(err) => Err((format!("couldn't be waited for ({err})"), output)),
+    }
+}
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let mut bytes = vec![];
+            let _ = pipe.read_to_end(&mut bytes);
+            output = String::from_utf8_lossy(&bytes).to_string();
+        }
+        output
+    })
+}
+//None when the command was killed for taking too long, processes it started are left running
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> Option<std::io::Result<std::process::ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(Ok(status)),
+            Ok(None) if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(err) => return Some(Err(err)),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::dashboard;
+use crate::health::{self, HealthConfig};
+use crate::metrics;
+use crate::unending_process::{format_err, log_to_file_and_console, Config, LogConfig, LogType};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Endpoints {
+    Metrics,
+    Health,
+    Dashboard,
+}
+//Endpoints that are set to the same address are served by one server
+pub fn start(config: &Config) {
+    let mut servers: BTreeMap<String, Vec<Endpoints>> = BTreeMap::new();
+    if config.metrics.enabled {
+        servers
+            .entry(config.metrics.listen.clone())
+            .or_default()
+            .push(Endpoints::Metrics);
+    }
+    if config.health.enabled {
+        servers
+            .entry(config.health.listen.clone())
+            .or_default()
+            .push(Endpoints::Health);
+    }
+    if config.dashboard.enabled {
+        match config.dashboard.password {
+            Some(ref password) if !password.is_empty() => servers
+                .entry(config.dashboard.listen.clone())
+                .or_default()
+                .push(Endpoints::Dashboard),
+            _ => log_to_file_and_console(
+                "The dashboard isn't served because it has no password. Set dashboard.password, or CF_DNS_SYNC_DASHBOARD_PASSWORD.",
+                LogType::Error,
+                &config.log_config,
+            ),
+        }
+    }
+    for (listen, endpoints) in servers {
+        let server = match Server::http(&listen) {
+            Ok(server) => server,
+            Err(err) => {
+                log_to_file_and_console(
+                    &format!("Couldn't serve http on {listen}{}", format_err(err)),
+                    LogType::Error,
+                    &config.log_config,
+                );
+                continue;
+            }
+        };
+        let mut paths = vec![];
+        if endpoints.contains(&Endpoints::Metrics) {
+            paths.push("/metrics");
+        }
+        if endpoints.contains(&Endpoints::Health) {
+            paths.extend(["/healthz", "/readyz"]);
+        }
+        if endpoints.contains(&Endpoints::Dashboard) {
+            paths.push("the dashboard");
+        }
+        log_to_file_and_console(
+            &format!("Serving {} on http://{listen}", paths.join(", ")),
+            LogType::Log,
+            &config.log_config,
+        );
+        let health_config = config.health.clone();
+        let password = config.dashboard.password.clone().unwrap_or_default();
+        let environment_overrides = config.environment_overrides;
+        let log_config = config.log_config.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                //Every request gets its own thread, so a dashboard edit waiting for the config file doesn't hold up the health checks
+                let endpoints = endpoints.clone();
+                let health_config = health_config.clone();
+                let password = password.clone();
+                let log_config = log_config.clone();
+                std::thread::spawn(move || {
+                    respond(
+                        request,
+                        &endpoints,
+                        &health_config,
+                        &password,
+                        environment_overrides,
+                        &log_config,
+                    )
+                });
+            }
+        });
+    }
+}
+fn respond(
+    mut request: Request,
+    endpoints: &[Endpoints],
+    health_config: &HealthConfig,
+    password: &str,
+    environment_overrides: bool,
+    log_config: &LogConfig,
+) {
+    //Prometheus and probes can add query parameters to the path
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    if endpoints.contains(&Endpoints::Dashboard) && dashboard::is_dashboard_path(&path) {
+        let response = dashboard::handle(
+            &mut request,
+            &path,
+            password,
+            environment_overrides,
+            log_config,
+        );
+        let _ = request.respond(response);
+        return;
+    }
+    let response = match (
+        path.as_str(),
+        endpoints.contains(&Endpoints::Metrics),
+        endpoints.contains(&Endpoints::Health),
+    ) {
+        ("/metrics", true, _) => text_response(
+            200,
+            metrics::render_metrics(),
+            "text/plain; version=0.0.4; charset=utf-8",
+        ),
+        ("/healthz", _, true) => json_response(200, health::get_liveness()),
+        ("/readyz", _, true) => {
+            let (ready, body) = health::get_readiness(health_config);
+            json_response(if ready { 200 } else { 503 }, body)
+        }
+        _ => text_response(404, "Not found".to_string(), "text/plain"),
+    };
+    let _ = request.respond(response);
+}
+pub fn json_response(status: u16, body: Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    text_response(status, body.to_string(), "application/json")
+}
+pub fn text_response(
+    status: u16,
+    body: String,
+    content_type: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type =
+        Header::from_bytes("Content-Type", content_type).expect("the content type header is valid");
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
@@ -0,0 +1,156 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::unending_process::{format_err, log_to_console, LogConfig, LogType};
+
+//Returns the session number of log files like session12.txt, session12.3.txt or session12.3.txt.gz
+pub fn parse_session_number(file_name: &str) -> Option<i32> {
+    let rest = file_name.strip_prefix("session")?;
+    let digits: String = rest
+        .chars()
+        .take_while(|char| char.is_ascii_digit())
+        .collect();
+    let rest = &rest[digits.len()..];
+    if !(rest.starts_with('.') && (rest.ends_with(".txt") || rest.ends_with(".txt.gz"))) {
+        return None;
+    }
+    digits.parse().ok()
+}
+//Moves the active session file aside once it is too big or too old, so that logging continues in a fresh file
+pub fn rotate_if_needed(file_path: &Path, log_config: &LogConfig) {
+    let rotation = &log_config.rotation;
+    let metadata = match fs::metadata(file_path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    let too_big = match rotation.max_file_size_kb {
+        Some(max_file_size_kb) if max_file_size_kb > 0 => metadata.len() >= max_file_size_kb * 1024,
+        _ => false,
+    };
+    let too_old = match (rotation.max_file_age_hours, metadata.created()) {
+        (Some(max_file_age_hours), Ok(created)) if max_file_age_hours > 0 => {
+            match SystemTime::now().duration_since(created) {
+                Ok(age) => age >= Duration::from_secs(max_file_age_hours * 60 * 60),
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    };
+    if !too_big && !too_old {
+        return;
+    }
+    let rotated_path = match get_rotated_path(file_path) {
+        Some(rotated_path) => rotated_path,
+        None => return,
+    };
+    if let Err(err) = fs::rename(file_path, &rotated_path) {
+        log_to_console(
+            &format!("Couldn't rotate log file{}", format_err(err)),
+            LogType::Warn,
+            log_config,
+        );
+        return;
+    }
+    if rotation.compress {
+        if let Err(err) = compress(&rotated_path) {
+            log_to_console(
+                &format!("Couldn't compress rotated log file{}", format_err(err)),
+                LogType::Warn,
+                log_config,
+            );
+        }
+    }
+    apply_retention(log_config);
+}
+//session12.txt is rotated to the next free session12.{n}.txt, so a higher n is always a newer part of the session
+fn get_rotated_path(file_path: &Path) -> Option<PathBuf> {
+    let folder_path = file_path.parent()?;
+    let stem = file_path.file_stem()?.to_str()?;
+    let mut part = 1;
+    loop {
+        let rotated_path = folder_path.join(format!("{stem}.{part}.txt"));
+        let compressed_path = folder_path.join(format!("{stem}.{part}.txt.gz"));
+        if !rotated_path.exists() && !compressed_path.exists() {
+            return Some(rotated_path);
+        }
+        part += 1;
+    }
+}
+fn compress(file_path: &Path) -> Result<(), io::Error> {
+    let mut compressed_name = file_path.as_os_str().to_owned();
+    compressed_name.push(".gz");
+    let mut reader = BufReader::new(File::open(file_path)?);
+    let mut encoder = GzEncoder::new(
+        BufWriter::new(File::create(PathBuf::from(compressed_name))?),
+        Compression::default(),
+    );
+    io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(file_path)
+}
+//Deletes the log files of old sessions and the files older than the configured number of days. The current session is never touched.
+pub fn apply_retention(log_config: &LogConfig) {
+    let rotation = &log_config.rotation;
+    let keep_sessions = rotation
+        .keep_sessions
+        .filter(|keep_sessions| *keep_sessions > 0);
+    let keep_days = rotation.keep_days.filter(|keep_days| *keep_days > 0);
+    if keep_sessions.is_none() && keep_days.is_none() {
+        return;
+    }
+    let folder_path = Path::new(&log_config.log_folder_path).join("logs");
+    let entries = match fs::read_dir(&folder_path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut log_files: Vec<(PathBuf, i32)> = vec![];
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let session_number = match file_name.to_str().and_then(parse_session_number) {
+            Some(session_number) => session_number,
+            None => continue,
+        };
+        log_files.push((entry.path(), session_number));
+    }
+    let mut session_numbers: Vec<i32> = log_files.iter().map(|(_, number)| *number).collect();
+    session_numbers.sort_unstable();
+    session_numbers.dedup();
+    let oldest_kept_session = match keep_sessions {
+        Some(keep_sessions) if session_numbers.len() > keep_sessions as usize => {
+            Some(session_numbers[session_numbers.len() - keep_sessions as usize])
+        }
+        _ => None,
+    };
+    let max_age = keep_days.map(|keep_days| Duration::from_secs(keep_days as u64 * 24 * 60 * 60));
+    for (file_path, session_number) in log_files {
+        if Some(session_number) == log_config.session_number {
+            continue;
+        }
+        let too_many_sessions = oldest_kept_session.is_some_and(|oldest| session_number < oldest);
+        let too_old = match (
+            max_age,
+            fs::metadata(&file_path).and_then(|metadata| metadata.modified()),
+        ) {
+            (Some(max_age), Ok(modified)) => SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age > max_age),
+            _ => false,
+        };
+        if !too_many_sessions && !too_old {
+            continue;
+        }
+        if let Err(err) = fs::remove_file(&file_path) {
+            log_to_console(
+                &format!("Couldn't delete old log file{}", format_err(err)),
+                LogType::Warn,
+                log_config,
+            );
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::unending_process::{format_now, log_to_console, LogConfig, LogDetails, LogType};
+
+#[cfg(unix)]
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+#[cfg(unix)]
+const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+//The daemon facility, as defined in RFC 5424
+#[cfg(unix)]
+const SYSLOG_FACILITY: u8 = 3;
+//32473 is the enterprise number RFC 5424 reserves for examples, there is no registered one for cf_dns_sync
+#[cfg(unix)]
+const STRUCTURED_DATA_ID: &str = "record@32473";
+
+//A failing sink is only reported once, otherwise every line would add another warning to the console
+static SYSLOG_FAILURE_REPORTED: AtomicBool = AtomicBool::new(false);
+static JOURNALD_FAILURE_REPORTED: AtomicBool = AtomicBool::new(false);
+
+//Syslog and journald share the severities of RFC 5424
+#[cfg(unix)]
+fn get_severity(log_type: LogType) -> u8 {
+    match log_type {
+        LogType::Log => 6,
+        LogType::Warn => 4,
+        LogType::Error => 3,
+        LogType::Debug | LogType::Trace => 7,
+    }
+}
+#[cfg(unix)]
+pub fn send_to_syslog(
+    string: &str,
+    log_type: LogType,
+    details: &LogDetails,
+    log_config: &LogConfig,
+) {
+    let message = format!(
+        "<{}>1 {} {} cf_dns_sync {} {} {} {}",
+        SYSLOG_FACILITY * 8 + get_severity(log_type),
+        format_now(
+            "%Y-%m-%dT%H:%M:%S%.6f%:z",
+            &log_config.file.display.timezone
+        ),
+        get_host_name(),
+        std::process::id(),
+        details.event.unwrap_or("message"),
+        get_structured_data(details),
+        string
+    );
+    if let Err(err) = send(SYSLOG_SOCKET_PATH, message.as_bytes()) {
+        report_failure(&SYSLOG_FAILURE_REPORTED, "syslog", err, log_config);
+    }
+}
+#[cfg(unix)]
+pub fn send_to_journald(
+    string: &str,
+    log_type: LogType,
+    details: &LogDetails,
+    log_config: &LogConfig,
+) {
+    let mut message = vec![];
+    append_journald_field(&mut message, "MESSAGE", string);
+    append_journald_field(
+        &mut message,
+        "PRIORITY",
+        &get_severity(log_type).to_string(),
+    );
+    append_journald_field(&mut message, "SYSLOG_IDENTIFIER", "cf_dns_sync");
+    append_journald_field(&mut message, "EVENT", details.event.unwrap_or("message"));
+    let optional_fields = [
+        ("RECORD_NAME", details.record_name),
+        ("RECORD_ID", details.record_id),
+        ("OLD_IP", details.old_content),
+        ("NEW_IP", details.new_content),
+    ];
+    for (key, value) in optional_fields {
+        if let Some(value) = value {
+            append_journald_field(&mut message, key, value);
+        }
+    }
+    if let Some(duration) = details.duration {
+        append_journald_field(
+            &mut message,
+            "DURATION_MS",
+            &duration.as_millis().to_string(),
+        );
+    }
+    if let Err(err) = send(JOURNALD_SOCKET_PATH, &message) {
+        report_failure(&JOURNALD_FAILURE_REPORTED, "journald", err, log_config);
+    }
+}
+#[cfg(not(unix))]
+pub fn send_to_syslog(
+    _string: &str,
+    _log_type: LogType,
+    _details: &LogDetails,
+    log_config: &LogConfig,
+) {
+    report_failure(
+        &SYSLOG_FAILURE_REPORTED,
+        "syslog",
+        "it is only available on unix",
+        log_config,
+    );
+}
+#[cfg(not(unix))]
+pub fn send_to_journald(
+    _string: &str,
+    _log_type: LogType,
+    _details: &LogDetails,
+    log_config: &LogConfig,
+) {
+    report_failure(
+        &JOURNALD_FAILURE_REPORTED,
+        "journald",
+        "it is only available on unix",
+        log_config,
+    );
+}
+fn report_failure(
+    reported: &AtomicBool,
+    sink_name: &str,
+    err: impl std::fmt::Display,
+    log_config: &LogConfig,
+) {
+    if reported.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    log_to_console(
+        &format!("Couldn't send logs to {sink_name}, {err}. Further failures won't be reported."),
+        LogType::Warn,
+        log_config,
+    );
+}
+#[cfg(unix)]
+fn send(socket_path: &str, message: &[u8]) -> Result<(), std::io::Error> {
+    use std::os::unix::net::UnixDatagram;
+    use std::sync::OnceLock;
+    static SOCKET: OnceLock<Result<UnixDatagram, String>> = OnceLock::new();
+    let socket = match SOCKET.get_or_init(|| UnixDatagram::unbound().map_err(|err| err.to_string()))
+    {
+        Ok(socket) => socket,
+        Err(err) => return Err(std::io::Error::other(err.clone())),
+    };
+    socket.send_to(message, socket_path)?;
+    Ok(())
+}
+pub fn get_host_name() -> &'static str {
+    use std::sync::OnceLock;
+    use sysinfo::{System, SystemExt};
+    static HOST_NAME: OnceLock<String> = OnceLock::new();
+    HOST_NAME.get_or_init(|| {
+        System::new()
+            .host_name()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("-".to_string())
+    })
+}
+//[record@32473 name="home.example.com" id="..."] or - when the line isn't about a record
+#[cfg(unix)]
+fn get_structured_data(details: &LogDetails) -> String {
+    let duration_ms = details
+        .duration
+        .map(|duration| duration.as_millis().to_string());
+    let params = [
+        ("name", details.record_name),
+        ("id", details.record_id),
+        ("old_content", details.old_content),
+        ("new_content", details.new_content),
+        ("duration_ms", duration_ms.as_deref()),
+    ];
+    let mut structured_data = String::new();
+    for (key, value) in params {
+        if let Some(value) = value {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace(']', "\\]");
+            structured_data.push_str(&format!(" {key}=\"{value}\""));
+        }
+    }
+    if structured_data.is_empty() {
+        return "-".to_string();
+    }
+    format!("[{STRUCTURED_DATA_ID}{structured_data}]")
+}
+//Uses the binary form of the native journal protocol for values with newlines, since KEY=value can't hold them
+#[cfg(unix)]
+fn append_journald_field(message: &mut Vec<u8>, key: &str, value: &str) {
+    message.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        message.extend_from_slice(value.as_bytes());
+    } else {
+        message.push(b'=');
+        message.extend_from_slice(value.as_bytes());
+    }
+    message.push(b'\n');
+}
@@ -0,0 +1,542 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use serde_json::Value;
+
+use crate::{
+    environment,
+    state::format_local,
+    unending_process::{
+        get_config_path, get_timestamp_format, parse_config_file, DisplayConfig, IncompleteConfig,
+        LogConfig, LogFormat, LogTimezone, SinkConfig, Verbosity,
+    },
+};
+
+//How many lines of the current session are printed before following it
+const FOLLOW_CONTEXT_LINES: usize = 20;
+//How much is read at a time when reading a file from its end
+const TAIL_BLOCK_BYTES: u64 = 16 * 1024;
+
+pub struct LogFilter {
+    pub level: Option<Verbosity>,
+    pub record_name: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+impl LogFilter {
+    fn is_empty(&self) -> bool {
+        self.level.is_none()
+            && self.record_name.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+    }
+}
+//The parts of one session, oldest first. The active sessionN.txt always comes last.
+struct Session {
+    number: i32,
+    parts: Vec<PathBuf>,
+}
+pub fn logs(session_number: Option<i32>, follow: bool, filter: LogFilter) {
+    let log_config = load_log_config();
+    if (filter.since.is_some() || filter.until.is_some())
+        && log_config.file.format == LogFormat::Text
+        && !has_full_timestamps(&log_config.file)
+    {
+        println!(
+            "--since and --until need log timestamps with a date and a time, but the timestamp format of log_config.file is \"{}\"",
+            get_timestamp_format(&log_config.file.display)
+        );
+        process::exit(1);
+    }
+    let sessions = find_sessions(&log_config);
+    if follow {
+        follow_session(&log_config, sessions, session_number, &filter);
+    }
+    match session_number {
+        Some(session_number) => match sessions
+            .iter()
+            .find(|session| session.number == session_number)
+        {
+            Some(session) => {
+                print_session(session, &log_config, &filter, false);
+            }
+            None => {
+                println!("There are no logs of session {session_number}");
+                process::exit(1);
+            }
+        },
+        None if filter.is_empty() => list_sessions(&sessions, &log_config),
+        None => {
+            let mut has_matches = false;
+            for session in sessions.iter() {
+                has_matches |= print_session(session, &log_config, &filter, true);
+            }
+            if !has_matches {
+                println!("No log lines match the filter");
+            }
+        }
+    }
+}
+//Reads the config without migrating, prompting or logging anything, since this command only looks at the logs
+fn load_log_config() -> LogConfig {
+    let config_path = match get_config_path() {
+        Ok(config_path) => config_path,
+        Err(()) => {
+            println!("Couldn't determine where the config file is");
+            process::exit(1);
+        }
+    };
+    let mut config = match fs::read_to_string(&config_path) {
+        Ok(contents) => match parse_config_file(&config_path, &contents) {
+            Ok((config, _)) => config,
+            Err(err) => {
+                println!(
+                    "Couldn't read the config file at {}\n{err}",
+                    config_path.display()
+                );
+                process::exit(1);
+            }
+        },
+        Err(_) => IncompleteConfig::from_defaults(),
+    };
+    environment::apply_overrides(&mut config);
+    config.log_config
+}
+fn find_sessions(log_config: &LogConfig) -> Vec<Session> {
+    let folder_path = Path::new(&log_config.log_folder_path).join("logs");
+    let entries = match fs::read_dir(&folder_path) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    //None is the active file, which sorts after every rotated part
+    let mut sessions: BTreeMap<i32, Vec<(Option<u32>, PathBuf)>> = BTreeMap::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let (number, part) = match file_name.to_str().and_then(parse_file_name) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        sessions
+            .entry(number)
+            .or_default()
+            .push((part, entry.path()));
+    }
+    sessions
+        .into_iter()
+        .map(|(number, mut parts)| {
+            parts.sort_by_key(|(part, _)| part.unwrap_or(u32::MAX));
+            Session {
+                number,
+                parts: parts.into_iter().map(|(_, path)| path).collect(),
+            }
+        })
+        .collect()
+}
+//session12.txt is (12, None) and session12.3.txt or session12.3.txt.gz is (12, Some(3))
+fn parse_file_name(file_name: &str) -> Option<(i32, Option<u32>)> {
+    let rest = file_name.strip_prefix("session")?;
+    let number_length = rest
+        .chars()
+        .take_while(|char| char.is_ascii_digit())
+        .count();
+    let number = rest[..number_length].parse().ok()?;
+    let rest = &rest[number_length..];
+    if rest == ".txt" {
+        return Some((number, None));
+    }
+    let rest = rest.strip_prefix('.')?;
+    let part_length = rest
+        .chars()
+        .take_while(|char| char.is_ascii_digit())
+        .count();
+    let part = rest[..part_length].parse().ok()?;
+    match &rest[part_length..] {
+        ".txt" | ".txt.gz" => Some((number, Some(part))),
+        _ => None,
+    }
+}
+fn read_part(path: &Path) -> String {
+    read_part_start(path, u64::MAX)
+}
+//Rotated parts are decompressed on the fly, so they can be read like the active file
+fn read_part_start(path: &Path, max_bytes: u64) -> String {
+    let mut bytes = vec![];
+    let result = match File::open(path) {
+        Ok(file) => {
+            if path.extension().is_some_and(|extension| extension == "gz") {
+                GzDecoder::new(file).take(max_bytes).read_to_end(&mut bytes)
+            } else {
+                file.take(max_bytes).read_to_end(&mut bytes)
+            }
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        println!("Couldn't read {}: {err}", path.display());
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+fn list_sessions(sessions: &[Session], log_config: &LogConfig) {
+    if sessions.is_empty() {
+        println!(
+            "There are no session logs in {}",
+            Path::new(&log_config.log_folder_path)
+                .join("logs")
+                .display()
+        );
+        return;
+    }
+    println!(
+        "{:8} {:20} {:20} {:>10} Parts",
+        "Session", "Started", "Last written", "Size"
+    );
+    for session in sessions.iter() {
+        let size: u64 = session
+            .parts
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let started = match session
+            .parts
+            .first()
+            .and_then(|path| get_start_time(path, log_config))
+        {
+            Some(started) => format_local(&started),
+            None => "unknown".to_string(),
+        };
+        let last_written = match session.parts.last().and_then(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        }) {
+            Some(modified) => format_local(&DateTime::<Utc>::from(modified)),
+            None => "unknown".to_string(),
+        };
+        println!(
+            "{:8} {:20} {:20} {:>10} {}",
+            session.number,
+            started,
+            last_written,
+            format_size(size),
+            session.parts.len()
+        );
+    }
+}
+//The time of the first line of the oldest part, or when the file was created if the line can't be read
+fn get_start_time(path: &Path, log_config: &LogConfig) -> Option<DateTime<Utc>> {
+    let contents = read_part_start(path, 4096);
+    let mut line_filter = LineFilter::new(&log_config.file, None);
+    let first_time = contents.lines().find_map(|line| {
+        line_filter.accepts(line);
+        line_filter.last_time
+    });
+    first_time.or_else(|| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.created().or(metadata.modified()))
+            .ok()
+            .map(DateTime::<Utc>::from)
+    })
+}
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+//Returns if anything was printed. When searching through every session, each one with matches gets a header.
+fn print_session(
+    session: &Session,
+    log_config: &LogConfig,
+    filter: &LogFilter,
+    with_header: bool,
+) -> bool {
+    let mut line_filter = LineFilter::new(&log_config.file, Some(filter));
+    let mut has_matches = false;
+    for path in session.parts.iter() {
+        for line in read_part(path).lines() {
+            if !line_filter.accepts(line) {
+                continue;
+            }
+            if with_header && !has_matches {
+                println!("--- session {} ---", session.number);
+            }
+            has_matches = true;
+            println!("{line}");
+        }
+    }
+    has_matches
+}
+fn follow_session(
+    log_config: &LogConfig,
+    sessions: Vec<Session>,
+    session_number: Option<i32>,
+    filter: &LogFilter,
+) -> ! {
+    let mut session_number =
+        match session_number.or_else(|| sessions.last().map(|session| session.number)) {
+            Some(session_number) => session_number,
+            None => {
+                println!("There are no session logs to follow yet");
+                process::exit(1);
+            }
+        };
+    let folder_path = Path::new(&log_config.log_folder_path).join("logs");
+    let mut line_filter = LineFilter::new(&log_config.file, Some(filter));
+    let mut file_path = folder_path.join(format!("session{session_number}.txt"));
+    //The position counts bytes, since a lossy conversion can change the length of invalid utf-8
+    let bytes = fs::read(&file_path).unwrap_or_default();
+    let mut position = bytes.len() as u64;
+    let contents = String::from_utf8_lossy(&bytes);
+    let context: Vec<&str> = contents
+        .lines()
+        .filter(|line| line_filter.accepts(line))
+        .collect();
+    for line in context[context.len().saturating_sub(FOLLOW_CONTEXT_LINES)..].iter() {
+        println!("{line}");
+    }
+    let mut partial_line: Vec<u8> = vec![];
+    loop {
+        thread::sleep(Duration::from_millis(500));
+        //A new session starts when the daemon is restarted with separate_logs_by_session
+        let newer_session = find_sessions(log_config)
+            .into_iter()
+            .map(|session| session.number)
+            .filter(|number| *number > session_number)
+            .min();
+        let length = match fs::metadata(&file_path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        //The file got shorter, so it was rotated and logging restarted in a fresh file
+        if length < position {
+            position = 0;
+            partial_line.clear();
+        }
+        if length > position {
+            if let Ok(mut file) = File::open(&file_path) {
+                let mut bytes = vec![];
+                if file.seek(SeekFrom::Start(position)).is_ok()
+                    && file.read_to_end(&mut bytes).is_ok()
+                {
+                    position += bytes.len() as u64;
+                    partial_line.extend_from_slice(&bytes);
+                    //Only complete lines are printed, the rest waits for the next read, so a character split
+                    //between two reads is decoded whole
+                    while let Some(index) = partial_line.iter().position(|byte| *byte == b'\n') {
+                        let line: Vec<u8> = partial_line.drain(..=index).collect();
+                        let line = String::from_utf8_lossy(&line);
+                        let line = line.trim_end_matches(['\n', '\r']);
+                        if line_filter.accepts(line) {
+                            println!("{line}");
+                        }
+                    }
+                }
+            }
+        } else if let Some(newer_session) = newer_session {
+            session_number = newer_session;
+            file_path = folder_path.join(format!("session{session_number}.txt"));
+            position = 0;
+            partial_line.clear();
+            println!("--- session {session_number} ---");
+        }
+    }
+}
+//The session the daemon writes to is the newest one
+pub fn get_latest_session_number(log_config: &LogConfig) -> Option<i32> {
+    find_sessions(log_config)
+        .last()
+        .map(|session| session.number)
+}
+//The last lines of the session the daemon is writing to, for the dashboard
+pub fn tail_session(log_config: &LogConfig, line_count: usize) -> Vec<String> {
+    let session_number = match log_config.session_number {
+        Some(session_number) => session_number,
+        None => return vec![],
+    };
+    let file_path = Path::new(&log_config.log_folder_path)
+        .join("logs")
+        .join(format!("session{session_number}.txt"));
+    read_last_lines(&file_path, line_count)
+}
+//Reads blocks from the end of the file until there are enough lines, so a long file isn't read whole
+pub fn read_last_lines(path: &Path, line_count: usize) -> Vec<String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return vec![],
+    };
+    let mut start = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return vec![],
+    };
+    let mut contents: Vec<u8> = vec![];
+    //The first line read is usually cut off, so one more line break than lines is needed
+    while start > 0 && contents.iter().filter(|byte| **byte == b'\n').count() <= line_count {
+        let block_size = TAIL_BLOCK_BYTES.min(start);
+        start -= block_size;
+        let mut block = vec![0; block_size as usize];
+        if file
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut block))
+            .is_err()
+        {
+            return vec![];
+        }
+        block.extend_from_slice(&contents);
+        contents = block;
+    }
+    let contents = String::from_utf8_lossy(&contents);
+    let mut lines: Vec<&str> = contents.lines().collect();
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    lines[lines.len().saturating_sub(line_count)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+//Decides line by line what to print. Lines that don't start a new entry (like the rest of a multi line error)
+//belong to the entry before them and are printed with it.
+struct LineFilter<'a> {
+    display: &'a DisplayConfig,
+    timestamp_format: String,
+    filter: Option<&'a LogFilter>,
+    last_time: Option<DateTime<Utc>>,
+    printing: bool,
+}
+impl<'a> LineFilter<'a> {
+    fn new(sink: &'a SinkConfig, filter: Option<&'a LogFilter>) -> Self {
+        LineFilter {
+            display: &sink.display,
+            timestamp_format: get_timestamp_format(&sink.display),
+            filter,
+            last_time: None,
+            printing: true,
+        }
+    }
+    fn accepts(&mut self, line: &str) -> bool {
+        let entry = match self.parse_entry_start(line) {
+            Some(entry) => entry,
+            None => return self.printing,
+        };
+        //Lines without a readable time happened at about the same time as the line before them
+        if entry.time.is_some() {
+            self.last_time = entry.time;
+        }
+        let filter = match self.filter {
+            Some(filter) => filter,
+            None => return true,
+        };
+        self.printing = filter
+            .level
+            .is_none_or(|max_level| entry.level.is_some_and(|level| level <= max_level))
+            && filter.record_name.as_ref().is_none_or(|name| {
+                entry.record_name.as_deref() == Some(name.as_str()) || line.contains(name.as_str())
+            })
+            && filter
+                .since
+                .is_none_or(|since| self.last_time.is_some_and(|time| time >= since))
+            && filter
+                .until
+                .is_none_or(|until| self.last_time.is_some_and(|time| time <= until));
+        self.printing
+    }
+    fn parse_entry_start(&self, line: &str) -> Option<EntryStart> {
+        //Json lines are recognized even when the format was changed since they were written
+        if line.starts_with('{') {
+            if let Ok(Value::Object(json)) = serde_json::from_str::<Value>(line) {
+                let time = json
+                    .get("timestamp")
+                    .and_then(|time| time.as_str())
+                    .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                    .map(|time| time.with_timezone(&Utc));
+                let level = json
+                    .get("level")
+                    .and_then(|level| level.as_str())
+                    .and_then(|level| level.parse().ok());
+                let record_name = json
+                    .get("record_name")
+                    .and_then(|name| name.as_str())
+                    .map(|name| name.to_string());
+                return Some(EntryStart {
+                    time,
+                    level,
+                    record_name,
+                });
+            }
+        }
+        let mut rest = line;
+        let mut time = None;
+        if !self.timestamp_format.is_empty() {
+            let end = rest.strip_prefix('[')?.find("] ")? + 1;
+            time = parse_timestamp(
+                &rest[1..end],
+                &self.timestamp_format,
+                &self.display.timezone,
+            );
+            rest = &rest[end + 2..];
+        }
+        let mut level = None;
+        if self.display.log_type {
+            let labels = [
+                ("[LOG] ", Verbosity::Info),
+                ("[WARN] ", Verbosity::Warn),
+                ("[ERROR] ", Verbosity::Error),
+                ("[DEBUG] ", Verbosity::Debug),
+                ("[TRACE] ", Verbosity::Trace),
+            ];
+            let (_, verbosity) = labels.iter().find(|(label, _)| rest.starts_with(label))?;
+            level = Some(*verbosity);
+        }
+        Some(EntryStart {
+            time,
+            level,
+            record_name: None,
+        })
+    }
+}
+//What is known about a line that starts a new entry
+struct EntryStart {
+    time: Option<DateTime<Utc>>,
+    level: Option<Verbosity>,
+    record_name: Option<String>,
+}
+//Formats a sample time and reads it back, since a format like %H:%M:%S can't be placed on a day
+fn has_full_timestamps(sink: &SinkConfig) -> bool {
+    let format = get_timestamp_format(&sink.display);
+    if format.is_empty() {
+        return false;
+    }
+    let sample = Utc.with_ymd_and_hms(2023, 11, 24, 23, 40, 0).unwrap();
+    let timestamp = sample.format(&format).to_string();
+    parse_timestamp(&timestamp, &format, "utc").is_some()
+}
+fn parse_timestamp(timestamp: &str, format: &str, timezone: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_str(timestamp, format) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(timestamp, format).ok()?;
+    let time = match LogTimezone::parse(timezone).unwrap_or(LogTimezone::Local) {
+        LogTimezone::Local => Local
+            .from_local_datetime(&naive)
+            .earliest()?
+            .with_timezone(&Utc),
+        LogTimezone::Utc => Utc.from_utc_datetime(&naive),
+        LogTimezone::Named(timezone) => timezone
+            .from_local_datetime(&naive)
+            .earliest()?
+            .with_timezone(&Utc),
+    };
+    Some(time)
+}
@@ -1,33 +1,170 @@
 use std::{
     error::Error,
+    net::ToSocketAddrs,
     path::{Path, PathBuf},
     process,
 };
 
-use dialoguer::{theme::ColorfulTheme, Input, MultiSelect, Select};
+use dialoguer::{theme::ColorfulTheme, Input, MultiSelect, Password, Select};
 use unending_process::{
-    create_selection_list, get_log_folder, get_session_number, update_dns_list, Config,
+    create_selection_list, get_log_folder, get_session_number, is_valid_timestamp_format,
+    preview_log_line, update_dns_list, Config, LogTimezone, SinkConfig,
 };
 
-use crate::unending_process::{format_err, get_config, log_to_file_and_console, LogType};
+use crate::chat::{ChatConfig, ChatService, CHAT_SERVICES};
+use crate::email::{EmailConfig, SmtpSecurity};
+use crate::hooks::{HookConfig, HookKind};
+use crate::notifications::{
+    Notification, NotificationEvent, QuietHoursConfig, WebhookConfig, NOTIFICATION_EVENTS,
+};
+use crate::unending_process::{
+    format_err, get_config, log_to_file_and_console, LogFormat, LogType, Verbosity,
+};
 
+mod chat;
+mod config_file;
+mod control;
+mod dashboard;
+mod email;
+mod environment;
+mod health;
+mod hooks;
+mod http_server;
+mod log_rotation;
+mod log_sinks;
+mod logs;
+mod metrics;
+mod migrations;
+mod mqtt;
+mod notifications;
+mod state;
+mod tui;
 mod unending_process;
+mod validate;
 fn main() {
-    let mut args = std::env::args();
-    if args.len() > 1 {
-        args.next().unwrap();
-        let arg = args.next().unwrap();
-        if arg == "configure".to_string() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(config_path) = take_flag_value(&mut args, "--config") {
+        unending_process::set_config_path_override(PathBuf::from(config_path));
+    }
+    if let Some(color) = take_flag_value(&mut args, "--color") {
+        match color.parse() {
+            Ok(color_choice) => unending_process::set_color_choice(color_choice),
+            Err(()) => {
+                println!("--color must be auto, always or never, not {color}");
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(verbosity) = take_verbosity_flags(&mut args) {
+        unending_process::set_verbosity(verbosity);
+    }
+    match args.first().map(|arg| arg.as_str()) {
+        Some("configure") => {
+            if environment::has_overrides() {
+                println!("configure edits the config file, but CF_DNS_SYNC_* environment variables are overriding it. Unset them and try again.");
+                process::exit(1);
+            }
             let (config, config_path) = get_config();
             main_selection(config, config_path);
-        } else {
+        }
+        Some("validate") => validate::validate(),
+        Some("healthcheck") => health::healthcheck(args.iter().any(|arg| arg == "--live")),
+        Some("status") => state::print_status(),
+        Some("sync-now") => control::run_command("sync_now"),
+        Some("pause") => control::run_command("pause"),
+        Some("resume") => control::run_command("resume"),
+        Some("tui") => tui::tui(),
+        Some("history") => {
+            let since = take_date_flag(&mut args, "--since", false);
+            let until = take_date_flag(&mut args, "--until", true);
+            let show_updates = args.iter().any(|arg| arg == "--updates");
+            state::print_history(since, until, show_updates);
+        }
+        Some("logs") => {
+            let follow = args.iter().any(|arg| arg == "-f" || arg == "--follow");
+            args.retain(|arg| arg != "-f" && arg != "--follow");
+            let level = take_flag_value(&mut args, "--level").map(|level| match level.parse() {
+                Ok(level) => level,
+                Err(()) => {
+                    println!("--level must be error, warn, info, debug or trace, not {level}");
+                    process::exit(1);
+                }
+            });
+            let filter = logs::LogFilter {
+                level,
+                record_name: take_flag_value(&mut args, "--record"),
+                since: take_date_flag(&mut args, "--since", false),
+                until: take_date_flag(&mut args, "--until", true),
+            };
+            let session_number = args.get(1).map(|arg| match arg.parse() {
+                Ok(session_number) => session_number,
+                Err(_) => {
+                    println!("{arg} isn't a session number");
+                    process::exit(1);
+                }
+            });
+            logs::logs(session_number, follow, filter);
+        }
+        Some(arg) => {
             println!(
-                "There is no command called {}. Did you mean to write configure?",
+                "There is no command called {}. Did you mean to write configure, validate, healthcheck, status, sync-now, pause, resume, tui, history or logs?",
                 arg
             );
         }
+        None => unending_process::process(),
+    }
+}
+//Removes a flag and its value from the arguments, accepting both "--flag value" and "--flag=value"
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let prefix = format!("{flag}=");
+    let index = args
+        .iter()
+        .position(|arg| arg == flag || arg.starts_with(&prefix))?;
+    let arg = args.remove(index);
+    if arg != flag {
+        return Some(arg[prefix.len()..].to_string());
+    }
+    if index < args.len() {
+        Some(args.remove(index))
     } else {
-        unending_process::process();
+        println!("{flag} needs a value");
+        process::exit(1);
+    }
+}
+//-v enables debug lines and -vv (or -v -v) trace lines. They take precedence over RUST_LOG.
+fn take_verbosity_flags(args: &mut Vec<String>) -> Option<Verbosity> {
+    let mut count = 0;
+    args.retain(|arg| {
+        if arg == "--verbose" {
+            count += 1;
+            return false;
+        }
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && flags.chars().all(|char| char == 'v') => {
+                count += flags.len();
+                false
+            }
+            _ => true,
+        }
+    });
+    match count {
+        0 => None,
+        1 => Some(Verbosity::Debug),
+        _ => Some(Verbosity::Trace),
+    }
+}
+fn take_date_flag(
+    args: &mut Vec<String>,
+    flag: &str,
+    end_of_day: bool,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let value = take_flag_value(args, flag)?;
+    match state::parse_date_argument(&value, end_of_day) {
+        Some(date) => Some(date),
+        None => {
+            println!("{flag} must be a date like 2023-11-24 or 2023-11-24 23:40, not {value}");
+            process::exit(1);
+        }
     }
 }
 fn main_selection(mut config: Config, config_path: PathBuf) {
@@ -36,6 +173,9 @@ fn main_selection(mut config: Config, config_path: PathBuf) {
         "Authentication",
         "Log Configuration",
         "DNS Records",
+        "Notifications",
+        "Hooks",
+        "Monitoring",
         "Exit",
     ];
     let index = match Select::with_theme(&ColorfulTheme::default())
@@ -63,7 +203,10 @@ fn main_selection(mut config: Config, config_path: PathBuf) {
         1 => authentication_selection(config, config_path),
         2 => log_config_selection(config, config_path),
         3 => dns_config_selection(config, config_path),
-        4 => {
+        4 => notifications_selection(config, config_path),
+        5 => hooks_selection(config, config_path),
+        6 => monitoring_selection(config, config_path),
+        7 => {
             return;
         }
         _ => out_of_bounds_selection(&config),
@@ -143,8 +286,10 @@ fn log_config_selection(mut config: Config, config_path: PathBuf) {
     let options = &[
         "Log configuration path",
         "Separate logs by session",
-        "Display",
-        "Show",
+        "Console",
+        "Log file",
+        "Rotation and retention",
+        "Outputs",
         "Back",
         "Exit",
     ];
@@ -244,15 +389,42 @@ fn log_config_selection(mut config: Config, config_path: PathBuf) {
             save_config(&config, &config_path, "separating logs by session");
             log_config_selection(config, config_path);
         }
-        2 => display_selection(config, config_path),
-        3 => show_selection(config, config_path),
-        4 => main_selection(config, config_path),
-        5 => process::exit(0),
+        2 => sink_selection(config, config_path, Sink::Console),
+        3 => sink_selection(config, config_path, Sink::File),
+        4 => rotation_selection(config, config_path),
+        5 => outputs_selection(config, config_path),
+        6 => main_selection(config, config_path),
+        7 => process::exit(0),
         _ => out_of_bounds_selection(&config),
     }
 }
-fn display_selection(mut config: Config, config_path: PathBuf) {
-    let options = &["Date", "Time", "Log Type", "Back", "Exit"];
+#[derive(Clone, Copy)]
+enum Sink {
+    Console,
+    File,
+}
+impl Sink {
+    fn name(self) -> &'static str {
+        match self {
+            Sink::Console => "console",
+            Sink::File => "log file",
+        }
+    }
+    fn get(self, config: &Config) -> &SinkConfig {
+        match self {
+            Sink::Console => &config.log_config.console,
+            Sink::File => &config.log_config.file,
+        }
+    }
+    fn get_mut(self, config: &mut Config) -> &mut SinkConfig {
+        match self {
+            Sink::Console => &mut config.log_config.console,
+            Sink::File => &mut config.log_config.file,
+        }
+    }
+}
+fn sink_selection(mut config: Config, config_path: PathBuf, sink: Sink) {
+    let options = &["Level", "Format", "Display", "Back", "Exit"];
     let index = match Select::with_theme(&ColorfulTheme::default())
         .items(&options[..])
         .interact()
@@ -262,56 +434,91 @@ fn display_selection(mut config: Config, config_path: PathBuf) {
     };
     match index {
         0 => {
-            match bool_select(
+            match level_select(
                 &config,
-                "[ Should the date be added to the beginning to every new line? -> 24/11/2023 23:40:43] [LOG]",
+                &format!("Which lines should be written to the {}?", sink.name()),
             ) {
-                Some(value) => config.log_config.display.date = value,
+                Some(level) => sink.get_mut(&mut config).level = level,
                 None => {
-                    log_config_selection(config, config_path);
+                    sink_selection(config, config_path, sink);
                     return;
                 }
             }
-            save_config(&config, &config_path, "if the date should be shown");
-            display_selection(config, config_path);
+            save_config(&config, &config_path, &format!("the {} level", sink.name()));
+            sink_selection(config, config_path, sink);
         }
         1 => {
-            match bool_select(
+            match log_format_select(
                 &config,
-                "[24/11/2023 Should the time be added to the beginning to every new line? -> 23:40:43] [LOG]",
+                &format!("How should lines be written to the {}?", sink.name()),
             ) {
-                Some(value) => config.log_config.display.time = value,
+                Some(format) => sink.get_mut(&mut config).format = format,
                 None => {
-                    log_config_selection(config, config_path);
+                    sink_selection(config, config_path, sink);
                     return;
                 }
             }
-            save_config(&config, &config_path, "if the time should be shown");
-            display_selection(config, config_path);
-        }
-        2 => {
-            match bool_select(
+            save_config(
                 &config,
-                "[24/11/2023 23:40:43] Should the log type be added to the beginning to every new line? -> [LOG]",
-            ) {
-                Some(value) => config.log_config.display.log_type = value,
-                None => {
-                    log_config_selection(config, config_path);
-                    return;
-                }
-            }
-            save_config(&config, &config_path, "if the log type should be shown");
-            display_selection(config, config_path);
+                &config_path,
+                &format!("the {} format", sink.name()),
+            );
+            sink_selection(config, config_path, sink);
         }
+        2 => display_selection(config, config_path, sink),
         3 => log_config_selection(config, config_path),
         4 => process::exit(0),
         _ => out_of_bounds_selection(&config),
     }
 }
-fn show_selection(mut config: Config, config_path: PathBuf) {
-    let options = &["Logs", "Warnings", "Errors", "Back", "Exit"];
+fn level_select(config: &Config, prompt: &str) -> Option<Verbosity> {
+    let options = &[
+        "Errors",
+        "Warnings and errors",
+        "Logs, warnings and errors",
+        "Debug (adds cloudflare requests and responses)",
+        "Trace (adds complete request and response bodies)",
+        "Nothing",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .with_prompt(prompt)
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(config, Box::new(err)),
+    };
+    match index {
+        0 => Some(Verbosity::Error),
+        1 => Some(Verbosity::Warn),
+        2 => Some(Verbosity::Info),
+        3 => Some(Verbosity::Debug),
+        4 => Some(Verbosity::Trace),
+        5 => Some(Verbosity::Off),
+        6 => None,
+        7 => process::exit(0),
+        _ => out_of_bounds_selection(config),
+    }
+}
+fn display_selection(mut config: Config, config_path: PathBuf, sink: Sink) {
+    let options = &[
+        "Date",
+        "Time",
+        "Log Type",
+        "Timestamp format",
+        "Timezone",
+        "Milliseconds",
+        "Back",
+        "Exit",
+    ];
     let index = match Select::with_theme(&ColorfulTheme::default())
         .items(&options[..])
+        .with_prompt(format!(
+            "Preview: {}",
+            preview_log_line(&sink.get(&config).display)
+        ))
         .interact()
     {
         Ok(list) => list,
@@ -321,62 +528,301 @@ fn show_selection(mut config: Config, config_path: PathBuf) {
         0 => {
             match bool_select(
                 &config,
-                "Should logs be printed to the console and written to the log file?",
+                "[ Should the date be added to the beginning to every new line? -> 24/11/2023 23:40:43] [LOG]",
             ) {
-                Some(value) => config.log_config.show.logs = value,
+                Some(value) => sink.get_mut(&mut config).display.date = value,
                 None => {
-                    log_config_selection(config, config_path);
+                    display_selection(config, config_path, sink);
                     return;
                 }
             }
             save_config(
                 &config,
                 &config_path,
-                "if logs should be displayed to the console",
+                &format!("if the date should be shown in the {}", sink.name()),
             );
-            show_selection(config, config_path);
+            display_selection(config, config_path, sink);
         }
         1 => {
             match bool_select(
                 &config,
-                "Should warnings be printed to the console and written to the log file?",
+                "[24/11/2023 Should the time be added to the beginning to every new line? -> 23:40:43] [LOG]",
             ) {
-                Some(value) => config.log_config.show.warnings = value,
+                Some(value) => sink.get_mut(&mut config).display.time = value,
                 None => {
-                    log_config_selection(config, config_path);
+                    display_selection(config, config_path, sink);
                     return;
                 }
             }
             save_config(
                 &config,
                 &config_path,
-                "if warnings should be displayed to the console",
+                &format!("if the time should be shown in the {}", sink.name()),
             );
-            show_selection(config, config_path);
+            display_selection(config, config_path, sink);
         }
         2 => {
             match bool_select(
                 &config,
-                "Should errors be printed to the console and written to the log file?",
+                "[24/11/2023 23:40:43] Should the log type be added to the beginning to every new line? -> [LOG]",
             ) {
-                Some(value) => config.log_config.show.errors = value,
+                Some(value) => sink.get_mut(&mut config).display.log_type = value,
                 None => {
-                    log_config_selection(config, config_path);
+                    display_selection(config, config_path, sink);
+                    return;
+                }
+            }
+            save_config(
+                &config,
+                &config_path,
+                &format!("if the log type should be shown in the {}", sink.name()),
+            );
+            display_selection(config, config_path, sink);
+        }
+        3 => {
+            let format: String = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("A strftime format like %Y-%m-%d %H:%M:%S%.3f (leave empty to use the date, time and milliseconds settings)")
+                .allow_empty(true)
+                .validate_with(|format: &String| {
+                    if is_valid_timestamp_format(format) {
+                        Ok(())
+                    } else {
+                        Err("This isn't a valid strftime format")
+                    }
+                })
+                .interact_text()
+            {
+                Ok(format) => format,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            sink.get_mut(&mut config).display.timestamp_format = if format.is_empty() {
+                None
+            } else {
+                Some(format)
+            };
+            save_config(
+                &config,
+                &config_path,
+                &format!("the {} timestamp format", sink.name()),
+            );
+            display_selection(config, config_path, sink);
+        }
+        4 => {
+            let timezone: String = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The timezone of timestamps: local, utc or a name like Europe/Berlin")
+                .with_initial_text(sink.get(&config).display.timezone.clone())
+                .validate_with(|timezone: &String| match LogTimezone::parse(timezone) {
+                    Some(_) => Ok(()),
+                    None => Err("This isn't a known timezone"),
+                })
+                .interact_text()
+            {
+                Ok(timezone) => timezone,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            sink.get_mut(&mut config).display.timezone = timezone.trim().to_string();
+            save_config(
+                &config,
+                &config_path,
+                &format!("the {} timezone", sink.name()),
+            );
+            display_selection(config, config_path, sink);
+        }
+        5 => {
+            match bool_select(
+                &config,
+                "[24/11/2023 23:40:43 Should milliseconds be added to the time? -> .512] [LOG]",
+            ) {
+                Some(value) => sink.get_mut(&mut config).display.milliseconds = value,
+                None => {
+                    display_selection(config, config_path, sink);
+                    return;
+                }
+            }
+            save_config(
+                &config,
+                &config_path,
+                &format!("if milliseconds should be shown in the {}", sink.name()),
+            );
+            display_selection(config, config_path, sink);
+        }
+        6 => sink_selection(config, config_path, sink),
+        7 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn rotation_selection(mut config: Config, config_path: PathBuf) {
+    let options = &[
+        "Maximum log file size",
+        "Maximum log file age",
+        "Compress rotated files",
+        "Sessions to keep",
+        "Days to keep",
+        "Maximum history size",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            let kilobytes = limit_input(
+                &config,
+                "The size in kilobytes after which the log file is rotated (0 to never rotate by size)",
+            );
+            config.log_config.rotation.max_file_size_kb = kilobytes;
+            save_config(&config, &config_path, "the maximum log file size");
+            rotation_selection(config, config_path);
+        }
+        1 => {
+            let hours = limit_input(
+                &config,
+                "The age in hours after which the log file is rotated (0 to never rotate by age)",
+            );
+            config.log_config.rotation.max_file_age_hours = hours;
+            save_config(&config, &config_path, "the maximum log file age");
+            rotation_selection(config, config_path);
+        }
+        2 => {
+            match bool_select(&config, "Should rotated log files be compressed with gzip?") {
+                Some(value) => config.log_config.rotation.compress = value,
+                None => {
+                    rotation_selection(config, config_path);
                     return;
                 }
             }
             save_config(
                 &config,
                 &config_path,
-                "if errors should be displayed to the console",
+                "if rotated logs should be compressed",
+            );
+            rotation_selection(config, config_path);
+        }
+        3 => {
+            let sessions = limit_input(
+                &config,
+                "The number of most recent sessions whose logs are kept (0 to keep all)",
+            );
+            config.log_config.rotation.keep_sessions = sessions.map(|sessions| sessions as u32);
+            save_config(&config, &config_path, "the number of sessions to keep");
+            rotation_selection(config, config_path);
+        }
+        4 => {
+            let days = limit_input(
+                &config,
+                "The number of days log files are kept for (0 to keep them forever)",
+            );
+            config.log_config.rotation.keep_days = days.map(|days| days as u32);
+            save_config(&config, &config_path, "the number of days to keep logs for");
+            rotation_selection(config, config_path);
+        }
+        5 => {
+            let kilobytes = limit_input(
+                &config,
+                "The size in kilobytes after which the oldest half of the history is dropped (0 to keep it all)",
             );
-            show_selection(config, config_path);
+            config.log_config.rotation.max_history_size_kb = kilobytes;
+            save_config(&config, &config_path, "the maximum history size");
+            rotation_selection(config, config_path);
+        }
+        6 => log_config_selection(config, config_path),
+        7 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn outputs_selection(mut config: Config, config_path: PathBuf) {
+    let options = &["Log file", "Syslog", "Journald", "Back", "Exit"];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            match bool_select(&config, "Should lines be written to the session log files?") {
+                Some(value) => config.log_config.outputs.file = value,
+                None => {
+                    outputs_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if logs should be written to files");
+            outputs_selection(config, config_path);
+        }
+        1 => {
+            match bool_select(
+                &config,
+                "Should lines be sent to syslog? (/dev/log, RFC 5424)",
+            ) {
+                Some(value) => config.log_config.outputs.syslog = value,
+                None => {
+                    outputs_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if logs should be sent to syslog");
+            outputs_selection(config, config_path);
+        }
+        2 => {
+            match bool_select(&config, "Should lines be sent to the systemd journal?") {
+                Some(value) => config.log_config.outputs.journald = value,
+                None => {
+                    outputs_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if logs should be sent to journald");
+            outputs_selection(config, config_path);
         }
         3 => log_config_selection(config, config_path),
         4 => process::exit(0),
         _ => out_of_bounds_selection(&config),
     }
 }
+fn log_format_select(config: &Config, prompt: &str) -> Option<LogFormat> {
+    let options = &[
+        "Text",
+        "Json (one object per line, for log shippers)",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .with_prompt(prompt)
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(config, Box::new(err)),
+    };
+    match index {
+        0 => Some(LogFormat::Text),
+        1 => Some(LogFormat::Json),
+        2 => None,
+        3 => process::exit(0),
+        _ => out_of_bounds_selection(config),
+    }
+}
+//Asks for a number where 0 means there is no limit
+fn limit_input(config: &Config, prompt: &str) -> Option<u64> {
+    let value: u32 = match Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact_text()
+    {
+        Ok(number) => number,
+        Err(err) => selection_fail(config, Box::new(err)),
+    };
+    match value {
+        0 => None,
+        value => Some(value as u64),
+    }
+}
 fn dns_config_selection(mut config: Config, config_path: PathBuf) {
     update_dns_list(&mut config, &config_path);
     let ((multiselected, ids), defaults) = create_selection_list(&config.dns_config);
@@ -404,51 +850,1244 @@ fn dns_config_selection(mut config: Config, config_path: PathBuf) {
     save_config(&config, &config_path, "the DNS records list");
     main_selection(config, config_path);
 }
-fn bool_select(config: &Config, prompt: &str) -> Option<bool> {
-    let options = &["True", "False", "Back", "Exit"];
+fn notifications_selection(config: Config, config_path: PathBuf) {
+    let options = &["Webhooks", "Email", "Chat", "Quiet hours", "Back", "Exit"];
     let index = match Select::with_theme(&ColorfulTheme::default())
         .items(&options[..])
-        .with_prompt(prompt)
         .interact()
     {
         Ok(list) => list,
         Err(err) => selection_fail(&config, Box::new(err)),
     };
-    let value: Option<bool>;
     match index {
-        0 => value = Some(true),
-        1 => value = Some(false),
-        2 => {
-            return None;
-        }
-        3 => process::exit(0),
+        0 => webhooks_selection(config, config_path),
+        1 => email_selection(config, config_path),
+        2 => chats_selection(config, config_path),
+        3 => quiet_hours_selection(config, config_path),
+        4 => main_selection(config, config_path),
+        5 => process::exit(0),
         _ => out_of_bounds_selection(&config),
-    };
-    value
+    }
 }
-fn save_config(config: &Config, config_path: &PathBuf, name: &str) {
-    match config.save_to_json(&config_path) {
-        Ok(()) => log_to_file_and_console(
-            &format!("Successfully saved {name} to the config file!"),
-            LogType::Log,
-            &config.log_config,
-        ),
-        Err(()) => {
-            log_to_file_and_console("Failed to save config", LogType::Error, &config.log_config);
-            panic!("Failed to save config");
+fn webhooks_selection(mut config: Config, config_path: PathBuf) {
+    let webhooks = &config.notifications.webhooks;
+    let mut options: Vec<String> = webhooks
+        .iter()
+        .map(|webhook| format!("{} {}", webhook.method, webhook.url))
+        .collect();
+    options.push("Add a webhook".to_string());
+    options.push("Back".to_string());
+    options.push("Exit".to_string());
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let webhook_count = webhooks.len();
+    if index < webhook_count {
+        webhook_selection(config, config_path, index);
+        return;
+    }
+    match index - webhook_count {
+        0 => {
+            let url = webhook_url_input(&config, "");
+            config.notifications.webhooks.push(WebhookConfig::new(url));
+            save_config(&config, &config_path, "the new webhook");
+            webhook_selection(config, config_path, webhook_count);
         }
+        1 => notifications_selection(config, config_path),
+        2 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
     }
 }
-fn out_of_bounds_selection(config: &Config) -> ! {
-    log_to_file_and_console(
-        "Selection index was out of bounds. Unable to proceed",
-        LogType::Error,
-        &config.log_config,
-    );
-    process::exit(0)
-}
-fn selection_fail(config: &Config, err: Box<dyn Error>) -> ! {
-    {
+fn webhook_selection(mut config: Config, config_path: PathBuf, index: usize) {
+    let options = &[
+        "URL",
+        "Method",
+        "Headers",
+        "Body template",
+        "Events",
+        "Retries",
+        "Send a test notification",
+        "Remove",
+        "Back",
+        "Exit",
+    ];
+    let selection = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(&config.notifications.webhooks[index].url)
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match selection {
+        0 => {
+            let url = webhook_url_input(&config, &config.notifications.webhooks[index].url);
+            config.notifications.webhooks[index].url = url;
+            save_config(&config, &config_path, "the webhook url");
+        }
+        1 => {
+            let methods = &["POST", "PUT", "PATCH", "GET", "Back", "Exit"];
+            let method = match Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("The HTTP method of the request")
+                .items(&methods[..])
+                .interact()
+            {
+                Ok(list) => list,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            match method {
+                0..=3 => {
+                    config.notifications.webhooks[index].method = methods[method].to_string();
+                    save_config(&config, &config_path, "the webhook method");
+                }
+                4 => {}
+                5 => process::exit(0),
+                _ => out_of_bounds_selection(&config),
+            }
+        }
+        2 => {
+            webhook_headers_selection(config, config_path, index);
+            return;
+        }
+        3 => {
+            println!("Placeholders: {{event}}, {{message}}, {{old_ip}}, {{new_ip}}, {{records}}, {{error}} and {{timestamp}}. Leave empty to send the default json body.");
+            let current = config.notifications.webhooks[index]
+                .body
+                .clone()
+                .unwrap_or_default();
+            let body: String = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The body template")
+                .with_initial_text(current)
+                .allow_empty(true)
+                .interact_text()
+            {
+                Ok(body) => body,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.notifications.webhooks[index].body =
+                Some(body).filter(|body| !body.trim().is_empty());
+            save_config(&config, &config_path, "the webhook body template");
+        }
+        4 => {
+            config.notifications.webhooks[index].events = events_select(
+                &config,
+                "Select which events are sent to this webhook",
+                &config.notifications.webhooks[index].events,
+            );
+            save_config(&config, &config_path, "the webhook events");
+        }
+        5 => {
+            let retries: u32 = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("How many times a failed request is retried")
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.notifications.webhooks[index].retries = retries;
+            save_config(&config, &config_path, "the webhook retries");
+        }
+        6 => {
+            //A failure was already logged with the reason
+            if let Ok(()) = notifications::send_webhook(
+                &config.notifications.webhooks[index],
+                &Notification::test(get_synced_record_names(&config)),
+                &config.log_config,
+            ) {
+                log_to_file_and_console(
+                    "The test notification was sent",
+                    LogType::Log,
+                    &config.log_config,
+                );
+            }
+        }
+        7 => {
+            config.notifications.webhooks.remove(index);
+            save_config(&config, &config_path, "the removal of the webhook");
+            webhooks_selection(config, config_path);
+            return;
+        }
+        8 => {
+            webhooks_selection(config, config_path);
+            return;
+        }
+        9 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    webhook_selection(config, config_path, index);
+}
+fn webhook_headers_selection(mut config: Config, config_path: PathBuf, index: usize) {
+    let headers = &config.notifications.webhooks[index].headers;
+    let mut options: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| format!("Remove {name}: {value}"))
+        .collect();
+    options.push("Add a header".to_string());
+    options.push("Back".to_string());
+    options.push("Exit".to_string());
+    let selection = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let header_count = headers.len();
+    if selection < header_count {
+        let name = match headers.keys().nth(selection) {
+            Some(name) => name.clone(),
+            None => out_of_bounds_selection(&config),
+        };
+        config.notifications.webhooks[index].headers.remove(&name);
+        save_config(&config, &config_path, "the removal of the header");
+        webhook_headers_selection(config, config_path, index);
+        return;
+    }
+    match selection - header_count {
+        0 => {
+            let header: String = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The header, as Name: value")
+                .validate_with(|input: &String| -> Result<(), &str> {
+                    match input.split_once(':') {
+                        Some((name, _)) if !name.trim().is_empty() => Ok(()),
+                        _ => Err("Write the header as Name: value"),
+                    }
+                })
+                .interact_text()
+            {
+                Ok(header) => header,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            if let Some((name, value)) = header.split_once(':') {
+                config.notifications.webhooks[index]
+                    .headers
+                    .insert(name.trim().to_string(), value.trim().to_string());
+            }
+            save_config(&config, &config_path, "the header");
+            webhook_headers_selection(config, config_path, index);
+        }
+        1 => webhook_selection(config, config_path, index),
+        2 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn email_selection(mut config: Config, config_path: PathBuf) {
+    let mut email = match config.notifications.email.clone() {
+        Some(email) => email,
+        None => {
+            let host = text_input(&config, "The host of the SMTP server", "");
+            let from = text_input(
+                &config,
+                "The sender, like cf_dns_sync <dns@example.com>",
+                "",
+            );
+            let to = text_input(&config, "The recipients, separated by commas", "");
+            config.notifications.email = Some(EmailConfig::new(host, from, split_addresses(&to)));
+            save_config(&config, &config_path, "the email settings");
+            email_selection(config, config_path);
+            return;
+        }
+    };
+    let options = &[
+        "Host",
+        "Port",
+        "Security",
+        "Username",
+        "Password",
+        "Sender",
+        "Recipients",
+        "Events",
+        "Minutes of failures before mailing",
+        "Maximum emails per hour",
+        "Send a test email",
+        "Remove",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{}:{}", email.host, email.get_port()))
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => email.host = text_input(&config, "The host of the SMTP server", &email.host),
+        1 => {
+            let port: u16 = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!(
+                    "The port of the SMTP server (0 for the usual port of {})",
+                    email.security.default_port()
+                ))
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            email.port = Some(port).filter(|port| *port > 0);
+        }
+        2 => {
+            let securities = &[
+                "STARTTLS (usually port 587)",
+                "TLS (usually port 465)",
+                "None (only for relays on the local network)",
+                "Back",
+                "Exit",
+            ];
+            let security = match Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("How the connection to the SMTP server is encrypted")
+                .items(&securities[..])
+                .interact()
+            {
+                Ok(list) => list,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            match security {
+                0 => email.security = SmtpSecurity::StartTls,
+                1 => email.security = SmtpSecurity::Tls,
+                2 => email.security = SmtpSecurity::None,
+                3 => {
+                    email_selection(config, config_path);
+                    return;
+                }
+                4 => process::exit(0),
+                _ => out_of_bounds_selection(&config),
+            }
+        }
+        3 => {
+            let username = text_input(
+                &config,
+                "The SMTP username (empty if the server doesn't need authentication)",
+                email.username.as_deref().unwrap_or(""),
+            );
+            email.username = Some(username).filter(|username| !username.is_empty());
+        }
+        4 => {
+            let password = match Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("The SMTP password")
+                .allow_empty_password(true)
+                .interact()
+            {
+                Ok(password) => password,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            email.password = Some(password).filter(|password| !password.is_empty());
+        }
+        5 => {
+            email.from = text_input(
+                &config,
+                "The sender, like cf_dns_sync <dns@example.com>",
+                &email.from,
+            )
+        }
+        6 => {
+            let to = text_input(
+                &config,
+                "The recipients, separated by commas",
+                &email.to.join(", "),
+            );
+            email.to = split_addresses(&to);
+        }
+        7 => email.events = events_select(&config, "Select which events are mailed", &email.events),
+        8 => {
+            let minutes: u32 = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("How many minutes syncing has to fail before an email is sent")
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            email.failure_minutes = minutes;
+        }
+        9 => {
+            let max_per_hour: u32 = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The maximum number of emails sent per hour")
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            email.max_per_hour = max_per_hour;
+        }
+        10 => {
+            //A failure was already logged with the reason
+            if let Ok(()) = email::send_email(
+                &email,
+                &Notification::test(get_synced_record_names(&config)),
+                &config.log_config,
+            ) {
+                log_to_file_and_console(
+                    "The test email was sent",
+                    LogType::Log,
+                    &config.log_config,
+                );
+            }
+            email_selection(config, config_path);
+            return;
+        }
+        11 => {
+            config.notifications.email = None;
+            save_config(&config, &config_path, "the removal of the email settings");
+            notifications_selection(config, config_path);
+            return;
+        }
+        12 => {
+            notifications_selection(config, config_path);
+            return;
+        }
+        13 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    config.notifications.email = Some(email);
+    save_config(&config, &config_path, "the email settings");
+    email_selection(config, config_path);
+}
+fn chats_selection(mut config: Config, config_path: PathBuf) {
+    let chats = &config.notifications.chat;
+    let mut options: Vec<String> = chats
+        .iter()
+        .map(|chat| {
+            format!(
+                "{} {}",
+                chat.service.name(),
+                notifications::get_host(&chat.url)
+            )
+        })
+        .collect();
+    options.push("Add a chat".to_string());
+    options.push("Back".to_string());
+    options.push("Exit".to_string());
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let chat_count = chats.len();
+    if index < chat_count {
+        chat_selection(config, config_path, index);
+        return;
+    }
+    match index - chat_count {
+        0 => {
+            let mut services: Vec<&str> =
+                CHAT_SERVICES.iter().map(|service| service.name()).collect();
+            services.push("Back");
+            services.push("Exit");
+            let selection = match Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Which service should messages be sent to?")
+                .items(&services[..])
+                .interact()
+            {
+                Ok(list) => list,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            let service = match CHAT_SERVICES.get(selection) {
+                Some(service) => *service,
+                None if selection == CHAT_SERVICES.len() => {
+                    chats_selection(config, config_path);
+                    return;
+                }
+                None => process::exit(0),
+            };
+            let mut chat = ChatConfig::new(service, text_input(&config, service.url_prompt(), ""));
+            if service == ChatService::Matrix {
+                chat.room_id = Some(text_input(
+                    &config,
+                    "The room id, like !abcdefg:matrix.org",
+                    "",
+                ));
+                chat.token = Some(text_input(
+                    &config,
+                    "The access token of the account sending the messages",
+                    "",
+                ));
+            }
+            config.notifications.chat.push(chat);
+            save_config(&config, &config_path, "the new chat");
+            chat_selection(config, config_path, chat_count);
+        }
+        1 => notifications_selection(config, config_path),
+        2 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn chat_selection(mut config: Config, config_path: PathBuf, index: usize) {
+    let service = config.notifications.chat[index].service;
+    //Only matrix uses a room id
+    let mut options = vec!["URL"];
+    if service == ChatService::Matrix {
+        options.push("Room id");
+    }
+    if service == ChatService::Matrix || service == ChatService::Ntfy {
+        options.push("Access token");
+    }
+    options.extend(["Events", "Send a test message", "Remove", "Back", "Exit"]);
+    let selection = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "{} {}",
+            service.name(),
+            notifications::get_host(&config.notifications.chat[index].url)
+        ))
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let mut chat = config.notifications.chat[index].clone();
+    match options.get(selection).copied() {
+        Some("URL") => chat.url = text_input(&config, service.url_prompt(), &chat.url),
+        Some("Room id") => {
+            let room_id = text_input(
+                &config,
+                "The room id, like !abcdefg:matrix.org",
+                chat.room_id.as_deref().unwrap_or(""),
+            );
+            chat.room_id = Some(room_id).filter(|room_id| !room_id.is_empty());
+        }
+        Some("Access token") => {
+            let token = match Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("The access token (empty for none)")
+                .allow_empty_password(true)
+                .interact()
+            {
+                Ok(token) => token,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            chat.token = Some(token).filter(|token| !token.is_empty());
+        }
+        Some("Events") => {
+            chat.events = events_select(
+                &config,
+                "Select which events are sent to this chat",
+                &chat.events,
+            )
+        }
+        Some("Send a test message") => {
+            //A failure was already logged with the reason
+            if let Ok(()) = chat::send_chat(
+                &chat,
+                &Notification::test(get_synced_record_names(&config)),
+                &config.log_config,
+            ) {
+                log_to_file_and_console(
+                    "The test message was sent",
+                    LogType::Log,
+                    &config.log_config,
+                );
+            }
+            chat_selection(config, config_path, index);
+            return;
+        }
+        Some("Remove") => {
+            config.notifications.chat.remove(index);
+            save_config(&config, &config_path, "the removal of the chat");
+            chats_selection(config, config_path);
+            return;
+        }
+        Some("Back") => {
+            chats_selection(config, config_path);
+            return;
+        }
+        Some("Exit") => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    config.notifications.chat[index] = chat;
+    save_config(&config, &config_path, "the chat settings");
+    chat_selection(config, config_path, index);
+}
+fn quiet_hours_selection(mut config: Config, config_path: PathBuf) {
+    let options = &["Set quiet hours", "Turn quiet hours off", "Back", "Exit"];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(match &config.notifications.quiet_hours {
+            Some(quiet_hours) => format!(
+                "Chat messages and emails are held from {} to {} ({})",
+                quiet_hours.start, quiet_hours.end, quiet_hours.timezone
+            ),
+            None => "There are no quiet hours".to_string(),
+        })
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            let start = time_input(&config, "When the quiet hours start, like 22:00");
+            let end = time_input(&config, "When the quiet hours end, like 07:00");
+            let timezone: String = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt(
+                    "The timezone of these times (local, utc or a name like Europe/Berlin)",
+                )
+                .with_initial_text("local")
+                .validate_with(|timezone: &String| match LogTimezone::parse(timezone) {
+                    Some(_) => Ok(()),
+                    None => Err("This isn't a known timezone"),
+                })
+                .interact_text()
+            {
+                Ok(timezone) => timezone,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.notifications.quiet_hours = Some(QuietHoursConfig {
+                start,
+                end,
+                timezone,
+            });
+            save_config(&config, &config_path, "the quiet hours");
+            quiet_hours_selection(config, config_path);
+        }
+        1 => {
+            config.notifications.quiet_hours = None;
+            save_config(&config, &config_path, "the removal of the quiet hours");
+            quiet_hours_selection(config, config_path);
+        }
+        2 => notifications_selection(config, config_path),
+        3 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn time_input(config: &Config, prompt: &str) -> String {
+    match Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .validate_with(|time: &String| match QuietHoursConfig::parse_time(time) {
+            Some(_) => Ok(()),
+            None => Err("Write the time as hours and minutes, like 22:00"),
+        })
+        .interact_text()
+    {
+        Ok(time) => time.trim().to_string(),
+        Err(err) => selection_fail(config, Box::new(err)),
+    }
+}
+fn hooks_selection(mut config: Config, config_path: PathBuf) {
+    let mut options: Vec<String> = vec![];
+    for hook in config.hooks.pre_update.iter() {
+        options.push(format!("Before updating: {}", hook.command));
+    }
+    for hook in config.hooks.post_update.iter() {
+        options.push(format!("After updating: {}", hook.command));
+    }
+    options.push("Add a hook that runs before updating".to_string());
+    options.push("Add a hook that runs after updating".to_string());
+    options.push("Back".to_string());
+    options.push("Exit".to_string());
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Commands run when a record is set to a new ip address")
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let pre_update_count = config.hooks.pre_update.len();
+    let hook_count = pre_update_count + config.hooks.post_update.len();
+    if index < pre_update_count {
+        hook_selection(config, config_path, HookKind::PreUpdate, index);
+        return;
+    }
+    if index < hook_count {
+        hook_selection(
+            config,
+            config_path,
+            HookKind::PostUpdate,
+            index - pre_update_count,
+        );
+        return;
+    }
+    match index - hook_count {
+        0 | 1 => {
+            let kind = match index - hook_count {
+                0 => HookKind::PreUpdate,
+                _ => HookKind::PostUpdate,
+            };
+            println!("The command gets OLD_IP, NEW_IP, RECORD_NAME and RECORD_ID, and hooks run after updating also get RESULT (success or failure).");
+            let command = hook_command_input(&config, "");
+            let hooks = get_hooks(&mut config, kind);
+            hooks.push(HookConfig::new(command));
+            let index = hooks.len() - 1;
+            save_config(&config, &config_path, "the new hook");
+            hook_selection(config, config_path, kind, index);
+        }
+        2 => main_selection(config, config_path),
+        3 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn hook_selection(mut config: Config, config_path: PathBuf, kind: HookKind, index: usize) {
+    //Only hooks that run before updating can keep a record from being updated
+    let mut options = vec!["Command", "Timeout"];
+    if kind == HookKind::PreUpdate {
+        options.push("Cancel the update when it fails");
+    }
+    options.extend(["Remove", "Back", "Exit"]);
+    let selection = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(&get_hooks(&mut config, kind)[index].command)
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let mut hook = get_hooks(&mut config, kind)[index].clone();
+    match options.get(selection).copied() {
+        Some("Command") => hook.command = hook_command_input(&config, &hook.command),
+        Some("Timeout") => {
+            hook.timeout_seconds = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The number of seconds after which the command is stopped")
+                .with_initial_text(hook.timeout_seconds.to_string())
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            }
+        }
+        Some("Cancel the update when it fails") => match bool_select(
+            &config,
+            "Should the record keep its ip address when the command fails or times out?",
+        ) {
+            Some(value) => hook.veto = value,
+            None => {
+                hook_selection(config, config_path, kind, index);
+                return;
+            }
+        },
+        Some("Remove") => {
+            get_hooks(&mut config, kind).remove(index);
+            save_config(&config, &config_path, "the removal of the hook");
+            hooks_selection(config, config_path);
+            return;
+        }
+        Some("Back") => {
+            hooks_selection(config, config_path);
+            return;
+        }
+        Some("Exit") => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    get_hooks(&mut config, kind)[index] = hook;
+    save_config(&config, &config_path, "the hook");
+    hook_selection(config, config_path, kind, index);
+}
+fn get_hooks(config: &mut Config, kind: HookKind) -> &mut Vec<HookConfig> {
+    match kind {
+        HookKind::PreUpdate => &mut config.hooks.pre_update,
+        HookKind::PostUpdate => &mut config.hooks.post_update,
+    }
+}
+fn hook_command_input(config: &Config, current: &str) -> String {
+    match Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("The command")
+        .with_initial_text(current)
+        .interact_text()
+    {
+        Ok(command) => command,
+        Err(err) => selection_fail(config, Box::new(err)),
+    }
+}
+fn monitoring_selection(config: Config, config_path: PathBuf) {
+    let options = &[
+        "Metrics",
+        "Health checks",
+        "Control api",
+        "Web dashboard",
+        "MQTT",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => metrics_selection(config, config_path),
+        1 => health_selection(config, config_path),
+        2 => control_selection(config, config_path),
+        3 => dashboard_selection(config, config_path),
+        4 => mqtt_selection(config, config_path),
+        5 => main_selection(config, config_path),
+        6 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn metrics_selection(mut config: Config, config_path: PathBuf) {
+    let options = &["Serve metrics", "Listen address", "Back", "Exit"];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(match config.metrics.enabled {
+            true => format!(
+                "Metrics are served on http://{}/metrics (restart to apply changes)",
+                config.metrics.listen
+            ),
+            false => "Metrics aren't served".to_string(),
+        })
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            match bool_select(&config, "Should prometheus metrics be served over http?") {
+                Some(value) => config.metrics.enabled = value,
+                None => {
+                    metrics_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if metrics should be served");
+            metrics_selection(config, config_path);
+        }
+        1 => {
+            let listen = listen_input(&config, &config.metrics.listen);
+            config.metrics.listen = listen;
+            save_config(&config, &config_path, "the metrics address");
+            metrics_selection(config, config_path);
+        }
+        2 => monitoring_selection(config, config_path),
+        3 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+}
+fn health_selection(mut config: Config, config_path: PathBuf) {
+    let options = &[
+        "Serve health checks",
+        "Listen address",
+        "Intervals until not ready",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(match config.health.enabled {
+            true => format!(
+                "/healthz and /readyz are served on http://{} (restart to apply changes)",
+                config.health.listen
+            ),
+            false => "Health checks aren't served".to_string(),
+        })
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            match bool_select(&config, "Should /healthz and /readyz be served over http?") {
+                Some(value) => config.health.enabled = value,
+                None => {
+                    health_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if health checks should be served");
+        }
+        1 => {
+            config.health.listen = listen_input(&config, &config.health.listen);
+            save_config(&config, &config_path, "the health check address");
+        }
+        2 => {
+            let intervals: u32 = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("After how many intervals without a successful sync /readyz fails")
+                .with_initial_text(config.health.stale_after_intervals.to_string())
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.health.stale_after_intervals = intervals;
+            save_config(&config, &config_path, "the intervals until not ready");
+        }
+        3 => {
+            monitoring_selection(config, config_path);
+            return;
+        }
+        4 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    health_selection(config, config_path);
+}
+fn control_selection(mut config: Config, config_path: PathBuf) {
+    let options = &["Unix socket", "Tcp address", "Tcp token", "Back", "Exit"];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(match &config.control.listen {
+            Some(listen) => {
+                format!("The control api is also served on {listen} (restart to apply changes)")
+            }
+            None => "The control api is only served on the unix socket (restart to apply changes)"
+                .to_string(),
+        })
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            match bool_select(
+                &config,
+                "Should the status, sync-now and pause commands reach the daemon through control.sock?",
+            ) {
+                Some(value) => config.control.socket = value,
+                None => {
+                    control_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(
+                &config,
+                &config_path,
+                "if the control socket should be used",
+            );
+        }
+        1 => {
+            let listen = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The loopback address and port to serve the control api on, or nothing to turn it off")
+                .with_initial_text(config.control.listen.clone().unwrap_or_default())
+                .allow_empty(true)
+                .validate_with(|listen: &String| match control::get_loopback(listen) {
+                    _ if listen.is_empty() => Ok(()),
+                    Some(true) => Ok(()),
+                    Some(false) => Err("Only loopback addresses are allowed, like 127.0.0.1:9470"),
+                    None => Err("Write a host and port, like 127.0.0.1:9470"),
+                })
+                .interact_text()
+            {
+                Ok(listen) => listen,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.control.listen = match listen.is_empty() {
+                true => None,
+                false => Some(listen),
+            };
+            save_config(&config, &config_path, "the control api address");
+        }
+        2 => {
+            let token = match Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("The token tcp requests must send, or nothing to only answer status and records")
+                .allow_empty_password(true)
+                .interact()
+            {
+                Ok(token) => token,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.control.token = match token.is_empty() {
+                true => None,
+                false => Some(token),
+            };
+            save_config(&config, &config_path, "the control api token");
+        }
+        3 => {
+            monitoring_selection(config, config_path);
+            return;
+        }
+        4 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    control_selection(config, config_path);
+}
+fn dashboard_selection(mut config: Config, config_path: PathBuf) {
+    let options = &[
+        "Serve the dashboard",
+        "Listen address",
+        "Password",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(match config.dashboard.enabled {
+            true => format!(
+                "The dashboard is served on http://{} (restart to apply changes)",
+                config.dashboard.listen
+            ),
+            false => "The dashboard isn't served".to_string(),
+        })
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            match bool_select(&config, "Should the web dashboard be served?") {
+                Some(value) => config.dashboard.enabled = value,
+                None => {
+                    dashboard_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if the dashboard should be served");
+        }
+        1 => {
+            config.dashboard.listen = listen_input(&config, &config.dashboard.listen);
+            save_config(&config, &config_path, "the dashboard address");
+        }
+        2 => {
+            let password = match Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("The password the browser asks for")
+                .interact()
+            {
+                Ok(password) => password,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.dashboard.password = Some(password);
+            save_config(&config, &config_path, "the dashboard password");
+        }
+        3 => {
+            monitoring_selection(config, config_path);
+            return;
+        }
+        4 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    dashboard_selection(config, config_path);
+}
+fn mqtt_selection(mut config: Config, config_path: PathBuf) {
+    let options = &[
+        "Publish to MQTT",
+        "Host",
+        "Port",
+        "Username",
+        "Password",
+        "Topic prefix",
+        "Home Assistant discovery",
+        "Back",
+        "Exit",
+    ];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(match config.mqtt.enabled {
+            true => format!(
+                "Publishing to {}:{} under {}/ (restart to apply changes)",
+                config.mqtt.host, config.mqtt.port, config.mqtt.topic_prefix
+            ),
+            false => "Nothing is published to MQTT".to_string(),
+        })
+        .items(&options[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    match index {
+        0 => {
+            match bool_select(&config, "Should the ip and records be published to MQTT?") {
+                Some(value) => config.mqtt.enabled = value,
+                None => {
+                    mqtt_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if MQTT is used");
+        }
+        1 => {
+            config.mqtt.host =
+                text_input(&config, "The host of the MQTT broker", &config.mqtt.host);
+            save_config(&config, &config_path, "the MQTT host");
+        }
+        2 => {
+            config.mqtt.port = match Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("The port of the MQTT broker")
+                .with_initial_text(config.mqtt.port.to_string())
+                .interact_text()
+            {
+                Ok(number) => number,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            save_config(&config, &config_path, "the MQTT port");
+        }
+        3 => {
+            let username = text_input(
+                &config,
+                "The MQTT username (empty if the broker doesn't need authentication)",
+                config.mqtt.username.as_deref().unwrap_or(""),
+            );
+            config.mqtt.username = Some(username).filter(|username| !username.is_empty());
+            save_config(&config, &config_path, "the MQTT username");
+        }
+        4 => {
+            let password = match Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("The MQTT password")
+                .allow_empty_password(true)
+                .interact()
+            {
+                Ok(password) => password,
+                Err(err) => selection_fail(&config, Box::new(err)),
+            };
+            config.mqtt.password = Some(password).filter(|password| !password.is_empty());
+            save_config(&config, &config_path, "the MQTT password");
+        }
+        5 => {
+            config.mqtt.topic_prefix = text_input(
+                &config,
+                "The topic everything is published under",
+                &config.mqtt.topic_prefix,
+            );
+            save_config(&config, &config_path, "the MQTT topic prefix");
+        }
+        6 => {
+            match bool_select(
+                &config,
+                "Should the sensors be added to Home Assistant automatically?",
+            ) {
+                Some(value) => config.mqtt.discovery = value,
+                None => {
+                    mqtt_selection(config, config_path);
+                    return;
+                }
+            }
+            save_config(&config, &config_path, "if Home Assistant discovery is used");
+        }
+        7 => {
+            monitoring_selection(config, config_path);
+            return;
+        }
+        8 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    }
+    mqtt_selection(config, config_path);
+}
+fn listen_input(config: &Config, current: &str) -> String {
+    match Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("The address and port to serve on (0.0.0.0:9469 for every network)")
+        .with_initial_text(current)
+        .validate_with(|listen: &String| match listen.to_socket_addrs() {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Write a host and port, like 127.0.0.1:9469"),
+        })
+        .interact_text()
+    {
+        Ok(listen) => listen,
+        Err(err) => selection_fail(config, Box::new(err)),
+    }
+}
+fn split_addresses(addresses: &str) -> Vec<String> {
+    addresses
+        .split(',')
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+        .collect()
+}
+fn get_synced_record_names(config: &Config) -> Vec<String> {
+    config
+        .dns_config
+        .iter()
+        .filter(|record| record.sync == Some(true))
+        .map(|record| record.name.clone())
+        .collect()
+}
+fn events_select(
+    config: &Config,
+    prompt: &str,
+    events: &[NotificationEvent],
+) -> Vec<NotificationEvent> {
+    let names: Vec<&str> = NOTIFICATION_EVENTS
+        .iter()
+        .map(|event| event.name())
+        .collect();
+    let defaults: Vec<bool> = NOTIFICATION_EVENTS
+        .iter()
+        .map(|event| events.contains(event))
+        .collect();
+    let selections = match MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(&names[..])
+        .defaults(&defaults[..])
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(config, Box::new(err)),
+    };
+    selections
+        .into_iter()
+        .map(|selection| NOTIFICATION_EVENTS[selection])
+        .collect()
+}
+fn text_input(config: &Config, prompt: &str, current: &str) -> String {
+    match Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .with_initial_text(current)
+        .allow_empty(true)
+        .interact_text()
+    {
+        Ok(text) => text,
+        Err(err) => selection_fail(config, Box::new(err)),
+    }
+}
+fn webhook_url_input(config: &Config, current: &str) -> String {
+    match Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("The webhook url")
+        .with_initial_text(current)
+        .validate_with(|input: &String| -> Result<(), &str> {
+            if input.starts_with("http://") || input.starts_with("https://") {
+                Ok(())
+            } else {
+                Err("The url must start with http:// or https://")
+            }
+        })
+        .interact_text()
+    {
+        Ok(url) => url,
+        Err(err) => selection_fail(config, Box::new(err)),
+    }
+}
+fn bool_select(config: &Config, prompt: &str) -> Option<bool> {
+    let options = &["True", "False", "Back", "Exit"];
+    let index = match Select::with_theme(&ColorfulTheme::default())
+        .items(&options[..])
+        .with_prompt(prompt)
+        .interact()
+    {
+        Ok(list) => list,
+        Err(err) => selection_fail(&config, Box::new(err)),
+    };
+    let value: Option<bool>;
+    match index {
+        0 => value = Some(true),
+        1 => value = Some(false),
+        2 => {
+            return None;
+        }
+        3 => process::exit(0),
+        _ => out_of_bounds_selection(&config),
+    };
+    value
+}
+fn save_config(config: &Config, config_path: &Path, name: &str) {
+    match config.save_to_file(config_path) {
+        Ok(()) => log_to_file_and_console(
+            &format!("Successfully saved {name} to the config file!"),
+            LogType::Log,
+            &config.log_config,
+        ),
+        Err(()) => {
+            log_to_file_and_console("Failed to save config", LogType::Error, &config.log_config);
+            panic!("Failed to save config");
+        }
+    }
+}
+fn out_of_bounds_selection(config: &Config) -> ! {
+    log_to_file_and_console(
+        "Selection index was out of bounds. Unable to proceed",
+        LogType::Error,
+        &config.log_config,
+    );
+    process::exit(0)
+}
+fn selection_fail(config: &Config, err: Box<dyn Error>) -> ! {
+    {
         unending_process::log_to_file_and_console(
             &format!("Failed to select option{}", format_err(err)),
             LogType::Error,
@@ -484,6 +2123,519 @@ mod test {
         }
     }
     #[test]
+    fn toml_save_keeps_comments() {
+        use crate::config_file::{self, ConfigFormat};
+        let contents = "# Checked every 5 minutes\nseconds_to_wait_per_restart = 300\n\n[[dns_config]]\n# The home server\nid = \"abc\"\ncontent = \"1.1.1.1\"\n";
+        let mut value: serde_json::Value =
+            config_file::parse(contents, ConfigFormat::Toml).unwrap();
+        value["dns_config"][0]["content"] = "2.2.2.2".into();
+        let saved = config_file::serialize(&value, ConfigFormat::Toml, Some(contents)).unwrap();
+        assert!(saved.contains("# Checked every 5 minutes"));
+        assert!(saved.contains("# The home server"));
+        assert!(saved.contains("content = \"2.2.2.2\""));
+    }
+    #[test]
+    fn find_unknown_field_lines() {
+        use crate::config_file::ConfigFormat;
+        use crate::validate::find_line;
+        //color is a known key of another table, so only the full path points at the right line
+        let toml = "[log_config.file]\ncolor = true\n\n[log_config.console]\ncolor = true\n\n[[dns_config]]\nname = \"a\"\n\n[[dns_config]]\ncomment = \"b\"\n";
+        assert_eq!(
+            find_line(toml, "log_config.console.color", ConfigFormat::Toml),
+            Some(5)
+        );
+        assert_eq!(
+            find_line(toml, "dns_config.1.comment", ConfigFormat::Toml),
+            Some(11)
+        );
+        let yaml = "log_config:\n  file:\n    color: true\n  console:\n    color: true\ndns_config:\n- name: a\n  comment: b\n";
+        assert_eq!(
+            find_line(yaml, "log_config.console.color", ConfigFormat::Yaml),
+            Some(5)
+        );
+        assert_eq!(
+            find_line(yaml, "dns_config.0.comment", ConfigFormat::Yaml),
+            Some(8)
+        );
+        let json = "{\n  \"log_config\": {\n    \"file\": { \"color\": true },\n    \"console\": {\n      \"color\": true\n    }\n  },\n  \"dns_config\": [{ \"name\": \"a:b\" }, { \"comment\": \"b\" }]\n}\n";
+        assert_eq!(
+            find_line(json, "log_config.console.color", ConfigFormat::Json),
+            Some(5)
+        );
+        assert_eq!(
+            find_line(json, "dns_config.1.comment", ConfigFormat::Json),
+            Some(8)
+        );
+        assert_eq!(
+            find_line(json, "dns_config.0.color", ConfigFormat::Json),
+            None
+        );
+    }
+    #[test]
+    fn yaml_save_keeps_comments() {
+        use crate::config_file::{self, ConfigFormat};
+        let contents = "# Checked every 5 minutes\nseconds_to_wait_per_restart: 300 # seconds\n\n# The records\ndns_config:\n# The home server\n- id: abc\n  content: 1.1.1.1 # updated by the daemon\nhooks:\n  body: |\n    # not a comment\n    done\nlog_config: {a: \"# not a comment either\"}\n";
+        let mut value: serde_json::Value =
+            config_file::parse(contents, ConfigFormat::Yaml).unwrap();
+        value["dns_config"][0]["content"] = "2.2.2.2".into();
+        let saved = config_file::serialize(&value, ConfigFormat::Yaml, Some(contents)).unwrap();
+        let saved_value: serde_json::Value =
+            config_file::parse(&saved, ConfigFormat::Yaml).unwrap();
+        assert_eq!(saved_value, value);
+        assert!(
+            saved.contains("# Checked every 5 minutes\nseconds_to_wait_per_restart: 300 # seconds")
+        );
+        assert!(saved.contains("\n# The records\ndns_config:\n"));
+        //Only the comments of top-level keys are kept
+        assert!(!saved.contains("# The home server"));
+        assert!(!saved.contains("# updated by the daemon"));
+        assert_eq!(saved.matches("# not a comment").count(), 2);
+    }
+    #[test]
+    fn parse_history_dates() {
+        use crate::state::parse_date_argument;
+        let start = parse_date_argument("2023-11-24", false).unwrap();
+        let end = parse_date_argument("2023-11-24", true).unwrap();
+        assert_eq!((end - start).num_seconds(), 24 * 60 * 60 - 1);
+        assert_eq!(
+            parse_date_argument("2023-11-24T23:40:43+00:00", false)
+                .unwrap()
+                .to_rfc3339(),
+            "2023-11-24T23:40:43+00:00"
+        );
+        assert!(parse_date_argument("24/11/2023", false).is_none());
+    }
+    #[test]
+    fn history_is_pruned() {
+        let history_path =
+            std::env::temp_dir().join(format!("cf_dns_sync_history_{}.jsonl", std::process::id()));
+        let lines: Vec<String> = (0..100).map(|index| format!("line {index:03}")).collect();
+        std::fs::write(&history_path, lines.join("\n") + "\n").unwrap();
+        assert_eq!(crate::state::prune_history(&history_path, 2000).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&history_path).unwrap().len(), 900);
+        //About half of the limit is kept, made of the newest lines
+        assert_eq!(crate::state::prune_history(&history_path, 800).unwrap(), 56);
+        let contents = std::fs::read_to_string(&history_path).unwrap();
+        assert_eq!(contents.lines().count(), 44);
+        assert!(contents.starts_with("line 056\n"));
+        assert!(contents.ends_with("line 099\n"));
+        let _ = std::fs::remove_file(history_path);
+    }
+    #[test]
+    fn parse_rotated_session_names() {
+        use crate::log_rotation::parse_session_number;
+        assert_eq!(parse_session_number("session12.txt"), Some(12));
+        assert_eq!(parse_session_number("session12.3.txt"), Some(12));
+        assert_eq!(parse_session_number("session12.3.txt.gz"), Some(12));
+        assert_eq!(parse_session_number("session.txt"), None);
+        assert_eq!(parse_session_number("session12.log"), None);
+    }
+    #[test]
+    fn parse_verbosity() {
+        use crate::take_verbosity_flags;
+        use crate::unending_process::{parse_rust_log, Verbosity};
+        assert_eq!(parse_rust_log("debug"), Some(Verbosity::Debug));
+        assert_eq!(
+            parse_rust_log("warn,cf_dns_sync=trace"),
+            Some(Verbosity::Trace)
+        );
+        assert_eq!(parse_rust_log("cf_dns_sync=off,info"), Some(Verbosity::Off));
+        assert_eq!(parse_rust_log("ureq=debug"), None);
+        let mut args: Vec<String> = ["-vv", "history", "-v"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(take_verbosity_flags(&mut args), Some(Verbosity::Trace));
+        assert_eq!(args, vec!["history".to_string()]);
+    }
+    #[test]
+    fn migrate_show_to_sink_levels() {
+        let mut value = serde_json::json!({
+            "version": 2,
+            "log_config": {
+                "display": { "date": false },
+                "show": { "logs": false, "warnings": true, "errors": true },
+                "file_format": "json"
+            }
+        });
+        let report = crate::migrations::migrate(&mut value).unwrap().unwrap();
+        assert_eq!(report.to_version, 3);
+        let log_config = &value["log_config"];
+        assert!(log_config.get("show").is_none());
+        assert_eq!(log_config["console"]["level"], "warn");
+        assert_eq!(log_config["file"]["level"], "warn");
+        assert_eq!(log_config["file"]["format"], "json");
+        assert!(log_config["console"].get("format").is_none());
+        assert_eq!(log_config["console"]["display"]["date"], false);
+    }
+    #[test]
+    fn render_webhook_body() {
+        use crate::notifications::{
+            get_host, render_body, Notification, NotificationEvent, WebhookConfig,
+        };
+        let mut webhook = WebhookConfig::new("https://user@hooks.example.com/secret".to_string());
+        let notification = Notification::new(
+            NotificationEvent::SyncFailed,
+            "Couldn't set \"home\"".to_string(),
+        )
+        .records(vec![
+            "a.example.com".to_string(),
+            "b.example.com".to_string(),
+        ])
+        .error("first\nsecond".to_string());
+        let body: serde_json::Value =
+            serde_json::from_str(&render_body(&webhook, &notification)).unwrap();
+        assert_eq!(body["event"], "sync_failed");
+        assert_eq!(body["records"][1], "b.example.com");
+        assert!(body["old_ip"].is_null());
+        webhook.body = Some(r#"{"text": "{event}: {message} ({records}) {error}"}"#.to_string());
+        let body: serde_json::Value =
+            serde_json::from_str(&render_body(&webhook, &notification)).unwrap();
+        assert_eq!(
+            body["text"],
+            "sync_failed: Couldn't set \"home\" (a.example.com, b.example.com) first\nsecond"
+        );
+        //A placeholder inside a value is sent as it is
+        let notification = Notification::new(
+            NotificationEvent::SyncFailed,
+            "Failed with {error}".to_string(),
+        )
+        .error("timeout".to_string());
+        let body: serde_json::Value =
+            serde_json::from_str(&render_body(&webhook, &notification)).unwrap();
+        assert_eq!(body["text"], "sync_failed: Failed with {error} () timeout");
+        assert_eq!(get_host(&webhook.url), "hooks.example.com");
+    }
+    #[test]
+    fn render_chat_bodies() {
+        use crate::chat::{encode_path_segment, get_discord_body, get_ntfy_url, get_slack_body};
+        use crate::notifications::{Notification, NotificationEvent};
+        let notification = Notification::new(
+            NotificationEvent::SyncFailed,
+            "Couldn't set <home> & <work>".to_string(),
+        )
+        .error("é".repeat(2000));
+        let body = get_slack_body(&notification);
+        let text = body["blocks"][0]["text"]["text"].as_str().unwrap();
+        assert!(text.contains("Couldn't set &lt;home&gt; &amp; &lt;work&gt;"));
+        //The fallback text isn't mrkdwn, so it stays as it is
+        assert_eq!(
+            body["text"],
+            "Syncing is failing: Couldn't set <home> & <work>"
+        );
+        let body = get_discord_body(&notification);
+        let fields = body["embeds"][0]["fields"].as_array().unwrap();
+        let errors = fields
+            .iter()
+            .find(|field| field["name"] == "Errors")
+            .unwrap();
+        let length = errors["value"].as_str().unwrap().chars().count();
+        assert!(length <= 1024, "{length} characters");
+        assert!(fields.iter().all(|field| field["name"] != "Time"));
+        assert_eq!(
+            encode_path_segment("!abc-D_e.f~g:matrix.org"),
+            "%21abc-D_e.f~g%3Amatrix.org"
+        );
+        assert_eq!(encode_path_segment("é/"), "%C3%A9%2F");
+        assert_eq!(get_ntfy_url("my_topic"), "https://ntfy.sh/my_topic");
+        assert_eq!(
+            get_ntfy_url("https://ntfy.example.com/my_topic"),
+            "https://ntfy.example.com/my_topic"
+        );
+    }
+    #[test]
+    fn email_message() {
+        use crate::email::{build_message, EmailConfig};
+        use crate::notifications::Notification;
+        let mut email = EmailConfig::new(
+            "smtp.example.com".to_string(),
+            "cf_dns_sync <dns@example.com>".to_string(),
+            vec!["a@example.com".to_string(), "b@example.com".to_string()],
+        );
+        let notification = Notification::test(vec!["home.example.com".to_string()]);
+        let message = build_message(&email, &notification).unwrap();
+        let subject = message.headers().get_raw("Subject").unwrap();
+        assert!(subject.starts_with("[cf_dns_sync on "));
+        assert!(subject.ends_with("] Public ip address changed to 203.0.113.7"));
+        let recipients: Vec<String> = message
+            .envelope()
+            .to()
+            .iter()
+            .map(|address| address.to_string())
+            .collect();
+        assert_eq!(recipients, vec!["a@example.com", "b@example.com"]);
+        assert_eq!(
+            message.envelope().from().unwrap().to_string(),
+            "dns@example.com"
+        );
+        email.to.push("not an address".to_string());
+        assert!(build_message(&email, &notification)
+            .unwrap_err()
+            .starts_with("the recipient not an address is invalid"));
+        email.from = "nobody".to_string();
+        assert!(build_message(&email, &notification)
+            .unwrap_err()
+            .starts_with("the sender nobody is invalid"));
+    }
+    #[test]
+    fn email_waits_and_is_rate_limited() {
+        use crate::email::EmailConfig;
+        use crate::notifications::{
+            send_rate_limited_email, should_email, DispatchState, Notification, NotificationEvent,
+        };
+        use chrono::Duration;
+        let mut log_config = unending_process::LogConfig::default();
+        log_config.outputs.file = false;
+        //The invalid sender makes every send fail right away, without connecting to anything
+        let email = EmailConfig {
+            failure_minutes: 30,
+            max_per_hour: 2,
+            ..EmailConfig::new(
+                "smtp.example.com".to_string(),
+                "nobody".to_string(),
+                vec!["a@example.com".to_string()],
+            )
+        };
+        let mut state = DispatchState::default();
+        let failed = |minutes| {
+            let notification =
+                Notification::new(NotificationEvent::SyncFailed, "Couldn't set".to_string());
+            let failing_since = notification.time - Duration::minutes(minutes);
+            notification.failing_since(failing_since)
+        };
+        assert!(!should_email(&failed(29), &email, &state));
+        let long_failure = failed(31);
+        assert!(should_email(&long_failure, &email, &state));
+        send_rate_limited_email(&long_failure, &email, &log_config, &mut state);
+        assert_eq!(state.email_failure, long_failure.failing_since);
+        //The same run of failures is only mailed about once, and then its recovery is
+        assert!(!should_email(&long_failure, &email, &state));
+        let recovered = Notification::new(NotificationEvent::Recovered, "Recovered".to_string())
+            .failing_since(long_failure.failing_since.unwrap());
+        assert!(should_email(&recovered, &email, &state));
+        send_rate_limited_email(&recovered, &email, &log_config, &mut state);
+        assert_eq!(state.email_failure, None);
+        assert_eq!(state.email_times.len(), 2);
+        //max_per_hour is reached, so the next one isn't sent until an hour after the first
+        let ip_changed = Notification::test(vec![]);
+        send_rate_limited_email(&ip_changed, &email, &log_config, &mut state);
+        assert_eq!(state.email_times.len(), 2);
+        let mut later = Notification::test(vec![]);
+        later.time += Duration::minutes(61);
+        send_rate_limited_email(&later, &email, &log_config, &mut state);
+        assert_eq!(state.email_times.len(), 1);
+    }
+    #[test]
+    fn quiet_hours_past_midnight() {
+        use crate::notifications::QuietHoursConfig;
+        use chrono::TimeZone;
+        let quiet_hours = QuietHoursConfig {
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+            timezone: "Europe/Berlin".to_string(),
+        };
+        //Berlin is UTC+2 in summer
+        let at = |hour| {
+            chrono::Utc
+                .with_ymd_and_hms(2024, 7, 1, hour, 30, 0)
+                .unwrap()
+        };
+        assert!(quiet_hours.is_quiet(at(20)));
+        assert!(quiet_hours.is_quiet(at(4)));
+        assert!(!quiet_hours.is_quiet(at(5)));
+        assert!(!quiet_hours.is_quiet(at(12)));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn pre_update_hooks_veto() {
+        use crate::hooks::{run_pre_update_hooks, HookConfig, HooksConfig};
+        let mut log_config = unending_process::LogConfig::default();
+        log_config.outputs.file = false;
+        let record = unending_process::DNSRecord {
+            record_type: "A".to_string(),
+            name: "home.example.com".to_string(),
+            content: "203.0.113.5".to_string(),
+            proxy_status: Some(false),
+            ttl: 1,
+            id: "372e67954025e0ba6aaa6d586b9e0b59".to_string(),
+            sync: Some(true),
+        };
+        let run = |hook: HookConfig| {
+            let hooks = HooksConfig {
+                pre_update: vec![hook],
+                post_update: vec![],
+            };
+            run_pre_update_hooks(&hooks, &record, "203.0.113.7", &log_config)
+        };
+        let passes = HookConfig::new(
+            r#"test "$OLD_IP $NEW_IP $RECORD_NAME" = "203.0.113.5 203.0.113.7 home.example.com""#
+                .to_string(),
+        );
+        assert!(run(passes).is_ok());
+        let fails = HookConfig::new("exit 3".to_string());
+        assert!(run(fails.clone()).unwrap_err().ends_with("exited with 3"));
+        assert!(run(HookConfig {
+            veto: false,
+            ..fails
+        })
+        .is_ok());
+        let slow = HookConfig {
+            timeout_seconds: 1,
+            ..HookConfig::new("sleep 5".to_string())
+        };
+        assert!(run(slow)
+            .unwrap_err()
+            .ends_with("timed out after 1 seconds"));
+    }
+    #[test]
+    fn health_readiness() {
+        use crate::health::{get_readiness_at, is_credential_error, Health, HealthConfig};
+        use chrono::{Duration, TimeZone};
+        let now = chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        //Three intervals of 5 minutes and a minute of slack
+        let health_config = HealthConfig::default();
+        let mut health = Health {
+            started_at: Some(now - Duration::hours(1)),
+            interval_seconds: 300,
+            ..Default::default()
+        };
+        let (ready, body) = get_readiness_at(&health, &health_config, now);
+        assert!(!ready);
+        assert_eq!(body["reason"], "no sync has succeeded yet");
+        health.last_success = Some(now - Duration::seconds(960));
+        let (ready, body) = get_readiness_at(&health, &health_config, now);
+        assert!(ready);
+        assert_eq!(body["status"], "ready");
+        assert!(body["reason"].is_null());
+        health.last_success = Some(now - Duration::seconds(961));
+        let (ready, body) = get_readiness_at(&health, &health_config, now);
+        assert!(!ready);
+        assert_eq!(
+            body["reason"],
+            "the last successful sync was more than 960 seconds ago"
+        );
+        //Rejected credentials are reported even right after a successful sync
+        health.last_success = Some(now);
+        health.credentials_valid = Some(false);
+        let (ready, body) = get_readiness_at(&health, &health_config, now);
+        assert!(!ready);
+        assert_eq!(body["reason"], "cloudflare rejected the credentials");
+        assert_eq!(body["credentials_valid"], false);
+        assert!(is_credential_error(Some(403), &[]));
+        assert!(is_credential_error(Some(400), &["9103".to_string()]));
+        assert!(!is_credential_error(Some(500), &[]));
+        assert!(!is_credential_error(None, &["1004".to_string()]));
+    }
+    #[test]
+    fn render_metrics() {
+        let mut metrics = crate::metrics::Metrics::default();
+        metrics.count_sync_cycle(true);
+        metrics.count_sync_cycle(false);
+        metrics.count_record_update("home.example.com", "success");
+        metrics.count_api_error("9103");
+        metrics.set_ip("203.0.113.7", true);
+        metrics.observe_api_request("update_record", std::time::Duration::from_millis(300));
+        let text = metrics.render();
+        assert!(text.contains("cf_dns_sync_sync_cycles_total{result=\"failure\"} 1\n"));
+        assert!(text.contains(
+            "cf_dns_sync_record_updates_total{record=\"home.example.com\",result=\"success\"} 1\n"
+        ));
+        assert!(text.contains("cf_dns_sync_api_errors_total{code=\"9103\"} 1\n"));
+        assert!(text.contains("cf_dns_sync_ip_info{ip=\"203.0.113.7\",version=\"4\"} 1\n"));
+        assert!(text
+            .contains("cf_dns_sync_last_success_timestamp_seconds{record=\"home.example.com\"}"));
+        //The latency falls into the 0.5 second bucket and every one after it
+        assert!(text.contains(
+            "cf_dns_sync_api_request_duration_seconds_bucket{operation=\"update_record\",le=\"0.25\"} 0\n"
+        ));
+        assert!(text.contains(
+            "cf_dns_sync_api_request_duration_seconds_bucket{operation=\"update_record\",le=\"0.5\"} 1\n"
+        ));
+        assert!(text.contains(
+            "cf_dns_sync_api_request_duration_seconds_bucket{operation=\"update_record\",le=\"+Inf\"} 1\n"
+        ));
+    }
+    #[test]
+    fn control_commands_wake_the_wait() {
+        use crate::control::{self, Wake};
+        use serde_json::json;
+        //Started a second ago, so the interval of 1 second set below has already passed
+        let start = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        let response = control::handle_request(&json!({ "command": "set_interval", "seconds": 0 }));
+        assert_eq!(response["ok"], false);
+        control::handle_request(&json!({ "command": "pause" }));
+        control::handle_request(&json!({ "command": "reload" }));
+        assert!(matches!(control::wait(start, 3600), Wake::Reload));
+        //A sync that is asked for runs even while paused
+        control::handle_request(&json!({ "command": "sync_now" }));
+        assert!(matches!(control::wait(start, 3600), Wake::Sync));
+        control::handle_request(&json!({ "command": "resume" }));
+        control::handle_request(&json!({ "command": "set_interval", "seconds": 1 }));
+        assert!(matches!(control::wait(start, 3600), Wake::Sync));
+        let response = control::handle_request(&json!({ "command": "status" }));
+        assert_eq!(response["interval_seconds"], 1);
+        assert_eq!(response["paused"], false);
+        //The override is global, so it's cleared for the other tests
+        control::clear_interval_override();
+        let response = control::handle_request(&json!({ "command": "status" }));
+        assert_eq!(response["interval_overridden"], false);
+    }
+    #[test]
+    fn dashboard_log_tail() {
+        let folder_path =
+            std::env::temp_dir().join(format!("cf_dns_sync_tail_{}", std::process::id()));
+        std::fs::create_dir_all(folder_path.join("logs")).unwrap();
+        std::fs::write(
+            folder_path.join("logs").join("session7.txt"),
+            "one\ntwo\nthree\n",
+        )
+        .unwrap();
+        let mut log_config = unending_process::LogConfig {
+            log_folder_path: folder_path.to_string_lossy().into_owned(),
+            session_number: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(
+            crate::logs::tail_session(&log_config, 2),
+            vec!["two", "three"]
+        );
+        assert_eq!(crate::logs::tail_session(&log_config, 10).len(), 3);
+        log_config.session_number = Some(8);
+        assert!(crate::logs::tail_session(&log_config, 2).is_empty());
+        //Longer than one block, so it is read from the end in several parts
+        let lines: Vec<String> = (0..5000).map(|index| format!("line {index:04}")).collect();
+        let history_path = folder_path.join("history.jsonl");
+        std::fs::write(&history_path, lines.join("\n") + "\n").unwrap();
+        assert_eq!(
+            crate::logs::read_last_lines(&history_path, 2000),
+            lines[3000..]
+        );
+        assert_eq!(
+            crate::logs::read_last_lines(&history_path, 9000).len(),
+            5000
+        );
+        let _ = std::fs::remove_dir_all(folder_path);
+    }
+    #[test]
+    fn mqtt_payloads() {
+        use chrono::TimeZone;
+        assert_eq!(crate::mqtt::get_node_id("cf dns.sync-1"), "cf_dns_sync-1");
+        let record_state = crate::state::RecordState {
+            last_failure: Some(chrono::Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()),
+            last_error: Some("Cloudflare answered 9103".to_string()),
+            ..Default::default()
+        };
+        let payload =
+            crate::mqtt::get_record_payload("203.0.113.7", "failure", Some(&record_state));
+        assert_eq!(payload["result"], "failure");
+        assert_eq!(payload["content"], "203.0.113.7");
+        assert_eq!(payload["last_failure"], "2024-05-01T12:00:00Z");
+        assert_eq!(payload["last_error"], "Cloudflare answered 9103");
+        assert!(payload["last_success"].is_null());
+        let payload = crate::mqtt::get_record_payload("203.0.113.7", "vetoed", None);
+        assert!(payload["last_error"].is_null());
+    }
+    #[test]
     fn process_test() {
         unending_process::process();
     }
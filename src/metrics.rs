@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::state::State;
+
+//In seconds, from a quick answer to cloudflare being slow
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    //Only reachable from the same machine by default, use 0.0.0.0:9469 for prometheus on another one
+    #[serde(default = "default_listen")]
+    pub listen: String,
+}
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            listen: default_listen(),
+        }
+    }
+}
+fn default_listen() -> String {
+    "127.0.0.1:9469".to_string()
+}
+#[derive(Default)]
+pub struct Metrics {
+    sync_cycles: BTreeMap<&'static str, u64>,
+    //Keyed by record name and result
+    record_updates: BTreeMap<(String, &'static str), u64>,
+    api_errors: BTreeMap<String, u64>,
+    ip_changes: u64,
+    last_success: BTreeMap<String, DateTime<Utc>>,
+    ip: Option<String>,
+    api_latency: BTreeMap<&'static str, Histogram>,
+}
+#[derive(Default)]
+struct Histogram {
+    //Not cumulative, one count per bucket of LATENCY_BUCKETS
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+impl Metrics {
+    pub fn count_sync_cycle(&mut self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        *self.sync_cycles.entry(result).or_default() += 1;
+    }
+    pub fn count_record_update(&mut self, record_name: &str, result: &'static str) {
+        *self
+            .record_updates
+            .entry((record_name.to_string(), result))
+            .or_default() += 1;
+        if result == "success" {
+            self.last_success
+                .insert(record_name.to_string(), Utc::now());
+        }
+    }
+    pub fn count_api_error(&mut self, code: &str) {
+        *self.api_errors.entry(code.to_string()).or_default() += 1;
+    }
+    pub fn observe_api_request(&mut self, operation: &'static str, duration: Duration) {
+        let histogram = self.api_latency.entry(operation).or_default();
+        if histogram.counts.is_empty() {
+            histogram.counts = vec![0; LATENCY_BUCKETS.len()];
+        }
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.counts[index] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+    pub fn set_ip(&mut self, ip: &str, changed: bool) {
+        self.ip = Some(ip.to_string());
+        if changed {
+            self.ip_changes += 1;
+        }
+    }
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        write_header(
+            &mut text,
+            "cf_dns_sync_sync_cycles_total",
+            "counter",
+            "Sync cycles by result, a cycle fails when the ip address couldn't be detected or a record couldn't be updated",
+        );
+        for (result, count) in self.sync_cycles.iter() {
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_sync_cycles_total{{result=\"{result}\"}} {count}"
+            );
+        }
+        write_header(
+            &mut text,
+            "cf_dns_sync_record_updates_total",
+            "counter",
+            "Record updates by record and result (success, failure or vetoed by a hook)",
+        );
+        for ((record, result), count) in self.record_updates.iter() {
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_record_updates_total{{record=\"{}\",result=\"{result}\"}} {count}",
+                escape_label(record)
+            );
+        }
+        write_header(
+            &mut text,
+            "cf_dns_sync_api_errors_total",
+            "counter",
+            "Failed cloudflare requests by cloudflare error code, or http_<status> and transport when there is none",
+        );
+        for (code, count) in self.api_errors.iter() {
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_api_errors_total{{code=\"{}\"}} {count}",
+                escape_label(code)
+            );
+        }
+        write_header(
+            &mut text,
+            "cf_dns_sync_ip_changes_total",
+            "counter",
+            "Changes of the public ip address",
+        );
+        let _ = writeln!(text, "cf_dns_sync_ip_changes_total {}", self.ip_changes);
+        write_header(
+            &mut text,
+            "cf_dns_sync_last_success_timestamp_seconds",
+            "gauge",
+            "When each record was last updated successfully, as a unix timestamp",
+        );
+        for (record, time) in self.last_success.iter() {
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_last_success_timestamp_seconds{{record=\"{}\"}} {}",
+                escape_label(record),
+                time.timestamp()
+            );
+        }
+        write_header(
+            &mut text,
+            "cf_dns_sync_ip_info",
+            "gauge",
+            "The current public ip address",
+        );
+        if let Some(ip) = &self.ip {
+            let version = if ip.contains(':') { "6" } else { "4" };
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_ip_info{{ip=\"{}\",version=\"{version}\"}} 1",
+                escape_label(ip)
+            );
+        }
+        write_header(
+            &mut text,
+            "cf_dns_sync_api_request_duration_seconds",
+            "histogram",
+            "How long cloudflare requests took, by operation",
+        );
+        for (operation, histogram) in self.api_latency.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.counts.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "cf_dns_sync_api_request_duration_seconds_bucket{{operation=\"{operation}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_api_request_duration_seconds_bucket{{operation=\"{operation}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_api_request_duration_seconds_sum{{operation=\"{operation}\"}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                text,
+                "cf_dns_sync_api_request_duration_seconds_count{{operation=\"{operation}\"}} {}",
+                histogram.count
+            );
+        }
+        text
+    }
+}
+fn write_header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {name} {help}");
+    let _ = writeln!(text, "# TYPE {name} {kind}");
+}
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+//Metrics are always collected, the server only decides if anyone can read them
+pub fn with_metrics(change: impl FnOnce(&mut Metrics)) {
+    let metrics = METRICS.get_or_init(|| Mutex::new(Metrics::default()));
+    if let Ok(mut metrics) = metrics.lock() {
+        change(&mut metrics);
+    }
+}
+//Restores what is known from before a restart, so the gauges don't start empty
+pub fn load_state(state: &State) {
+    with_metrics(|metrics| {
+        for record in state.records.values() {
+            if let Some(last_success) = record.last_success {
+                metrics
+                    .last_success
+                    .insert(record.name.clone(), last_success);
+            }
+        }
+        metrics.ip = state.last_ip.clone();
+    });
+}
+pub fn render_metrics() -> String {
+    let mut text = String::new();
+    with_metrics(|metrics| text = metrics.render());
+    text
+}
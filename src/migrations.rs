@@ -0,0 +1,130 @@
+use serde_json::{Map, Value};
+
+pub const CURRENT_CONFIG_VERSION: u32 = 3;
+
+struct Migration {
+    //The version this migration upgrades from, it produces version from + 1
+    from: u32,
+    description: &'static str,
+    migrate: fn(&mut Map<String, Value>),
+}
+
+//Every structural change to the config file gets a migration here, so that older files keep working
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "Added the version field",
+        migrate: |_config| {},
+    },
+    Migration {
+        from: 2,
+        description:
+            "Split log_config display, show and formats into separate console and file settings",
+        migrate: split_log_sinks,
+    },
+];
+
+//display, show, console_format and file_format become log_config.console and log_config.file. The show switches turn into
+//the most detailed level that was shown, so an unusual combination like logs without warnings now shows warnings too.
+fn split_log_sinks(config: &mut Map<String, Value>) {
+    let log_config = match config
+        .get_mut("log_config")
+        .and_then(|log_config| log_config.as_object_mut())
+    {
+        Some(log_config) => log_config,
+        None => return,
+    };
+    let display = log_config.remove("display");
+    let show = log_config.remove("show");
+    let console_format = log_config.remove("console_format");
+    let file_format = log_config.remove("file_format");
+    let level = show.map(|show| {
+        let is_shown = |key: &str| {
+            show.get(key)
+                .and_then(|value| value.as_bool())
+                .unwrap_or(true)
+        };
+        let level = if is_shown("logs") {
+            "info"
+        } else if is_shown("warnings") {
+            "warn"
+        } else if is_shown("errors") {
+            "error"
+        } else {
+            "off"
+        };
+        Value::from(level)
+    });
+    for (key, format) in [("console", console_format), ("file", file_format)] {
+        let mut sink = Map::new();
+        if let Some(level) = &level {
+            sink.insert("level".to_string(), level.clone());
+        }
+        if let Some(format) = format {
+            sink.insert("format".to_string(), format);
+        }
+        if let Some(display) = &display {
+            sink.insert("display".to_string(), display.clone());
+        }
+        if !sink.is_empty() {
+            log_config.insert(key.to_string(), Value::Object(sink));
+        }
+    }
+}
+
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub descriptions: Vec<&'static str>,
+}
+
+//Upgrades a parsed config file to the current layout. Files written before versioning was introduced are version 1.
+pub fn migrate(value: &mut Value) -> Result<Option<MigrationReport>, String> {
+    let config = match value.as_object_mut() {
+        Some(config) => config,
+        None => return Err("The config file doesn't contain a table of settings".to_string()),
+    };
+    let from_version = match config.get("version") {
+        None => 1,
+        Some(version) => match version.as_u64() {
+            Some(version) => version as u32,
+            None => {
+                return Err(format!(
+                    "The version field must be a number, found {version}"
+                ))
+            }
+        },
+    };
+    if from_version > CURRENT_CONFIG_VERSION {
+        return Err(format!(
+            "The config file is version {from_version}, but this version of cf_dns_sync only understands up to version {CURRENT_CONFIG_VERSION}. Please update cf_dns_sync."
+        ));
+    }
+    if from_version == CURRENT_CONFIG_VERSION {
+        return Ok(None);
+    }
+    let mut descriptions = vec![];
+    let mut version = from_version;
+    while version < CURRENT_CONFIG_VERSION {
+        let migration = match MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+        {
+            Some(migration) => migration,
+            None => {
+                return Err(format!(
+                    "There is no migration from config version {version}"
+                ))
+            }
+        };
+        (migration.migrate)(config);
+        descriptions.push(migration.description);
+        version += 1;
+    }
+    config.insert("version".to_string(), Value::from(version));
+    Ok(Some(MigrationReport {
+        from_version,
+        to_version: version,
+        descriptions,
+    }))
+}
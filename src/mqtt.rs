@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::state::RecordState;
+use crate::unending_process::{log_to_file_and_console, Config, DNSRecord, LogType};
+
+const RECONNECT_SECONDS: u64 = 5;
+const KEEP_ALIVE_SECONDS: u64 = 30;
+//Every retained message is sent again after reconnecting, so there is room for a lot of records
+const REQUEST_CAPACITY: usize = 256;
+
+static MQTT: OnceLock<Mutex<Mqtt>> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct MqttConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    //The ip address, the sync state and the records are published under this topic
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    #[serde(default = "default_true")]
+    pub retain: bool,
+    //Home Assistant's discovery messages, which add the sensors without any yaml
+    #[serde(default = "default_true")]
+    pub discovery: bool,
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            enabled: false,
+            host: default_host(),
+            port: default_port(),
+            username: None,
+            password: None,
+            client_id: default_client_id(),
+            topic_prefix: default_topic_prefix(),
+            retain: default_true(),
+            discovery: default_true(),
+            discovery_prefix: default_discovery_prefix(),
+        }
+    }
+}
+fn default_host() -> String {
+    "localhost".to_string()
+}
+fn default_port() -> u16 {
+    1883
+}
+fn default_client_id() -> String {
+    "cf_dns_sync".to_string()
+}
+fn default_topic_prefix() -> String {
+    "cf_dns_sync".to_string()
+}
+fn default_true() -> bool {
+    true
+}
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+struct Mqtt {
+    client: Client,
+    config: MqttConfig,
+    //Everything that was published with retain, sent again after reconnecting in case the broker lost it
+    retained: BTreeMap<String, String>,
+    //The records that have a discovery message, by id
+    discovered_records: BTreeMap<String, String>,
+    connected: bool,
+}
+impl Mqtt {
+    //While the broker can't be reached only the retained messages are kept, to be sent after connecting
+    fn publish(&mut self, topic: String, payload: String, retain: bool) {
+        if retain {
+            self.retained.insert(topic.clone(), payload.clone());
+        }
+        if self.connected {
+            let _ = self
+                .client
+                .try_publish(topic, QoS::AtLeastOnce, retain, payload);
+        }
+    }
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{name}", self.config.topic_prefix)
+    }
+    fn node_id(&self) -> String {
+        get_node_id(&self.config.client_id)
+    }
+    fn discovery_topic(&self, component: &str, object_id: &str) -> String {
+        format!(
+            "{}/{component}/{}/{object_id}/config",
+            self.config.discovery_prefix,
+            self.node_id()
+        )
+    }
+    fn publish_discovery(
+        &mut self,
+        component: &str,
+        object_id: &str,
+        mut payload: serde_json::Value,
+    ) {
+        let node_id = self.node_id();
+        payload["unique_id"] = json!(format!("{node_id}_{object_id}"));
+        payload["availability_topic"] = json!(self.topic("status"));
+        payload["device"] = json!({
+            "identifiers": [node_id],
+            "name": "cf_dns_sync",
+            "manufacturer": "cf_dns_sync",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let topic = self.discovery_topic(component, object_id);
+        self.publish(topic, payload.to_string(), true);
+    }
+}
+//Home Assistant only allows letters, numbers, _ and - in the node id
+pub fn get_node_id(client_id: &str) -> String {
+    client_id
+        .chars()
+        .map(|char| match char.is_ascii_alphanumeric() || char == '-' {
+            true => char,
+            false => '_',
+        })
+        .collect()
+}
+fn with_mqtt(change: impl FnOnce(&mut Mqtt)) {
+    if let Some(mqtt) = MQTT.get() {
+        if let Ok(mut mqtt) = mqtt.lock() {
+            change(&mut mqtt);
+        }
+    }
+}
+pub fn start(config: &Config) {
+    if !config.mqtt.enabled {
+        return;
+    }
+    let mqtt_config = config.mqtt.clone();
+    let mut options = MqttOptions::new(&mqtt_config.client_id, &mqtt_config.host, mqtt_config.port);
+    options.set_keep_alive(Duration::from_secs(KEEP_ALIVE_SECONDS));
+    if let Some(username) = &mqtt_config.username {
+        options.set_credentials(username, mqtt_config.password.clone().unwrap_or_default());
+    }
+    //The broker marks the sensors unavailable when the daemon goes away without saying so
+    options.set_last_will(LastWill::new(
+        format!("{}/status", mqtt_config.topic_prefix),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    let (client, mut connection) = Client::new(options, REQUEST_CAPACITY);
+    let mut mqtt = Mqtt {
+        client,
+        config: mqtt_config.clone(),
+        retained: BTreeMap::new(),
+        discovered_records: BTreeMap::new(),
+        connected: false,
+    };
+    let status_topic = mqtt.topic("status");
+    mqtt.publish(status_topic, "online".to_string(), true);
+    if mqtt_config.discovery {
+        let ip_topic = mqtt.topic("ip");
+        mqtt.publish_discovery(
+            "sensor",
+            "ip",
+            json!({
+                "name": "Public IP address",
+                "state_topic": ip_topic,
+                "icon": "mdi:ip-network",
+            }),
+        );
+        let sync_topic = mqtt.topic("sync");
+        mqtt.publish_discovery(
+            "binary_sensor",
+            "sync",
+            json!({
+                "name": "Sync problem",
+                "state_topic": sync_topic,
+                "device_class": "problem",
+                "payload_on": "failing",
+                "payload_off": "ok",
+            }),
+        );
+    }
+    if MQTT.set(Mutex::new(mqtt)).is_err() {
+        return;
+    }
+    let log_config = config.log_config.clone();
+    let address = format!("{}:{}", mqtt_config.host, mqtt_config.port);
+    std::thread::spawn(move || {
+        let mut has_failed = false;
+        for notification in connection.iter() {
+            match notification {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    log_to_file_and_console(
+                        &format!("Connected to the MQTT broker at {address}"),
+                        LogType::Log,
+                        &log_config,
+                    );
+                    with_mqtt(|mqtt| {
+                        mqtt.connected = true;
+                        for (topic, payload) in mqtt.retained.iter() {
+                            let _ = mqtt.client.try_publish(
+                                topic,
+                                QoS::AtLeastOnce,
+                                true,
+                                payload.as_str(),
+                            );
+                        }
+                    });
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    let mut was_connected = true;
+                    with_mqtt(|mqtt| was_connected = std::mem::replace(&mut mqtt.connected, false));
+                    //Only the first failure is logged, the retries every few seconds would flood the log
+                    if was_connected || !has_failed {
+                        log_to_file_and_console(
+                            &format!("Couldn't reach the MQTT broker at {address} ({err}), retrying every {RECONNECT_SECONDS} seconds"),
+                            LogType::Warn,
+                            &log_config,
+                        );
+                    }
+                    has_failed = true;
+                    std::thread::sleep(Duration::from_secs(RECONNECT_SECONDS));
+                }
+            }
+        }
+    });
+}
+pub fn publish_ip(ip: &str) {
+    with_mqtt(|mqtt| {
+        let topic = mqtt.topic("ip");
+        let retain = mqtt.config.retain;
+        mqtt.publish(topic, ip.to_string(), retain);
+    });
+}
+pub fn publish_sync(success: bool) {
+    with_mqtt(|mqtt| {
+        let topic = mqtt.topic("sync");
+        let retain = mqtt.config.retain;
+        let payload = if success { "ok" } else { "failing" };
+        mqtt.publish(topic, payload.to_string(), retain);
+    });
+}
+//The result is success, failure or vetoed (by a pre-update hook), like in the metrics
+pub fn publish_record(
+    record: &DNSRecord,
+    content: &str,
+    result: &str,
+    record_state: Option<&RecordState>,
+) {
+    with_mqtt(|mqtt| {
+        let payload = get_record_payload(content, result, record_state);
+        let topic = mqtt.topic(&format!("records/{}", record.name));
+        let retain = mqtt.config.retain;
+        mqtt.publish(topic, payload.to_string(), retain);
+    });
+}
+pub fn get_record_payload(
+    content: &str,
+    result: &str,
+    record_state: Option<&RecordState>,
+) -> serde_json::Value {
+    let time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        time.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+    };
+    json!({
+        "result": result,
+        "content": content,
+        "last_success": time(record_state.and_then(|record_state| record_state.last_success)),
+        "last_failure": time(record_state.and_then(|record_state| record_state.last_failure)),
+        "last_error": record_state.and_then(|record_state| record_state.last_error.clone()),
+    })
+}
+//Adds a sensor for every synced record and removes the ones of records that aren't synced anymore
+pub fn publish_record_discovery(config: &Config) {
+    with_mqtt(|mqtt| {
+        if !mqtt.config.discovery {
+            return;
+        }
+        let synced: BTreeMap<String, String> = config
+            .dns_config
+            .iter()
+            .filter(|record| record.sync == Some(true))
+            .map(|record| (record.id.clone(), record.name.clone()))
+            .collect();
+        if synced == mqtt.discovered_records {
+            return;
+        }
+        let removed: Vec<String> = mqtt
+            .discovered_records
+            .keys()
+            .filter(|id| !synced.contains_key(*id))
+            .cloned()
+            .collect();
+        for id in removed {
+            //An empty retained message makes Home Assistant remove the sensor
+            let topic = mqtt.discovery_topic("sensor", &format!("record_{id}"));
+            mqtt.retained.remove(&topic);
+            let _ = mqtt.client.try_publish(topic, QoS::AtLeastOnce, true, "");
+        }
+        for (id, name) in synced.iter() {
+            if mqtt.discovered_records.get(id) == Some(name) {
+                continue;
+            }
+            let state_topic = mqtt.topic(&format!("records/{name}"));
+            mqtt.publish_discovery(
+                "sensor",
+                &format!("record_{id}"),
+                json!({
+                    "name": name,
+                    "state_topic": state_topic,
+                    "value_template": "{{ value_json.result }}",
+                    "json_attributes_topic": state_topic,
+                    "icon": "mdi:dns",
+                }),
+            );
+        }
+        mqtt.discovered_records = synced;
+    });
+}
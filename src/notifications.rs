@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Duration, NaiveTime, SecondsFormat, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::chat::{self, ChatConfig};
+use crate::email::{self, EmailConfig};
+use crate::state::format_local;
+use crate::unending_process::{
+    log_event, log_to_file_and_console, LogConfig, LogDetails, LogTimezone, LogType,
+};
+
+const REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+static SENDER: OnceLock<Sender<(Notification, NotificationsConfig, LogConfig)>> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Serialize, Default)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    pub email: Option<EmailConfig>,
+    #[serde(default)]
+    pub chat: Vec<ChatConfig>,
+    //Holds back chat messages and emails, webhooks are often used for automation so they are always sent
+    pub quiet_hours: Option<QuietHoursConfig>,
+}
+impl NotificationsConfig {
+    fn wants(&self, event: NotificationEvent) -> bool {
+        self.webhooks
+            .iter()
+            .any(|webhook| webhook.events.contains(&event))
+            || self
+                .email
+                .as_ref()
+                .is_some_and(|email| email.events.contains(&event))
+            || self.chat.iter().any(|chat| chat.events.contains(&event))
+    }
+}
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct QuietHoursConfig {
+    //Like 22:00, quiet hours may go past midnight
+    pub start: String,
+    pub end: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+impl QuietHoursConfig {
+    pub fn parse_time(time: &str) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+    }
+    //Invalid times never make it quiet, the validate command reports them
+    pub fn is_quiet(&self, time: DateTime<Utc>) -> bool {
+        let (start, end) = match (
+            QuietHoursConfig::parse_time(&self.start),
+            QuietHoursConfig::parse_time(&self.end),
+        ) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return false,
+        };
+        let time = LogTimezone::parse(&self.timezone)
+            .unwrap_or(LogTimezone::Local)
+            .time_of_day(time);
+        if start <= end {
+            start <= time && time < end
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+fn default_timezone() -> String {
+    "local".to_string()
+}
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    //The default json body is sent when there is no template
+    pub body: Option<String>,
+    #[serde(default = "default_events")]
+    pub events: Vec<NotificationEvent>,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+impl WebhookConfig {
+    pub fn new(url: String) -> Self {
+        WebhookConfig {
+            url,
+            method: default_method(),
+            headers: BTreeMap::new(),
+            body: None,
+            events: default_events(),
+            retries: default_retries(),
+        }
+    }
+}
+fn default_method() -> String {
+    "POST".to_string()
+}
+pub fn default_events() -> Vec<NotificationEvent> {
+    vec![
+        NotificationEvent::IpChanged,
+        NotificationEvent::RecordUpdated,
+        NotificationEvent::SyncFailed,
+        NotificationEvent::Recovered,
+    ]
+}
+fn default_retries() -> u32 {
+    3
+}
+#[derive(Deserialize, Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    IpChanged,
+    RecordUpdated,
+    SyncFailed,
+    Recovered,
+}
+pub const NOTIFICATION_EVENTS: &[NotificationEvent] = &[
+    NotificationEvent::IpChanged,
+    NotificationEvent::RecordUpdated,
+    NotificationEvent::SyncFailed,
+    NotificationEvent::Recovered,
+];
+impl NotificationEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            NotificationEvent::IpChanged => "ip_changed",
+            NotificationEvent::RecordUpdated => "record_updated",
+            NotificationEvent::SyncFailed => "sync_failed",
+            NotificationEvent::Recovered => "recovered",
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub message: String,
+    pub old_ip: Option<String>,
+    pub new_ip: Option<String>,
+    pub records: Vec<String>,
+    pub error: Option<String>,
+    //When the current run of failures started, for sync_failed and recovered
+    pub failing_since: Option<DateTime<Utc>>,
+    pub time: DateTime<Utc>,
+}
+impl Notification {
+    pub fn new(event: NotificationEvent, message: String) -> Self {
+        Notification {
+            event,
+            message,
+            old_ip: None,
+            new_ip: None,
+            records: vec![],
+            error: None,
+            failing_since: None,
+            time: Utc::now(),
+        }
+    }
+    pub fn old_ip(mut self, old_ip: &str) -> Self {
+        self.old_ip = Some(old_ip.to_string());
+        self
+    }
+    pub fn new_ip(mut self, new_ip: &str) -> Self {
+        self.new_ip = Some(new_ip.to_string());
+        self
+    }
+    pub fn records(mut self, records: Vec<String>) -> Self {
+        self.records = records;
+        self
+    }
+    pub fn error(mut self, error: String) -> Self {
+        self.error = Some(error);
+        self
+    }
+    pub fn failing_since(mut self, failing_since: DateTime<Utc>) -> Self {
+        self.failing_since = Some(failing_since);
+        self
+    }
+    //A short summary, used as the subject of emails and the title of chat messages
+    pub fn title(&self) -> String {
+        match self.event {
+            NotificationEvent::IpChanged => format!(
+                "Public ip address changed to {}",
+                self.new_ip.as_deref().unwrap_or("?")
+            ),
+            NotificationEvent::RecordUpdated => "Records updated".to_string(),
+            NotificationEvent::SyncFailed => "Syncing is failing".to_string(),
+            NotificationEvent::Recovered => "Syncing recovered".to_string(),
+        }
+    }
+    //The message followed by one line for every detail, as plain text
+    pub fn text(&self) -> String {
+        let mut text = format!("{}\n", self.message);
+        for (name, value) in self.get_fields() {
+            text.push_str(&format!("\n{name}: {value}"));
+        }
+        if let Some(error) = &self.error {
+            text.push_str(&format!("\n\nErrors:\n{error}"));
+        }
+        text.push('\n');
+        text
+    }
+    pub fn get_fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![];
+        if let Some(old_ip) = &self.old_ip {
+            fields.push(("Old ip address", old_ip.clone()));
+        }
+        if let Some(new_ip) = &self.new_ip {
+            fields.push(("New ip address", new_ip.clone()));
+        }
+        if !self.records.is_empty() {
+            fields.push(("Records", self.records.join(", ")));
+        }
+        if let Some(failing_since) = &self.failing_since {
+            fields.push((
+                "Failing since",
+                format!(
+                    "{} ({} minutes)",
+                    format_local(failing_since),
+                    (self.time - *failing_since).num_minutes()
+                ),
+            ));
+        }
+        fields.push(("Time", format_local(&self.time)));
+        fields
+    }
+    //Used by the configure menu and validate, so templates can be tried without waiting for a real event
+    pub fn test(records: Vec<String>) -> Self {
+        Notification::new(
+            NotificationEvent::IpChanged,
+            "This is a test notification from cf_dns_sync".to_string(),
+        )
+        .old_ip("203.0.113.5")
+        .new_ip("203.0.113.7")
+        .records(records)
+    }
+}
+//What the sending thread remembers between notifications
+#[derive(Default)]
+pub(crate) struct DispatchState {
+    //The start of the failures that were already reported, so every run of failures is only reported once
+    pub(crate) webhook_failure: Option<DateTime<Utc>>,
+    pub(crate) email_failure: Option<DateTime<Utc>>,
+    pub(crate) email_times: VecDeque<DateTime<Utc>>,
+    pub(crate) chat_failure: Option<DateTime<Utc>>,
+    //Notifications that came in during the quiet hours, sent once they are over
+    pub(crate) held: Vec<Notification>,
+}
+//Queues the notification for every notifier that wants the event. They are sent in order on a separate thread, so retries never delay a sync.
+pub fn notify(
+    notification: Notification,
+    notifications: &NotificationsConfig,
+    log_config: &LogConfig,
+) {
+    if !notifications.wants(notification.event) {
+        return;
+    }
+    let sender = SENDER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<(Notification, NotificationsConfig, LogConfig)>();
+        std::thread::spawn(move || {
+            let mut state = DispatchState::default();
+            let mut latest = None;
+            loop {
+                //Wakes up every minute to check if the quiet hours are over
+                match receiver.recv_timeout(std::time::Duration::from_secs(60)) {
+                    Ok((notification, notifications, log_config)) => {
+                        dispatch(notification, &notifications, &log_config, &mut state);
+                        latest = Some((notifications, log_config));
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+                if let Some((notifications, log_config)) = &latest {
+                    send_held(notifications, log_config, &mut state);
+                }
+            }
+        });
+        sender
+    });
+    if let Err(err) = sender.send((notification, notifications.clone(), log_config.clone())) {
+        log_to_file_and_console(
+            &format!("Couldn't queue notification: {err}"),
+            LogType::Error,
+            log_config,
+        );
+    }
+}
+fn dispatch(
+    notification: Notification,
+    notifications: &NotificationsConfig,
+    log_config: &LogConfig,
+    state: &mut DispatchState,
+) {
+    //Webhooks get sync_failed on the first failed run
+    let is_repeated_failure = notification.event == NotificationEvent::SyncFailed
+        && state.webhook_failure.is_some()
+        && state.webhook_failure == notification.failing_since;
+    if notification.event == NotificationEvent::SyncFailed {
+        state.webhook_failure = notification.failing_since;
+    }
+    if !is_repeated_failure {
+        for webhook in notifications.webhooks.iter() {
+            if webhook.events.contains(&notification.event) {
+                let _ = send_webhook(webhook, &notification, log_config);
+            }
+        }
+    }
+    if is_quiet(notifications) {
+        //sync_failed comes again with every failed run, so it is reported after the quiet hours if syncing still fails
+        if notification.event != NotificationEvent::SyncFailed {
+            state.held.push(notification);
+        }
+        return;
+    }
+    dispatch_to_people(&notification, notifications, log_config, state);
+}
+fn is_quiet(notifications: &NotificationsConfig) -> bool {
+    notifications
+        .quiet_hours
+        .as_ref()
+        .is_some_and(|quiet_hours| quiet_hours.is_quiet(Utc::now()))
+}
+fn send_held(
+    notifications: &NotificationsConfig,
+    log_config: &LogConfig,
+    state: &mut DispatchState,
+) {
+    if state.held.is_empty() || is_quiet(notifications) {
+        return;
+    }
+    for notification in std::mem::take(&mut state.held) {
+        dispatch_to_people(&notification, notifications, log_config, state);
+    }
+}
+//Emails and chat messages, which the quiet hours apply to
+fn dispatch_to_people(
+    notification: &Notification,
+    notifications: &NotificationsConfig,
+    log_config: &LogConfig,
+    state: &mut DispatchState,
+) {
+    if let Some(email) = &notifications.email {
+        if email.events.contains(&notification.event) && should_email(notification, email, state) {
+            send_rate_limited_email(notification, email, log_config, state);
+        }
+    }
+    //Like webhooks, chats get sync_failed once, and recovered only when they were told about the failure
+    let should_chat = match notification.event {
+        NotificationEvent::SyncFailed => {
+            let is_new = state.chat_failure != notification.failing_since;
+            state.chat_failure = notification.failing_since;
+            is_new
+        }
+        NotificationEvent::Recovered => {
+            state.chat_failure.is_some() && state.chat_failure.take() == notification.failing_since
+        }
+        _ => true,
+    };
+    if !should_chat {
+        return;
+    }
+    for chat in notifications.chat.iter() {
+        if chat.events.contains(&notification.event) {
+            let _ = chat::send_chat(chat, notification, log_config);
+        }
+    }
+}
+//Emails about failures wait for failure_minutes, and recovered is only mailed after a failure was
+pub(crate) fn should_email(
+    notification: &Notification,
+    email: &EmailConfig,
+    state: &DispatchState,
+) -> bool {
+    match notification.event {
+        NotificationEvent::SyncFailed => match notification.failing_since {
+            Some(failing_since) => {
+                state.email_failure != Some(failing_since)
+                    && notification.time - failing_since
+                        >= Duration::minutes(email.failure_minutes as i64)
+            }
+            None => true,
+        },
+        NotificationEvent::Recovered => {
+            !email.events.contains(&NotificationEvent::SyncFailed)
+                || (state.email_failure.is_some()
+                    && state.email_failure == notification.failing_since)
+        }
+        _ => true,
+    }
+}
+pub(crate) fn send_rate_limited_email(
+    notification: &Notification,
+    email: &EmailConfig,
+    log_config: &LogConfig,
+    state: &mut DispatchState,
+) {
+    let hour_ago = notification.time - Duration::hours(1);
+    while state
+        .email_times
+        .front()
+        .is_some_and(|time| *time < hour_ago)
+    {
+        state.email_times.pop_front();
+    }
+    if state.email_times.len() >= email.max_per_hour as usize {
+        log_event(
+            &format!(
+                "Didn't send the {} email, {} were already sent in the last hour",
+                notification.event.name(),
+                state.email_times.len()
+            ),
+            LogType::Warn,
+            &LogDetails::event("notification_failed"),
+            log_config,
+        );
+        return;
+    }
+    state.email_times.push_back(notification.time);
+    match notification.event {
+        NotificationEvent::SyncFailed => state.email_failure = notification.failing_since,
+        NotificationEvent::Recovered => state.email_failure = None,
+        _ => {}
+    }
+    let _ = email::send_email(email, notification, log_config);
+}
+pub fn send_webhook(
+    webhook: &WebhookConfig,
+    notification: &Notification,
+    log_config: &LogConfig,
+) -> Result<(), String> {
+    let body = render_body(webhook, notification);
+    send_with_retries(
+        get_host(&webhook.url),
+        notification,
+        webhook.retries,
+        log_config,
+        |agent| {
+            let mut request = agent.request(&webhook.method, &webhook.url);
+            if !webhook
+                .headers
+                .keys()
+                .any(|name| name.eq_ignore_ascii_case("content-type"))
+            {
+                request = request.set("Content-Type", "application/json");
+            }
+            for (name, value) in webhook.headers.iter() {
+                request = request.set(name, value);
+            }
+            match webhook.method.eq_ignore_ascii_case("GET") {
+                true => request.call(),
+                false => request.send_string(&body),
+            }
+            .map_err(Box::new)
+        },
+    )
+}
+//Tries once plus the number of retries, waiting 1, 2, 4... seconds in between. Used by webhooks and chat services.
+pub fn send_with_retries(
+    host: &str,
+    notification: &Notification,
+    retries: u32,
+    log_config: &LogConfig,
+    send: impl Fn(&ureq::Agent) -> Result<ureq::Response, Box<ureq::Error>>,
+) -> Result<(), String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+        .build();
+    let mut attempt = 0;
+    loop {
+        let start = std::time::Instant::now();
+        let (err, retryable) = match send(&agent) {
+            Ok(response) => {
+                log_event(
+                    &format!(
+                        "Sent the {} notification to {host} ({})",
+                        notification.event.name(),
+                        response.status()
+                    ),
+                    LogType::Debug,
+                    &LogDetails::event("notification_sent").duration(start.elapsed()),
+                    log_config,
+                );
+                return Ok(());
+            }
+            Err(err) => match *err {
+                //Other client errors mean the notifier is misconfigured, so sending it again wouldn't help
+                ureq::Error::Status(status, _) => (
+                    format!("it returned {status}"),
+                    status == 429 || status >= 500,
+                ),
+                //The transport error's own message would include the url
+                ureq::Error::Transport(transport) => match transport.message() {
+                    Some(message) => (format!("{} ({message})", transport.kind()), true),
+                    None => (transport.kind().to_string(), true),
+                },
+            },
+        };
+        if !retryable || attempt >= retries {
+            log_event(
+                &format!(
+                    "Couldn't send the {} notification to {host}, {err}",
+                    notification.event.name()
+                ),
+                LogType::Warn,
+                &LogDetails::event("notification_failed").duration(start.elapsed()),
+                log_config,
+            );
+            return Err(err);
+        }
+        log_to_file_and_console(
+            &format!(
+                "Couldn't send the {} notification to {host}, {err}. Retrying...",
+                notification.event.name()
+            ),
+            LogType::Debug,
+            log_config,
+        );
+        std::thread::sleep(std::time::Duration::from_secs(1 << attempt.min(6)));
+        attempt += 1;
+    }
+}
+//Placeholders are replaced with json escaped text, so they can be used inside json strings of the template
+pub fn render_body(webhook: &WebhookConfig, notification: &Notification) -> String {
+    let template = match &webhook.body {
+        Some(template) if !template.trim().is_empty() => template,
+        _ => return get_default_body(notification).to_string(),
+    };
+    let timestamp = notification.time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let records = notification.records.join(", ");
+    let placeholders = [
+        ("{event}", notification.event.name()),
+        ("{message}", &notification.message),
+        ("{old_ip}", notification.old_ip.as_deref().unwrap_or("")),
+        ("{new_ip}", notification.new_ip.as_deref().unwrap_or("")),
+        ("{records}", &records),
+        ("{error}", notification.error.as_deref().unwrap_or("")),
+        ("{timestamp}", &timestamp),
+    ];
+    //Replaced in a single pass, so a value that contains a placeholder isn't replaced again
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(character) = rest.chars().next() {
+        match placeholders
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                let escaped = Value::String(value.to_string()).to_string();
+                body.push_str(&escaped[1..escaped.len() - 1]);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                body.push(character);
+                rest = &rest[character.len_utf8()..];
+            }
+        }
+    }
+    body
+}
+fn get_default_body(notification: &Notification) -> Value {
+    serde_json::json!({
+        "event": notification.event.name(),
+        "message": notification.message,
+        "old_ip": notification.old_ip,
+        "new_ip": notification.new_ip,
+        "records": notification.records,
+        "error": notification.error,
+        "timestamp": notification.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+    })
+}
+//Webhook urls often contain their secret (like slack's and discord's), so only the host is logged
+pub fn get_host(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = without_scheme
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or(without_scheme);
+    authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host)
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process,
+};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use sysinfo::{Pid, ProcessExt, System, SystemExt};
+
+use crate::control;
+use crate::logs;
+use crate::unending_process::{
+    create_parent_folder, format_err, get_config_folder_path, log_to_console,
+    log_to_file_and_console, DNSRecord, LogConfig, LogType,
+};
+
+//What the running daemon knows about the world, kept in state.json next to the config file so other commands can read it
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct State {
+    pub pid: Option<u32>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub last_ip: Option<String>,
+    pub last_ip_detected_at: Option<DateTime<Utc>>,
+    pub last_ip_changed_at: Option<DateTime<Utc>>,
+    //The last address of each family, which stays known when the daemon detects the other family
+    pub last_ipv4: Option<String>,
+    pub last_ipv4_detected_at: Option<DateTime<Utc>>,
+    pub last_ipv6: Option<String>,
+    pub last_ipv6_detected_at: Option<DateTime<Utc>>,
+    pub next_run: Option<DateTime<Utc>>,
+    //Keyed by record id
+    #[serde(default)]
+    pub records: BTreeMap<String, RecordState>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RecordState {
+    pub name: String,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+//One line of history.jsonl. Every detection and update attempt is appended, and the oldest entries are dropped once the
+//file grows past log_config.rotation.max_history_size_kb.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HistoryEntry {
+    IpDetected {
+        time: DateTime<Utc>,
+        ip: String,
+    },
+    RecordUpdate {
+        time: DateTime<Utc>,
+        record_id: String,
+        record_name: String,
+        old_content: String,
+        new_content: String,
+        success: bool,
+        error: Option<String>,
+    },
+}
+impl HistoryEntry {
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            HistoryEntry::IpDetected { time, .. } => *time,
+            HistoryEntry::RecordUpdate { time, .. } => *time,
+        }
+    }
+}
+impl State {
+    pub fn load() -> State {
+        let state_path = match get_state_path() {
+            Ok(state_path) => state_path,
+            Err(()) => return State::default(),
+        };
+        match fs::read_to_string(state_path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => State::default(),
+        }
+    }
+    pub fn save(&self, log_config: &LogConfig) {
+        let state_path = match get_state_path() {
+            Ok(state_path) => state_path,
+            Err(()) => {
+                log_to_console("Couldn't get state file path", LogType::Warn, log_config);
+                return;
+            }
+        };
+        if create_parent_folder(&state_path).is_err() {
+            return;
+        }
+        let string = match serde_json::to_string_pretty(self) {
+            Ok(string) => string,
+            Err(err) => {
+                log_to_console(
+                    &format!("Couldn't convert state to string{}", format_err(err)),
+                    LogType::Warn,
+                    log_config,
+                );
+                return;
+            }
+        };
+        //Written to a temporary file first, so that readers never see a half written state
+        let temporary_path = state_path.with_extension("json.tmp");
+        if let Err(err) = fs::write(&temporary_path, string)
+            .and_then(|()| fs::rename(&temporary_path, &state_path))
+        {
+            log_to_console(
+                &format!("Couldn't save state file{}", format_err(err)),
+                LogType::Warn,
+                log_config,
+            );
+        }
+    }
+    pub fn record_attempt(
+        &mut self,
+        record: &DNSRecord,
+        new_content: &str,
+        error: Option<String>,
+        log_config: &LogConfig,
+    ) {
+        let now = Utc::now();
+        let record_state = self.records.entry(record.id.clone()).or_default();
+        record_state.name = record.name.clone();
+        match &error {
+            None => record_state.last_success = Some(now),
+            Some(error) => {
+                record_state.last_failure = Some(now);
+                record_state.last_error = Some(error.clone());
+            }
+        }
+        append_history(
+            &HistoryEntry::RecordUpdate {
+                time: now,
+                record_id: record.id.clone(),
+                record_name: record.name.clone(),
+                old_content: record.content.clone(),
+                new_content: new_content.to_string(),
+                success: error.is_none(),
+                error,
+            },
+            log_config,
+        );
+    }
+    //Returns the previous ip if the ip changed since the last detection, even across restarts
+    pub fn set_ip(&mut self, ip: String, log_config: &LogConfig) -> Option<String> {
+        let now = Utc::now();
+        append_history(
+            &HistoryEntry::IpDetected {
+                time: now,
+                ip: ip.clone(),
+            },
+            log_config,
+        );
+        match ip.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => {
+                self.last_ipv6 = Some(ip.clone());
+                self.last_ipv6_detected_at = Some(now);
+            }
+            _ => {
+                self.last_ipv4 = Some(ip.clone());
+                self.last_ipv4_detected_at = Some(now);
+            }
+        }
+        let previous_ip = self.last_ip.replace(ip.clone());
+        self.last_ip_detected_at = Some(now);
+        match previous_ip {
+            Some(previous_ip) if previous_ip != ip => {
+                self.last_ip_changed_at = Some(now);
+                Some(previous_ip)
+            }
+            Some(_) => None,
+            None => {
+                self.last_ip_changed_at = Some(now);
+                None
+            }
+        }
+    }
+    pub fn is_daemon_running(&self) -> bool {
+        let pid = match self.pid {
+            Some(pid) => pid,
+            None => return false,
+        };
+        if pid == process::id() {
+            return true;
+        }
+        let pid = Pid::from(pid as usize);
+        let mut sys = System::new();
+        if !sys.refresh_process(pid) {
+            return false;
+        }
+        //The pid might have been reused by an unrelated process since the daemon stopped
+        match sys.process(pid) {
+            Some(daemon_process) => daemon_process.name().contains("cf_dns_sync"),
+            None => false,
+        }
+    }
+}
+pub fn get_state_path() -> Result<PathBuf, ()> {
+    Ok(get_config_folder_path()?.join("state.json"))
+}
+pub fn get_history_path() -> Result<PathBuf, ()> {
+    Ok(get_config_folder_path()?.join("history.jsonl"))
+}
+pub fn append_history(entry: &HistoryEntry, log_config: &LogConfig) {
+    let history_path = match get_history_path() {
+        Ok(history_path) => history_path,
+        Err(()) => {
+            log_to_console("Couldn't get history file path", LogType::Warn, log_config);
+            return;
+        }
+    };
+    if create_parent_folder(&history_path).is_err() {
+        return;
+    }
+    let line = match serde_json::to_string(entry) {
+        Ok(line) => line,
+        Err(err) => {
+            log_to_console(
+                &format!(
+                    "Couldn't convert history entry to string{}",
+                    format_err(err)
+                ),
+                LogType::Warn,
+                log_config,
+            );
+            return;
+        }
+    };
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_path)
+        .and_then(|mut file| writeln!(file, "{line}"));
+    if let Err(err) = result {
+        log_to_console(
+            &format!("Couldn't write to history file{}", format_err(err)),
+            LogType::Warn,
+            log_config,
+        );
+        return;
+    }
+    //A limit of None (or 0) keeps the whole history
+    let max_kilobytes = match log_config.rotation.max_history_size_kb {
+        Some(max_kilobytes) if max_kilobytes > 0 => max_kilobytes,
+        _ => return,
+    };
+    match prune_history(&history_path, max_kilobytes * 1024) {
+        Ok(0) => {}
+        Ok(dropped_entries) => {
+            log_to_file_and_console(
+                &format!(
+                    "Dropped the {dropped_entries} oldest entries of the history file, it grew past {max_kilobytes} KB"
+                ),
+                LogType::Log,
+                log_config,
+            );
+        }
+        Err(err) => {
+            log_to_console(
+                &format!("Couldn't prune history file{}", format_err(err)),
+                LogType::Warn,
+                log_config,
+            );
+        }
+    }
+}
+//Drops the oldest lines until about half of the limit is left, so the file isn't rewritten on every append.
+//Returns the number of dropped lines.
+pub fn prune_history(history_path: &Path, max_bytes: u64) -> std::io::Result<usize> {
+    let size = fs::metadata(history_path)?.len();
+    if size <= max_bytes {
+        return Ok(0);
+    }
+    let mut bytes_to_drop = size - max_bytes / 2;
+    let mut dropped_lines = 0;
+    let temporary_path = history_path.with_extension("jsonl.tmp");
+    let mut temporary_file = File::create(&temporary_path)?;
+    for line in BufReader::new(File::open(history_path)?).lines() {
+        let line = line?;
+        if bytes_to_drop > 0 {
+            bytes_to_drop = bytes_to_drop.saturating_sub(line.len() as u64 + 1);
+            dropped_lines += 1;
+            continue;
+        }
+        writeln!(temporary_file, "{line}")?;
+    }
+    fs::rename(&temporary_path, history_path)?;
+    Ok(dropped_lines)
+}
+pub fn read_history() -> Vec<HistoryEntry> {
+    let history_path = match get_history_path() {
+        Ok(history_path) => history_path,
+        Err(()) => return vec![],
+    };
+    let file = match File::open(history_path) {
+        Ok(file) => file,
+        Err(_) => return vec![],
+    };
+    //A line cut short by a crash is skipped rather than making the whole history unreadable
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect()
+}
+//Only the newest entries, oldest first, without reading the whole file
+pub fn read_recent_history(entry_count: usize) -> Vec<HistoryEntry> {
+    let history_path = match get_history_path() {
+        Ok(history_path) => history_path,
+        Err(()) => return vec![],
+    };
+    logs::read_last_lines(&history_path, entry_count)
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+pub fn print_history(
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    show_updates: bool,
+) {
+    let history = read_history();
+    //Every ip is used from its detection until another ip is detected, so consecutive detections of the same ip are
+    //collapsed into one period
+    let mut periods: Vec<(String, DateTime<Utc>, Option<DateTime<Utc>>)> = vec![];
+    for entry in history.iter() {
+        if let HistoryEntry::IpDetected { time, ip } = entry {
+            match periods.last_mut() {
+                Some((last_ip, _, _)) if last_ip == ip => {}
+                Some((_, _, used_until)) => {
+                    *used_until = Some(*time);
+                    periods.push((ip.clone(), *time, None));
+                }
+                None => periods.push((ip.clone(), *time, None)),
+            }
+        }
+    }
+    let periods: Vec<_> = periods
+        .into_iter()
+        .filter(|(_, used_from, used_until)| {
+            until.is_none_or(|until| *used_from <= until)
+                && since.is_none_or(|since| used_until.is_none_or(|used_until| used_until >= since))
+        })
+        .collect();
+    if periods.is_empty() {
+        println!("No ip addresses were used in this time range");
+    } else {
+        println!("{:40} {:20} Until", "IP address", "From");
+        for (ip, used_from, used_until) in periods.iter() {
+            let used_until = match used_until {
+                Some(used_until) => format_local(used_until),
+                None => "now".to_string(),
+            };
+            println!("{:40} {:20} {used_until}", ip, format_local(used_from));
+        }
+        println!("{} ip changes", periods.len() - 1);
+    }
+    if !show_updates {
+        return;
+    }
+    println!();
+    let mut has_updates = false;
+    let entries = history
+        .iter()
+        .filter(|entry| since.is_none_or(|since| entry.time() >= since))
+        .filter(|entry| until.is_none_or(|until| entry.time() <= until));
+    for entry in entries {
+        if let HistoryEntry::RecordUpdate {
+            time,
+            record_name,
+            old_content,
+            new_content,
+            success,
+            error,
+            ..
+        } = entry
+        {
+            has_updates = true;
+            let result = match (success, error) {
+                (true, _) => "ok".to_string(),
+                (false, Some(error)) => format!("failed: {error}"),
+                (false, None) => "failed".to_string(),
+            };
+            println!(
+                "{} {record_name}: {old_content} -> {new_content} {result}",
+                format_local(time)
+            );
+        }
+    }
+    if !has_updates {
+        println!("No record updates were attempted in this time range");
+    }
+}
+//Accepts 2023-11-24, 2023-11-24 23:40, 2023-11-24 23:40:43 in local time, or an rfc 3339 timestamp
+pub fn parse_date_argument(argument: &str, end_of_day: bool) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(argument) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(argument, format).ok())
+    .or_else(|| {
+        let date = NaiveDate::parse_from_str(argument, "%Y-%m-%d").ok()?;
+        if end_of_day {
+            date.and_hms_opt(23, 59, 59)
+        } else {
+            date.and_hms_opt(0, 0, 0)
+        }
+    })?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+pub fn format_local(time: &DateTime<Utc>) -> String {
+    let local: DateTime<Local> = DateTime::from(*time);
+    local.format("%d/%m/%Y %H:%M:%S").to_string()
+}
+pub fn print_status() {
+    //A running daemon answers with its state and what was changed through the control api
+    let (state, control) = match control::send_request(&json!({ "command": "status" })) {
+        Ok(response) if response["ok"] == true => {
+            match serde_json::from_value(response["state"].clone()) {
+                Ok(state) => (state, Some(response)),
+                Err(_) => (State::load(), None),
+            }
+        }
+        _ => (State::load(), None),
+    };
+    match (state.is_daemon_running(), state.pid) {
+        (true, Some(pid)) => println!("Daemon:             running (pid {pid})"),
+        _ => println!("Daemon:             not running"),
+    }
+    if let Some(control) = &control {
+        let syncing = match (control["paused"] == true, control["syncing"] == true) {
+            (true, true) => "paused, after the current sync",
+            (true, false) => "paused",
+            (false, true) => "syncing now",
+            (false, false) => "waiting",
+        };
+        println!("Syncing:            {syncing}");
+        let overridden = match control["interval_overridden"] == true {
+            true => " (set through the control api)",
+            false => "",
+        };
+        println!(
+            "Interval:           {} seconds{overridden}",
+            control["interval_seconds"]
+        );
+    }
+    if let Some(started_at) = state.started_at {
+        println!("Started:            {}", format_time(&started_at));
+    }
+    if let Some(last_ip_changed_at) = state.last_ip_changed_at {
+        println!("Last IP change:     {}", format_time(&last_ip_changed_at));
+    }
+    match (&state.last_ip, state.last_ip_detected_at) {
+        (Some(ip), Some(detected_at)) => {
+            println!("Last detected IP:   {ip} at {}", format_time(&detected_at))
+        }
+        _ => println!("Last detected IP:   none yet"),
+    }
+    match state.next_run {
+        _ if control
+            .as_ref()
+            .is_some_and(|control| control["paused"] == true) =>
+        {
+            println!("Next scheduled run: none, syncing is paused")
+        }
+        Some(next_run) if state.is_daemon_running() => {
+            println!("Next scheduled run: {}", format_time(&next_run))
+        }
+        _ => println!("Next scheduled run: none"),
+    }
+    if state.records.is_empty() {
+        println!("No records have been synced yet");
+        return;
+    }
+    println!("Records:");
+    for record in state.records.values() {
+        println!("  {}", record.name);
+        match record.last_success {
+            Some(last_success) => println!("    Last success: {}", format_time(&last_success)),
+            None => println!("    Last success: never"),
+        }
+        match record.last_failure {
+            Some(last_failure) => {
+                println!("    Last failure: {}", format_time(&last_failure));
+                if let Some(last_error) = &record.last_error {
+                    println!("    Last error:   {last_error}");
+                }
+            }
+            None => println!("    Last failure: never"),
+        }
+    }
+}
+pub fn format_time(time: &DateTime<Utc>) -> String {
+    let seconds = Utc::now().signed_duration_since(*time).num_seconds();
+    let relative = if seconds < 0 {
+        format!("in {}", format_duration(-seconds))
+    } else {
+        format!("{} ago", format_duration(seconds))
+    };
+    format!("{} ({relative})", format_local(time))
+}
+fn format_duration(seconds: i64) -> String {
+    if seconds < 60 {
+        format!("{seconds} seconds")
+    } else if seconds < 60 * 60 {
+        format!("{} minutes", seconds / 60)
+    } else if seconds < 60 * 60 * 24 {
+        format!("{} hours", seconds / (60 * 60))
+    } else {
+        format!("{} days", seconds / (60 * 60 * 24))
+    }
+}
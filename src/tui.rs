@@ -0,0 +1,386 @@
+use std::process;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use serde_json::{json, Value};
+
+use crate::control;
+use crate::environment;
+use crate::logs;
+use crate::state::{format_time, State};
+use crate::unending_process::{
+    is_terminal, read_config_file, read_incomplete_config_file, try_lock_config_file, DNSRecord,
+    IncompleteConfig,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const LOG_LINES: usize = 500;
+//Short, since the screen doesn't refresh while waiting
+const CONFIG_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct App {
+    config: IncompleteConfig,
+    state: State,
+    //The answer of the running daemon to the status command, None when it can't be reached
+    daemon: Option<Value>,
+    log_lines: Vec<String>,
+    //How many lines the log pane is scrolled up from the newest line
+    log_scroll: usize,
+    table_state: TableState,
+    message: String,
+    //The message is an error of reading the config file, cleared once it can be read again
+    config_failed: bool,
+}
+//Live view of the daemon for a server console. Changes go through the config file and the control api.
+pub fn tui() {
+    if !is_terminal() {
+        println!("The tui needs a terminal");
+        process::exit(1);
+    }
+    let mut app = App {
+        config: IncompleteConfig::from_defaults(),
+        state: State::default(),
+        daemon: None,
+        log_lines: vec![],
+        log_scroll: 0,
+        table_state: TableState::default().with_selected(Some(0)),
+        message: String::new(),
+        config_failed: false,
+    };
+    app.refresh();
+    let mut terminal = ratatui::init();
+    let mut last_refresh = Instant::now();
+    loop {
+        if terminal.draw(|frame| app.draw(frame)).is_err() {
+            break;
+        }
+        let timeout = REFRESH_INTERVAL.saturating_sub(last_refresh.elapsed());
+        if event::poll(timeout).unwrap_or(false) {
+            let key = match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Up | KeyCode::Char('k') => app.select(-1),
+                KeyCode::Down | KeyCode::Char('j') => app.select(1),
+                KeyCode::PageUp => app.log_scroll = app.log_scroll.saturating_add(10),
+                KeyCode::PageDown => app.log_scroll = app.log_scroll.saturating_sub(10),
+                KeyCode::End => app.log_scroll = 0,
+                KeyCode::Char(' ') | KeyCode::Enter => {
+                    app.toggle_sync();
+                    //Saving the config can log to the console, which would be left on the screen
+                    let _ = terminal.clear();
+                }
+                KeyCode::Char('s') => app.send_command("sync_now"),
+                KeyCode::Char('p') => {
+                    let paused = app
+                        .daemon
+                        .as_ref()
+                        .is_some_and(|daemon| daemon["paused"] == true);
+                    app.send_command(if paused { "resume" } else { "pause" });
+                }
+                KeyCode::Char('c') => {
+                    if environment::has_overrides() {
+                        app.message = "configure edits the config file, but CF_DNS_SYNC_* environment variables are overriding it".to_string();
+                    } else {
+                        //The records aren't fetched from cloudflare first like configure does, the daemon keeps them up to date
+                        match read_config_file() {
+                            Ok((config, config_path)) => {
+                                ratatui::restore();
+                                crate::main_selection(config, config_path);
+                                terminal = ratatui::init();
+                            }
+                            Err(err) => app.message = err.replace('\n', " "),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        app.refresh();
+        last_refresh = Instant::now();
+    }
+    ratatui::restore();
+}
+impl App {
+    fn refresh(&mut self) {
+        //A config file that can't be read keeps the last one on screen, the tui must never exit while it owns the terminal
+        match read_incomplete_config_file() {
+            Ok((config, _)) => {
+                self.config = config;
+                if self.config_failed {
+                    self.config_failed = false;
+                    self.message.clear();
+                }
+            }
+            Err(err) => {
+                self.config_failed = true;
+                self.message = err.replace('\n', " ");
+            }
+        }
+        self.daemon = match control::send_request(&json!({ "command": "status" })) {
+            Ok(response) if response["ok"] == true => Some(response),
+            _ => None,
+        };
+        self.state = match &self.daemon {
+            Some(daemon) => {
+                serde_json::from_value(daemon["state"].clone()).unwrap_or_else(|_| State::load())
+            }
+            None => State::load(),
+        };
+        let mut log_config = self.config.log_config.clone();
+        log_config.session_number = logs::get_latest_session_number(&log_config);
+        self.log_lines = logs::tail_session(&log_config, LOG_LINES);
+        let record_count = self.config.dns_config.len();
+        if let Some(selected) = self.table_state.selected() {
+            if selected >= record_count {
+                self.table_state
+                    .select(Some(record_count.saturating_sub(1)));
+            }
+        }
+    }
+    fn select(&mut self, offset: isize) {
+        let record_count = self.config.dns_config.len();
+        if record_count == 0 {
+            return;
+        }
+        let selected = self.table_state.selected().unwrap_or(0) as isize + offset;
+        self.table_state
+            .select(Some(selected.clamp(0, record_count as isize - 1) as usize));
+    }
+    fn send_command(&mut self, command: &str) {
+        self.message = match control::send_request(&json!({ "command": command })) {
+            Ok(response) if response["ok"] == true => {
+                response["message"].as_str().unwrap_or("Done").to_string()
+            }
+            Ok(response) => format!(
+                "The daemon refused: {}",
+                response["error"].as_str().unwrap_or("no reason given")
+            ),
+            Err(err) => format!("Couldn't reach the daemon, is it running? {err}"),
+        };
+    }
+    //Saves the flag like configure does, and has a running daemon reload the config file
+    fn toggle_sync(&mut self) {
+        let id = match self
+            .table_state
+            .selected()
+            .and_then(|selected| self.config.dns_config.get(selected))
+        {
+            Some(record) => record.id.clone(),
+            None => return,
+        };
+        if self.config.environment_overrides {
+            self.message = "CF_DNS_SYNC_* environment variables are overriding the config file, so it can't be edited".to_string();
+            return;
+        }
+        //Held until the config is saved, so a change made by another process in between isn't overwritten
+        let _config_file_lock = match try_lock_config_file(CONFIG_LOCK_TIMEOUT) {
+            Ok(config_file_lock) => config_file_lock,
+            Err(()) => {
+                self.message =
+                    "The config file is being written by another process, try again in a moment"
+                        .to_string();
+                return;
+            }
+        };
+        let (mut config, config_path) = match read_config_file() {
+            Ok(config) => config,
+            Err(err) => {
+                self.message = err.replace('\n', " ");
+                return;
+            }
+        };
+        let record = match config.dns_config.iter_mut().find(|record| record.id == id) {
+            Some(record) => record,
+            None => {
+                self.message = "The record isn't in the config file anymore".to_string();
+                return;
+            }
+        };
+        let sync = record.sync != Some(true);
+        record.sync = Some(sync);
+        let name = record.name.clone();
+        if config.save_to_file(&config_path).is_err() {
+            self.message = "Failed to save config".to_string();
+            return;
+        }
+        self.message = match sync {
+            true => format!("{name} is synced now"),
+            false => format!("{name} isn't synced anymore"),
+        };
+        if self.daemon.is_some() {
+            let _ = control::send_request(&json!({ "command": "reload" }));
+        }
+    }
+    fn draw(&mut self, frame: &mut Frame) {
+        let [status_area, records_area, logs_area, help_area] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Length(self.config.dns_config.len() as u16 + 3),
+            Constraint::Min(5),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+        frame.render_widget(
+            Paragraph::new(self.get_status_lines()).block(Block::bordered().title(" cf_dns_sync ")),
+            status_area,
+        );
+        let rows: Vec<Row> = self
+            .config
+            .dns_config
+            .iter()
+            .map(|record| self.get_record_row(record))
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(4),
+                Constraint::Fill(2),
+                Constraint::Length(5),
+                Constraint::Fill(2),
+                Constraint::Length(7),
+                Constraint::Length(5),
+                Constraint::Fill(3),
+            ],
+        )
+        .header(
+            Row::new([
+                "Sync",
+                "Name",
+                "Type",
+                "Content",
+                "Proxied",
+                "TTL",
+                "Last sync",
+            ])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(" Records "));
+        frame.render_stateful_widget(table, records_area, &mut self.table_state);
+        //The pane shows the newest lines, unless it was scrolled up
+        let visible_lines = logs_area.height.saturating_sub(2) as usize;
+        let max_scroll = self.log_lines.len().saturating_sub(visible_lines);
+        self.log_scroll = self.log_scroll.min(max_scroll);
+        let first_line = max_scroll - self.log_scroll;
+        let log_text: Vec<Line> = self.log_lines[first_line..]
+            .iter()
+            .take(visible_lines)
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        let log_title = match self.log_scroll {
+            0 => " Log ".to_string(),
+            scroll => format!(" Log ({scroll} lines up, End to follow) "),
+        };
+        frame.render_widget(
+            Paragraph::new(log_text).block(Block::bordered().title(log_title)),
+            logs_area,
+        );
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::raw(self.message.as_str()).style(Style::new().fg(Color::Yellow)),
+                Line::raw("↑/↓ select  space toggle sync  s sync now  p pause/resume  c configure  PgUp/PgDn scroll log  q quit"),
+            ]),
+            help_area,
+        );
+    }
+    fn get_status_lines(&self) -> Vec<Line<'static>> {
+        let daemon = match (&self.daemon, self.state.is_daemon_running(), self.state.pid) {
+            (Some(daemon), _, pid) => {
+                let syncing = match (daemon["paused"] == true, daemon["syncing"] == true) {
+                    (true, true) => "paused, after the current sync",
+                    (true, false) => "paused",
+                    (false, true) => "syncing now",
+                    (false, false) => "waiting",
+                };
+                format!(
+                    "running (pid {}), {syncing}, every {} seconds",
+                    pid.map(|pid| pid.to_string()).unwrap_or_default(),
+                    daemon["interval_seconds"]
+                )
+            }
+            (None, true, Some(pid)) => {
+                format!("running (pid {pid}), the control api can't be reached")
+            }
+            _ => "not running".to_string(),
+        };
+        let get_ip_line =
+            |ip: &Option<String>, detected_at: Option<DateTime<Utc>>| match (ip, detected_at) {
+                (Some(ip), Some(detected_at)) => {
+                    format!("{ip}, detected {}", format_time(&detected_at))
+                }
+                (Some(ip), None) => ip.clone(),
+                (None, _) => "not detected".to_string(),
+            };
+        let next_run = match (self.state.next_run, &self.daemon) {
+            (_, Some(daemon)) if daemon["paused"] == true => "none, syncing is paused".to_string(),
+            (Some(next_run), _) if self.state.is_daemon_running() => format_time(&next_run),
+            _ => "none".to_string(),
+        };
+        vec![
+            Line::raw(format!("Daemon:   {daemon}")),
+            Line::raw(format!(
+                "IPv4:     {}",
+                get_ip_line(&self.state.last_ipv4, self.state.last_ipv4_detected_at)
+            )),
+            Line::raw(format!(
+                "IPv6:     {}",
+                get_ip_line(&self.state.last_ipv6, self.state.last_ipv6_detected_at)
+            )),
+            Line::raw(format!("Next run: {next_run}")),
+        ]
+    }
+    fn get_record_row(&self, record: &DNSRecord) -> Row<'static> {
+        let record_state = self.state.records.get(&record.id);
+        let last_success = record_state.and_then(|record_state| record_state.last_success);
+        let last_failure = record_state.and_then(|record_state| record_state.last_failure);
+        let last_sync = match (last_success, last_failure) {
+            (Some(success), Some(failure)) if failure > success => get_failure_cell(
+                &failure,
+                record_state.and_then(|state| state.last_error.as_deref()),
+            ),
+            (None, Some(failure)) => get_failure_cell(
+                &failure,
+                record_state.and_then(|state| state.last_error.as_deref()),
+            ),
+            (Some(success), _) => Cell::from(format!("ok {}", format_time(&success)))
+                .style(Style::new().fg(Color::Green)),
+            (None, None) => Cell::from("never"),
+        };
+        Row::new([
+            Cell::from(if record.sync == Some(true) {
+                "[x]"
+            } else {
+                "[ ]"
+            }),
+            Cell::from(record.name.clone()),
+            Cell::from(record.record_type.clone()),
+            Cell::from(record.content.clone()),
+            Cell::from(match record.proxy_status {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "-",
+            }),
+            Cell::from(match record.ttl {
+                1 => "auto".to_string(),
+                ttl => ttl.to_string(),
+            }),
+            last_sync,
+        ])
+    }
+}
+fn get_failure_cell(failure: &DateTime<Utc>, error: Option<&str>) -> Cell<'static> {
+    let text = match error {
+        Some(error) => format!("failed {}: {error}", format_time(failure)),
+        None => format!("failed {}", format_time(failure)),
+    };
+    Cell::from(text).style(Style::new().fg(Color::Red))
+}
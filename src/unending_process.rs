@@ -10,18 +10,54 @@ use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::path::PathBuf;
 use std::process;
+use std::sync::OnceLock;
 use std::{io::Read, net::IpAddr, path::Path};
 use sysinfo::{System, SystemExt};
 
+use crate::config_file::{self, ConfigFormat, CONFIG_FILE_NAMES};
+use crate::control::{self, ControlConfig};
+use crate::dashboard::DashboardConfig;
+use crate::environment;
+use crate::health::{self, HealthConfig};
+use crate::hooks::{self, HooksConfig};
+use crate::http_server;
+use crate::log_rotation;
+use crate::log_sinks;
+use crate::metrics::{self, MetricsConfig};
+use crate::migrations::{self, MigrationReport, CURRENT_CONFIG_VERSION};
+use crate::mqtt::{self, MqttConfig};
+use crate::notifications::{self, Notification, NotificationEvent, NotificationsConfig};
+use crate::state::State;
+
+static CONFIG_PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+static CURRENT_SESSION_NUMBER: OnceLock<i32> = OnceLock::new();
 #[derive(Deserialize, Debug)]
 pub struct IncompleteConfig {
     #[serde(default = "default_stwpr")]
-    seconds_to_wait_per_restart: u32,
-    authentication: Option<AuthenticationConfig>,
+    pub seconds_to_wait_per_restart: u32,
+    pub authentication: Option<AuthenticationConfig>,
     #[serde(default = "default_log_config")]
-    log_config: LogConfig,
+    pub log_config: LogConfig,
     #[serde(default = "default_dns_config")]
-    dns_config: Vec<DNSRecord>,
+    pub dns_config: Vec<DNSRecord>,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(skip)]
+    pub environment_overrides: bool,
+    #[serde(skip)]
+    pub sync_record_names: Option<Vec<String>>,
 }
 fn default_stwpr() -> u32 {
     300
@@ -34,10 +70,31 @@ fn default_dns_config() -> Vec<DNSRecord> {
 }
 #[derive(Deserialize, Debug, Serialize, Clone)]
 pub struct Config {
+    pub version: u32,
     pub seconds_to_wait_per_restart: u32,
     pub authentication: AuthenticationConfig,
     pub log_config: LogConfig,
     pub dns_config: Vec<DNSRecord>,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    //Set when CF_DNS_SYNC_* variables changed any value, in which case the config is never written back to disk
+    #[serde(skip)]
+    pub environment_overrides: bool,
+    //Names of the records to sync from CF_DNS_SYNC_RECORDS, replacing the sync flags of the config file
+    #[serde(skip)]
+    pub sync_record_names: Option<Vec<String>>,
 }
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct AuthenticationConfig {
@@ -52,10 +109,15 @@ pub struct LogConfig {
     #[serde(default = "default_slbs")]
     pub separate_logs_by_session: bool,
     pub session_number: Option<i32>,
-    #[serde(default = "default_display_config")]
-    pub display: DisplayConfig,
-    #[serde(default = "default_show_config")]
-    pub show: ShowConfig,
+    #[serde(default = "default_sink_config")]
+    pub console: SinkConfig,
+    //Also used for syslog and journald
+    #[serde(default = "default_sink_config")]
+    pub file: SinkConfig,
+    #[serde(default = "default_rotation_config")]
+    pub rotation: RotationConfig,
+    #[serde(default = "default_outputs_config")]
+    pub outputs: OutputsConfig,
 }
 impl Default for LogConfig {
     fn default() -> Self {
@@ -63,18 +125,45 @@ impl Default for LogConfig {
             log_folder_path: get_log_folder(),
             separate_logs_by_session: default_slbs(),
             session_number: Some(1),
-            display: DisplayConfig::default(),
-            show: ShowConfig::default(),
+            console: SinkConfig::default(),
+            file: SinkConfig::default(),
+            rotation: RotationConfig::default(),
+            outputs: OutputsConfig::default(),
         }
     }
 }
 fn default_slbs() -> bool {
     true
 }
+fn default_sink_config() -> SinkConfig {
+    SinkConfig::default()
+}
+//The console and the log file are filtered and formatted independently, so one can stay quiet while the other keeps every detail
+#[derive(Deserialize, Debug, Clone, Serialize)]
+pub struct SinkConfig {
+    #[serde(default = "default_level")]
+    pub level: Verbosity,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default = "default_display_config")]
+    pub display: DisplayConfig,
+}
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig {
+            level: default_level(),
+            format: LogFormat::default(),
+            display: DisplayConfig::default(),
+        }
+    }
+}
+fn default_level() -> Verbosity {
+    Verbosity::Info
+}
 fn default_display_config() -> DisplayConfig {
     DisplayConfig::default()
 }
-#[derive(Deserialize, Debug, Clone, Copy, Serialize)]
+#[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct DisplayConfig {
     #[serde(default = "default_display")]
     pub date: bool,
@@ -82,6 +171,13 @@ pub struct DisplayConfig {
     pub time: bool,
     #[serde(default = "default_display")]
     pub log_type: bool,
+    //A strftime format like "%Y-%m-%d %H:%M:%S%.3f" that replaces the date, time and milliseconds settings
+    #[serde(default)]
+    pub timestamp_format: Option<String>,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub milliseconds: bool,
 }
 impl Default for DisplayConfig {
     fn default() -> Self {
@@ -89,34 +185,148 @@ impl Default for DisplayConfig {
             date: true,
             time: true,
             log_type: true,
+            timestamp_format: None,
+            timezone: default_timezone(),
+            milliseconds: false,
         }
     }
 }
 fn default_display() -> bool {
     true
 }
-fn default_show_config() -> ShowConfig {
-    ShowConfig::default()
+fn default_timezone() -> String {
+    "local".to_string()
+}
+pub enum LogTimezone {
+    Local,
+    Utc,
+    Named(chrono_tz::Tz),
+}
+impl LogTimezone {
+    //Accepts "local", "utc" or an IANA name like "Europe/Berlin"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "local" => Some(LogTimezone::Local),
+            "utc" => Some(LogTimezone::Utc),
+            _ => name.trim().parse().ok().map(LogTimezone::Named),
+        }
+    }
+    pub fn time_of_day(&self, time: DateTime<Utc>) -> NaiveTime {
+        match self {
+            LogTimezone::Local => time.with_timezone(&Local).time(),
+            LogTimezone::Utc => time.time(),
+            LogTimezone::Named(timezone) => time.with_timezone(timezone).time(),
+        }
+    }
+}
+//chrono panics while formatting an invalid format string, so formats from the config are checked first
+pub fn is_valid_timestamp_format(format: &str) -> bool {
+    !format::StrftimeItems::new(format).any(|item| matches!(item, format::Item::Error))
 }
+//An invalid timezone falls back to local time, the validate command reports it
+pub fn format_now(format: &str, timezone: &str) -> String {
+    let now = Utc::now();
+    match LogTimezone::parse(timezone).unwrap_or(LogTimezone::Local) {
+        LogTimezone::Local => now.with_timezone(&Local).format(format).to_string(),
+        LogTimezone::Utc => now.format(format).to_string(),
+        LogTimezone::Named(timezone) => now.with_timezone(&timezone).format(format).to_string(),
+    }
+}
+pub fn get_timestamp_format(display: &DisplayConfig) -> String {
+    if let Some(format) = &display.timestamp_format {
+        if is_valid_timestamp_format(format) {
+            return format.clone();
+        }
+    }
+    let seconds = if display.milliseconds { "%S%.3f" } else { "%S" };
+    match (display.date, display.time) {
+        (true, true) => format!("%d/%m/%Y %H:%M:{seconds}"),
+        (true, false) => "%d/%m/%Y".to_string(),
+        (false, true) => format!("%H:%M:{seconds}"),
+        (false, false) => "".to_string(),
+    }
+}
+//Json writes one object per line, for log shippers like Loki or Elasticsearch
+#[derive(Deserialize, Debug, Clone, Copy, Serialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+impl std::str::FromStr for LogFormat {
+    type Err = ();
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+fn default_outputs_config() -> OutputsConfig {
+    OutputsConfig::default()
+}
+//Where lines go besides the console. Syslog and journald are only available on unix.
+#[derive(Deserialize, Debug, Clone, Copy, Serialize)]
+pub struct OutputsConfig {
+    #[serde(default = "default_file_output")]
+    pub file: bool,
+    #[serde(default)]
+    pub syslog: bool,
+    #[serde(default)]
+    pub journald: bool,
+}
+impl Default for OutputsConfig {
+    fn default() -> Self {
+        OutputsConfig {
+            file: default_file_output(),
+            syslog: false,
+            journald: false,
+        }
+    }
+}
+fn default_file_output() -> bool {
+    true
+}
+fn default_rotation_config() -> RotationConfig {
+    RotationConfig::default()
+}
+//A limit of None (or 0) disables that kind of rotation or retention
 #[derive(Deserialize, Debug, Clone, Copy, Serialize)]
-pub struct ShowConfig {
-    #[serde(default = "default_show")]
-    pub logs: bool,
-    #[serde(default = "default_show")]
-    pub warnings: bool,
-    #[serde(default = "default_show")]
-    pub errors: bool,
-}
-impl Default for ShowConfig {
+pub struct RotationConfig {
+    #[serde(default = "default_max_file_size_kb")]
+    pub max_file_size_kb: Option<u64>,
+    #[serde(default)]
+    pub max_file_age_hours: Option<u64>,
+    #[serde(default = "default_compress")]
+    pub compress: bool,
+    #[serde(default)]
+    pub keep_sessions: Option<u32>,
+    #[serde(default)]
+    pub keep_days: Option<u32>,
+    #[serde(default = "default_max_history_size_kb")]
+    pub max_history_size_kb: Option<u64>,
+}
+impl Default for RotationConfig {
     fn default() -> Self {
-        ShowConfig {
-            logs: true,
-            warnings: true,
-            errors: true,
+        RotationConfig {
+            max_file_size_kb: default_max_file_size_kb(),
+            max_file_age_hours: None,
+            compress: default_compress(),
+            keep_sessions: None,
+            keep_days: None,
+            max_history_size_kb: default_max_history_size_kb(),
         }
     }
 }
-fn default_show() -> bool {
+fn default_max_file_size_kb() -> Option<u64> {
+    Some(10 * 1024)
+}
+fn default_max_history_size_kb() -> Option<u64> {
+    Some(1024)
+}
+fn default_compress() -> bool {
     true
 }
 #[derive(Deserialize, Debug, Clone, Serialize)]
@@ -132,6 +342,7 @@ pub struct DNSRecord {
 impl Config {
     fn default() -> Result<Self, ()> {
         Ok(Config {
+            version: CURRENT_CONFIG_VERSION,
             seconds_to_wait_per_restart: 300,
             authentication: match AuthenticationConfig::default() {
                 Ok(authentication) => authentication,
@@ -139,24 +350,65 @@ impl Config {
             },
             log_config: LogConfig::default(),
             dns_config: vec![],
+            notifications: NotificationsConfig::default(),
+            hooks: HooksConfig::default(),
+            metrics: MetricsConfig::default(),
+            health: HealthConfig::default(),
+            control: ControlConfig::default(),
+            dashboard: DashboardConfig::default(),
+            mqtt: MqttConfig::default(),
+            environment_overrides: false,
+            sync_record_names: None,
         })
     }
-    pub fn save_to_json(&self, path: &Path) -> Result<(), ()> {
+    pub fn save_to_file(&self, path: &Path) -> Result<(), ()> {
+        if self.environment_overrides {
+            return Ok(());
+        }
+        let format = ConfigFormat::from_path(path);
+        let previous_contents = fs::read_to_string(path).ok();
+        //Rewriting an unchanged config would throw away the comments of hand maintained toml and yaml files
+        if let Some(previous_contents) = &previous_contents {
+            if config_file::is_same_content(self, previous_contents, format) {
+                return Ok(());
+            }
+        }
+        let string = match config_file::serialize(self, format, previous_contents.as_deref()) {
+            Ok(string) => string,
+            Err(err) => {
+                log_to_file_and_console(
+                    &format!(
+                        "Failed to convert new config file to {}{}",
+                        format.name(),
+                        err
+                    ),
+                    LogType::Error,
+                    &self.log_config,
+                );
+                return Err(());
+            }
+        };
+        //The file is about to be replaced by the migrated config, so the older layout is backed up first
+        if let Some(previous_contents) = &previous_contents {
+            if let Ok((_, Some(report))) = parse_config_file(path, previous_contents) {
+                report_migration(&report, path, &self.log_config);
+            }
+        }
         if path.exists() {
             match std::fs::remove_file(path) {
                 Ok(()) => {}
-                Err(err) => panic!("Failed to delete old config file{}", format_err(err)),
+                Err(err) => {
+                    log_to_file_and_console(
+                        &format!("Failed to delete old config file{}", format_err(err)),
+                        LogType::Error,
+                        &self.log_config,
+                    );
+                    return Err(());
+                }
             }
         }
-        let string = match serde_json::to_string(&self) {
-            Ok(string) => string,
-            Err(err) => panic!(
-                "Failed to convert new config file to string{}",
-                format_err(err)
-            ),
-        };
-        let string = jsonformat::format(&string, jsonformat::Indentation::Tab);
-        let string = string.replace("\\/", "/");
+        let string = string.trim_end().to_string();
+        create_parent_folder(path)?;
         match write_to_file(path, string, None) {
             Ok(()) => Ok(()),
             Err(()) => Err(()),
@@ -168,6 +420,15 @@ impl Config {
             authentication: Some(self.authentication.clone()),
             log_config: self.log_config.clone(),
             dns_config: self.dns_config.clone(),
+            notifications: self.notifications.clone(),
+            hooks: self.hooks.clone(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            control: self.control.clone(),
+            dashboard: self.dashboard.clone(),
+            mqtt: self.mqtt.clone(),
+            environment_overrides: self.environment_overrides,
+            sync_record_names: self.sync_record_names.clone(),
         }
     }
 }
@@ -213,6 +474,23 @@ impl AuthenticationConfig {
     }
 }
 impl IncompleteConfig {
+    pub fn from_defaults() -> Self {
+        IncompleteConfig {
+            seconds_to_wait_per_restart: default_stwpr(),
+            authentication: None,
+            log_config: default_log_config(),
+            dns_config: default_dns_config(),
+            notifications: NotificationsConfig::default(),
+            hooks: HooksConfig::default(),
+            metrics: MetricsConfig::default(),
+            health: HealthConfig::default(),
+            control: ControlConfig::default(),
+            dashboard: DashboardConfig::default(),
+            mqtt: MqttConfig::default(),
+            environment_overrides: false,
+            sync_record_names: None,
+        }
+    }
     fn is_complete(&mut self) -> bool {
         let mut is_complete = true;
         match self.authentication {
@@ -224,7 +502,7 @@ impl IncompleteConfig {
     fn complete(&mut self) -> Result<Config, ()> {
         if !self.is_complete() {
             if !is_terminal() {
-                println!("Couldn't setup config because process is not running in a terminal. Please configure manually before running.");
+                println!("Couldn't setup config because process is not running in a terminal. Please configure manually before running, or set CF_DNS_SYNC_EMAIL, CF_DNS_SYNC_API_KEY and CF_DNS_SYNC_ZONE_ID.");
                 process::exit(0);
             }
         }
@@ -236,10 +514,20 @@ impl IncompleteConfig {
             },
         };
         let config = Config {
+            version: CURRENT_CONFIG_VERSION,
             seconds_to_wait_per_restart: self.seconds_to_wait_per_restart,
             authentication,
             log_config: self.log_config.clone(),
             dns_config: self.dns_config.clone(),
+            notifications: self.notifications.clone(),
+            hooks: self.hooks.clone(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            control: self.control.clone(),
+            dashboard: self.dashboard.clone(),
+            mqtt: self.mqtt.clone(),
+            environment_overrides: self.environment_overrides,
+            sync_record_names: self.sync_record_names.clone(),
         };
         Ok(config)
     }
@@ -248,97 +536,380 @@ impl IncompleteConfig {
 enum CustomError {
     ConvertIntoString,
     UnsuccessfullCloudflareRequest(String),
-    UReqRequstFailed(ureq::Error),
+    UReqRequstFailed(FailedRequest),
+}
+//A failed request, with the body of an error response already read so it can be both logged and reported
+struct FailedRequest {
+    error: String,
+    status: Option<u16>,
+    body: String,
 }
 #[derive(Clone, Copy)]
 pub enum LogType {
     Log,
     Warn,
     Error,
+    Debug,
+    Trace,
+}
+impl LogType {
+    fn verbosity(self) -> Verbosity {
+        match self {
+            LogType::Error => Verbosity::Error,
+            LogType::Warn => Verbosity::Warn,
+            LogType::Log => Verbosity::Info,
+            LogType::Debug => Verbosity::Debug,
+            LogType::Trace => Verbosity::Trace,
+        }
+    }
+}
+//The most detailed type of line that is logged
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl std::str::FromStr for Verbosity {
+    type Err = ();
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.to_lowercase().as_str() {
+            "off" => Ok(Verbosity::Off),
+            "error" => Ok(Verbosity::Error),
+            "warn" => Ok(Verbosity::Warn),
+            "info" => Ok(Verbosity::Info),
+            "debug" => Ok(Verbosity::Debug),
+            "trace" => Ok(Verbosity::Trace),
+            _ => Err(()),
+        }
+    }
+}
+//-v, -vv and RUST_LOG change the levels for one run without editing the config file
+static VERBOSITY_OVERRIDE: OnceLock<Option<Verbosity>> = OnceLock::new();
+pub fn set_verbosity(verbosity: Verbosity) {
+    let _ = VERBOSITY_OVERRIDE.set(Some(verbosity));
+}
+fn get_verbosity_override() -> Option<Verbosity> {
+    *VERBOSITY_OVERRIDE.get_or_init(|| match std::env::var("RUST_LOG") {
+        Ok(value) => parse_rust_log(&value),
+        Err(_) => None,
+    })
+}
+//The override replaces the console level, but can only raise the file level so that the file keeps its detail
+fn get_console_level(log_config: &LogConfig) -> Verbosity {
+    get_verbosity_override().unwrap_or(log_config.console.level)
+}
+fn get_file_level(log_config: &LogConfig) -> Verbosity {
+    match get_verbosity_override() {
+        Some(verbosity) if verbosity > log_config.file.level => verbosity,
+        _ => log_config.file.level,
+    }
+}
+pub fn is_log_type_enabled(log_type: LogType, log_config: &LogConfig) -> bool {
+    let verbosity = log_type.verbosity();
+    verbosity <= get_console_level(log_config) || verbosity <= get_file_level(log_config)
+}
+//Accepts RUST_LOG values like "debug" or "warn,cf_dns_sync=trace". A directive for cf_dns_sync wins over a global one, directives for other crates are ignored.
+pub fn parse_rust_log(value: &str) -> Option<Verbosity> {
+    let mut global = None;
+    let mut own = None;
+    for directive in value.split(',') {
+        let directive = directive.trim();
+        let (target, level) = match directive.split_once('=') {
+            Some((target, level)) => (Some(target.trim()), level.trim()),
+            None => (None, directive),
+        };
+        let verbosity: Verbosity = match level.parse() {
+            Ok(verbosity) => verbosity,
+            Err(()) => continue,
+        };
+        match target {
+            None => global = Some(verbosity),
+            Some("cf_dns_sync") => own = Some(verbosity),
+            Some(_) => {}
+        }
+    }
+    own.or(global)
 }
 #[tokio::main]
 pub async fn process() {
     check_for_root();
-    let (mut config, _) = get_config();
+    //The http server is started before the records are fetched, so the health endpoints answer while cloudflare can't be reached
+    let (mut config, config_path) = {
+        let _config_file_lock = lock_config_file();
+        load_config()
+    };
+    control::apply(&mut config);
+    health::set_interval(config.seconds_to_wait_per_restart);
+    let mut state = State::load();
+    state.pid = Some(process::id());
+    state.started_at = Some(Utc::now());
+    state.next_run = None;
+    state.save(&config.log_config);
+    metrics::load_state(&state);
+    http_server::start(&config);
+    control::start(&config);
+    mqtt::start(&config);
+    retrieve_dns_records(&mut config, &config_path);
+    control::apply(&mut config);
+    mqtt::publish_record_discovery(&config);
     let mut wait_on_startup = true;
+    //Every failed run is reported with the time the failures started, so each notifier can decide when it's worth telling
+    let mut failing_since: Option<DateTime<Utc>> = None;
     loop {
         if wait_on_startup {
             wait_on_startup = false;
         } else {
-            log_to_file_and_console(
+            log_event(
                 &format!(
                     "Waiting {} seconds to restart...",
                     config.seconds_to_wait_per_restart
                 ),
                 LogType::Log,
+                &LogDetails::event("waiting"),
                 &config.log_config,
             );
-            std::thread::sleep(std::time::Duration::from_secs_f32(
-                config.seconds_to_wait_per_restart as f32,
-            ));
+            state.next_run =
+                Some(Utc::now() + Duration::seconds(config.seconds_to_wait_per_restart as i64));
+            state.save(&config.log_config);
+            let wait_start = std::time::Instant::now();
+            //The control api can start a sync early, or reload the config while waiting
+            while let control::Wake::Reload =
+                control::wait(wait_start, config.seconds_to_wait_per_restart)
+            {
+                config = {
+                    let _config_file_lock = lock_config_file();
+                    load_config().0
+                };
+                control::apply(&mut config);
+                mqtt::publish_record_discovery(&config);
+                health::set_interval(config.seconds_to_wait_per_restart);
+                log_to_file_and_console(
+                    "Reloaded the config file",
+                    LogType::Log,
+                    &config.log_config,
+                );
+            }
             config = get_config().0;
+            control::apply(&mut config);
+            mqtt::publish_record_discovery(&config);
+            health::set_interval(config.seconds_to_wait_per_restart);
         }
+        let ip_detection_start = std::time::Instant::now();
         let ip = match public_ip::addr().await {
             Some(_ip_addr) => {
+                let ip_string = _ip_addr.to_string();
                 log_to_file_and_console(
+                    &format!(
+                        "Public ip address {ip_string} was detected in {} ms",
+                        ip_detection_start.elapsed().as_millis()
+                    ),
+                    LogType::Debug,
+                    &config.log_config,
+                );
+                log_event(
                     "Successfully obtained public ip address",
                     LogType::Log,
+                    &LogDetails::event("ip_detected")
+                        .new_content(&ip_string)
+                        .duration(ip_detection_start.elapsed()),
                     &config.log_config,
                 );
+                let previous_ip = state.set_ip(ip_string.clone(), &config.log_config);
+                metrics::with_metrics(|metrics| metrics.set_ip(&ip_string, previous_ip.is_some()));
+                mqtt::publish_ip(&ip_string);
+                if let Some(previous_ip) = previous_ip {
+                    log_event(
+                        &format!("Public ip address changed from {previous_ip} to {_ip_addr}"),
+                        LogType::Log,
+                        &LogDetails::event("ip_changed")
+                            .old_content(&previous_ip)
+                            .new_content(&ip_string),
+                        &config.log_config,
+                    );
+                    notifications::notify(
+                        Notification::new(
+                            NotificationEvent::IpChanged,
+                            format!("Public ip address changed from {previous_ip} to {ip_string}"),
+                        )
+                        .old_ip(&previous_ip)
+                        .new_ip(&ip_string),
+                        &config.notifications,
+                        &config.log_config,
+                    );
+                }
+                state.save(&config.log_config);
                 _ip_addr
             }
             None => {
-                log_to_file_and_console(
+                log_event(
                     "Couldn't get public ip address",
                     LogType::Error,
+                    &LogDetails::event("ip_detection_failed")
+                        .duration(ip_detection_start.elapsed()),
                     &config.log_config,
                 );
                 log_to_file_and_console("Retrying...", LogType::Error, &config.log_config);
+                metrics::with_metrics(|metrics| metrics.count_sync_cycle(false));
+                mqtt::publish_sync(false);
+                notifications::notify(
+                    Notification::new(
+                        NotificationEvent::SyncFailed,
+                        "Couldn't get public ip address".to_string(),
+                    )
+                    .error("Couldn't get public ip address".to_string())
+                    .failing_since(*failing_since.get_or_insert_with(Utc::now)),
+                    &config.notifications,
+                    &config.log_config,
+                );
                 continue;
             }
         };
         let mut failures = false;
         let mut records_changed_successfully = 0;
+        let mut updated_records = vec![];
+        let mut failed_records = vec![];
+        let mut vetoed_records = vec![];
+        let mut errors = vec![];
         for record in config.dns_config.iter() {
             if let Some(true) = record.sync {
-                match set_ip(
+                let ip_string = ip.to_string();
+                //Every run sets every record, but the hooks only run when the ip address of the record changes
+                let is_change = record.content != ip_string;
+                if is_change {
+                    if let Err(reason) = hooks::run_pre_update_hooks(
+                        &config.hooks,
+                        record,
+                        &ip_string,
+                        &config.log_config,
+                    ) {
+                        log_event(
+                            &format!("Didn't update {}, {reason}", record.name),
+                            LogType::Warn,
+                            &LogDetails::event("update_vetoed")
+                                .record(record)
+                                .new_content(&ip_string),
+                            &config.log_config,
+                        );
+                        metrics::with_metrics(|metrics| {
+                            metrics.count_record_update(&record.name, "vetoed")
+                        });
+                        mqtt::publish_record(
+                            record,
+                            &record.content,
+                            "vetoed",
+                            state.records.get(&record.id),
+                        );
+                        vetoed_records.push(record.name.clone());
+                        continue;
+                    }
+                }
+                let update_start = std::time::Instant::now();
+                let result = set_ip(
                     &ip,
                     &record.name,
                     &record.id,
                     &config.authentication,
                     &config.log_config,
-                ) {
+                );
+                let success = result.is_ok();
+                metrics::with_metrics(|metrics| {
+                    metrics.count_record_update(
+                        &record.name,
+                        if success { "success" } else { "failure" },
+                    )
+                });
+                match result {
                     Ok(()) => {
-                        log_to_file_and_console(
+                        log_event(
                             &format!("Successfully set ip for {}", &record.name),
                             LogType::Log,
+                            &LogDetails::event("record_updated")
+                                .record(record)
+                                .new_content(&ip_string)
+                                .duration(update_start.elapsed()),
                             &config.log_config,
                         );
+                        state.record_attempt(record, &ip.to_string(), None, &config.log_config);
                         records_changed_successfully += 1;
+                        //Setting the content a record already has isn't an update worth notifying about
+                        if is_change {
+                            updated_records.push(record.name.clone());
+                        }
                     }
                     Err(err) => match err {
                         CustomError::ConvertIntoString => {
-                            log_to_file_and_console(
+                            log_event(
                                 "Failed to convert cloudflare's result into a string, retrying...",
                                 LogType::Warn,
+                                &LogDetails::event("record_update_failed")
+                                    .record(record)
+                                    .new_content(&ip_string)
+                                    .duration(update_start.elapsed()),
                                 &config.log_config,
                             );
+                            let error =
+                                "Failed to convert cloudflare's result into a string".to_string();
+                            state.record_attempt(
+                                record,
+                                &ip.to_string(),
+                                Some(error.clone()),
+                                &config.log_config,
+                            );
+                            failed_records.push(record.name.clone());
+                            errors.push(format!("{}: {error}", record.name));
                             failures = true;
-                            continue;
                         }
                         CustomError::UnsuccessfullCloudflareRequest(string) => {
-                            log_to_file_and_console(
+                            report_api_errors(None, &string);
+                            log_event(
                                 &format!("The cloudflare request was unsuccessful. Here's the result:\n{string}"),
                                 LogType::Warn,
+                                &LogDetails::event("api_error")
+                                    .record(record)
+                                    .new_content(&ip_string)
+                                    .duration(update_start.elapsed()),
                                 &config.log_config,
                             );
+                            let error = format!(
+                                "The cloudflare request was unsuccessful: {}",
+                                string.replace(['\n', '\t'], "")
+                            );
+                            state.record_attempt(
+                                record,
+                                &ip.to_string(),
+                                Some(error.clone()),
+                                &config.log_config,
+                            );
+                            failed_records.push(record.name.clone());
+                            errors.push(format!("{}: {error}", record.name));
                             failures = true;
-                            continue;
                         }
-                        CustomError::UReqRequstFailed(err) => {
-                            log_to_file_and_console(
-                                &format!("The ureq request failed{}", format_err(err)),
+                        CustomError::UReqRequstFailed(failed_request) => {
+                            let error =
+                                format!("The ureq request failed: {}", failed_request.error);
+                            state.record_attempt(
+                                record,
+                                &ip.to_string(),
+                                Some(error.clone()),
+                                &config.log_config,
+                            );
+                            failed_records.push(record.name.clone());
+                            errors.push(format!("{}: {error}", record.name));
+                            log_event(
+                                &format!(
+                                    "The ureq request failed{}",
+                                    format_err(&failed_request.error)
+                                ),
                                 LogType::Error,
+                                &LogDetails::event("record_update_failed")
+                                    .record(record)
+                                    .new_content(&ip_string)
+                                    .duration(update_start.elapsed()),
                                 &config.log_config,
                             );
                             failures = true;
@@ -347,41 +918,126 @@ pub async fn process() {
                                 LogType::Error,
                                 &config.log_config,
                             );
-                            continue;
+                            report_failed_request(&failed_request);
                         }
                     },
                 }
+                mqtt::publish_record(
+                    record,
+                    if success { &ip_string } else { &record.content },
+                    if success { "success" } else { "failure" },
+                    state.records.get(&record.id),
+                );
+                if is_change {
+                    hooks::run_post_update_hooks(
+                        &config.hooks,
+                        record,
+                        &ip_string,
+                        success,
+                        &config.log_config,
+                    );
+                }
             }
         }
+        state.save(&config.log_config);
+        //A vetoed record isn't synced, so a run with vetoes doesn't count as a successful one
+        let succeeded = !failures && vetoed_records.is_empty();
+        metrics::with_metrics(|metrics| metrics.count_sync_cycle(succeeded));
+        mqtt::publish_sync(succeeded);
+        if succeeded {
+            health::sync_succeeded();
+        }
+        if !vetoed_records.is_empty() {
+            let synced_records = config
+                .dns_config
+                .iter()
+                .filter(|record| record.sync == Some(true))
+                .count();
+            log_event(
+                &format!(
+                    "{} out of {} records weren't updated, a pre_update hook vetoed them: {}",
+                    vetoed_records.len(),
+                    synced_records,
+                    vetoed_records.join(", ")
+                ),
+                LogType::Warn,
+                &LogDetails::event("sync_summary"),
+                &config.log_config,
+            );
+        }
         if failures {
             if records_changed_successfully > 0 {
-                log_to_file_and_console(
+                log_event(
                     &format!(
                         "Only {} out of {} records were changed successfully",
                         records_changed_successfully,
                         config.dns_config.len()
                     ),
                     LogType::Warn,
+                    &LogDetails::event("sync_summary"),
                     &config.log_config,
                 );
             } else {
-                log_to_file_and_console(
+                log_event(
                     "All record changes failed",
                     LogType::Warn,
+                    &LogDetails::event("sync_summary"),
                     &config.log_config,
                 );
             }
-        } else {
+        } else if vetoed_records.is_empty() {
             if records_changed_successfully > 0 {
-                log_to_file_and_console(
+                log_event(
                     "All records changed successfully!",
                     LogType::Log,
+                    &LogDetails::event("sync_summary"),
                     &config.log_config,
                 );
             } else {
-                log_to_file_and_console(
+                log_event(
                     "No records were changed",
                     LogType::Log,
+                    &LogDetails::event("sync_summary"),
+                    &config.log_config,
+                );
+            }
+        }
+        if !updated_records.is_empty() {
+            notifications::notify(
+                Notification::new(
+                    NotificationEvent::RecordUpdated,
+                    format!("Set {ip} for {}", updated_records.join(", ")),
+                )
+                .new_ip(&ip.to_string())
+                .records(updated_records),
+                &config.notifications,
+                &config.log_config,
+            );
+        }
+        if failures {
+            notifications::notify(
+                Notification::new(
+                    NotificationEvent::SyncFailed,
+                    format!("Couldn't set {ip} for {}", failed_records.join(", ")),
+                )
+                .new_ip(&ip.to_string())
+                .records(failed_records)
+                .error(errors.join("\n"))
+                .failing_since(*failing_since.get_or_insert_with(Utc::now)),
+                &config.notifications,
+                &config.log_config,
+            );
+        } else if succeeded {
+            //A run with a vetoed record hasn't recovered yet, so the failure is kept until every record is synced
+            if let Some(since) = failing_since.take() {
+                notifications::notify(
+                    Notification::new(
+                        NotificationEvent::Recovered,
+                        "Records are being synced again".to_string(),
+                    )
+                    .new_ip(&ip.to_string())
+                    .failing_since(since),
+                    &config.notifications,
                     &config.log_config,
                 );
             }
@@ -389,13 +1045,73 @@ pub async fn process() {
     }
 }
 pub fn get_config() -> (Config, PathBuf) {
+    let (mut config, config_path) = {
+        let _config_file_lock = lock_config_file();
+        load_config()
+    };
+    retrieve_dns_records(&mut config, &config_path);
+    (config, config_path)
+}
+fn retrieve_dns_records(config: &mut Config, config_path: &Path) {
+    log_to_file_and_console(
+        "Attempting to retrieve DNS records",
+        LogType::Log,
+        &config.log_config,
+    );
+    update_dns_list(config, config_path);
+}
+//The config file is locked while it's read and written back, so a change from the dashboard, the tui or configure isn't
+//overwritten by another process. The lock is a file of its own, since saving replaces the config file, and it's
+//released when the returned file is dropped. Without a config folder there is nothing to protect, so there is no lock.
+fn open_config_lock_file() -> Option<File> {
+    let config_path = get_config_path().ok()?;
+    let file_name = config_path.file_name()?.to_string_lossy().into_owned();
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(config_path.with_file_name(format!(".{file_name}.lock")))
+        .ok()
+}
+pub fn lock_config_file() -> Option<File> {
+    let lock_file = open_config_lock_file()?;
+    lock_file.lock().ok()?;
+    Some(lock_file)
+}
+//Err when someone else kept the config file locked for longer than the timeout
+pub fn try_lock_config_file(timeout: std::time::Duration) -> Result<Option<File>, ()> {
+    let lock_file = match open_config_lock_file() {
+        Some(lock_file) => lock_file,
+        None => return Ok(None),
+    };
+    let start = std::time::Instant::now();
+    loop {
+        match lock_file.try_lock() {
+            Ok(()) => return Ok(Some(lock_file)),
+            Err(fs::TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            Err(fs::TryLockError::WouldBlock) => return Err(()),
+            Err(fs::TryLockError::Error(_)) => return Ok(None),
+        }
+    }
+}
+//Everything of get_config except fetching the records from cloudflare, which is retried until it works
+pub fn load_config() -> (Config, PathBuf) {
     let (mut incomplete_config, config_path, config_file_contents) = match get_incomplete_config() {
         Ok((incomplete_config, config_path, config_file_contents)) => {
             (incomplete_config, config_path, Some(config_file_contents))
         }
         Err(err) => match err.kind() {
             std::io::ErrorKind::NotFound => {
-                if is_terminal() {
+                if environment::has_authentication() {
+                    //Running statelessly (e.g. in a container), everything comes from the environment
+                    let config_path = match get_config_path() {
+                        Ok(config_path) => config_path,
+                        Err(()) => panic!("Failed to get config path"),
+                    };
+                    (IncompleteConfig::from_defaults(), config_path, None)
+                } else if is_terminal() {
                     let config = match Config::default() {
                         Ok(config) => config,
                         Err(()) => panic!("Failed to get config"),
@@ -404,32 +1120,30 @@ pub fn get_config() -> (Config, PathBuf) {
                         Ok(config_path) => config_path,
                         Err(()) => panic!("Failed to get config path"),
                     };
-                    if let Err(()) = config.save_to_json(&config_path) {
+                    if let Err(()) = config.save_to_file(&config_path) {
                         log_to_console("Failed to save config", LogType::Error, &config.log_config);
                     }
                     (config.to_incomplete(), config_path, None)
                 } else {
-                    println!("There is no config file yet. You must create one using the configure command in a terminal.");
+                    println!("There is no config file yet. You must create one using the configure command in a terminal, or set CF_DNS_SYNC_EMAIL, CF_DNS_SYNC_API_KEY and CF_DNS_SYNC_ZONE_ID.");
                     std::process::exit(0);
                 }
             }
             _ => panic!("Failed to get config"),
         },
     };
+    environment::apply_overrides(&mut incomplete_config);
     let mut config: Config;
     if !incomplete_config.is_complete() {
         config = match incomplete_config.complete() {
             Ok(config) => {
-                match config.save_to_json(&config_path) {
-                    Err(()) => {
-                        log_to_console(
-                            "Failed to save config file",
-                            LogType::Error,
-                            &config.log_config,
-                        );
-                    }
-                    _ => {}
-                };
+                if let Err(()) = config.save_to_file(&config_path) {
+                    log_to_console(
+                        "Failed to save config file",
+                        LogType::Error,
+                        &config.log_config,
+                    );
+                }
                 config
             }
             Err(()) => panic!("Failed to get config"),
@@ -438,38 +1152,21 @@ pub fn get_config() -> (Config, PathBuf) {
         config = match incomplete_config.complete() {
             Ok(config) => {
                 //Checks if the new config is any different to the one currently saved. If it is, it tries to save the new one.
-                match serde_json::to_string(&config) {
-                    Ok(new_config_file_contents) => {
-                        let should_try_saving = match config_file_contents {
-                            Some(config_file_contents) => {
-                                config_file_contents != new_config_file_contents
-                            }
-                            None => true,
-                        };
-                        if should_try_saving {
-                            match config.save_to_json(&config_path) {
-                                Err(()) => {
-                                    log_to_console(
-                                        "Failed to save config file",
-                                        LogType::Error,
-                                        &config.log_config,
-                                    );
-                                }
-                                _ => {}
-                            };
-                        }
-                    }
-                    Err(err) => {
-                        log_to_console(
-                            &format!(
-                                "Failed to convert new config to string and save config file{}",
-                                format_err(err)
-                            ),
-                            LogType::Error,
-                            &config.log_config,
-                        );
-                    }
+                let should_try_saving = match config_file_contents {
+                    Some(config_file_contents) => !config_file::is_same_content(
+                        &config,
+                        &config_file_contents,
+                        ConfigFormat::from_path(&config_path),
+                    ),
+                    None => true,
                 };
+                if should_try_saving && config.save_to_file(&config_path).is_err() {
+                    log_to_console(
+                        "Failed to save config file",
+                        LogType::Error,
+                        &config.log_config,
+                    );
+                }
                 config
             }
             Err(()) => panic!("Failed to get config"),
@@ -487,8 +1184,9 @@ pub fn get_config() -> (Config, PathBuf) {
             None
         }
     };
+    log_rotation::apply_retention(&config.log_config);
     if previous_session_number != config.log_config.session_number {
-        if let Err(()) = config.save_to_json(&config_path) {
+        if let Err(()) = config.save_to_file(&config_path) {
             log_to_console(
                 "Failed to save config file after changing session number",
                 LogType::Warn,
@@ -496,52 +1194,148 @@ pub fn get_config() -> (Config, PathBuf) {
             );
         }
     }
-    log_to_file_and_console(
-        "Attempting to retrieve DNS records",
-        LogType::Log,
-        &config.log_config,
-    );
-    update_dns_list(&mut config, &config_path);
     (config, config_path)
 }
+//For the dashboard and the tui, which must never prompt or exit: reads the config file without logging a
+//migration or saving anything, and fails instead of asking for what is missing
+pub fn read_config_file() -> Result<(Config, PathBuf), String> {
+    let (mut incomplete_config, config_path) = read_incomplete_config_file()?;
+    if !incomplete_config.is_complete() {
+        return Err(
+            "The config has no authentication yet, run the configure command first".to_string(),
+        );
+    }
+    match incomplete_config.complete() {
+        Ok(config) => Ok((config, config_path)),
+        Err(()) => Err("Couldn't complete the config".to_string()),
+    }
+}
+//Like read_config_file, but a config without authentication is fine and a missing file gives the defaults
+pub fn read_incomplete_config_file() -> Result<(IncompleteConfig, PathBuf), String> {
+    let config_path = match get_config_path() {
+        Ok(config_path) => config_path,
+        Err(()) => return Err("Couldn't determine where the config file is".to_string()),
+    };
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => Some(contents),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(format!(
+                "Couldn't read the config file at {}{}",
+                config_path.display(),
+                format_err(err)
+            ))
+        }
+    };
+    let mut incomplete_config = match contents {
+        Some(contents) => match parse_config_file(&config_path, &contents) {
+            Ok((incomplete_config, _)) => incomplete_config,
+            Err(err) => {
+                return Err(format!(
+                    "Couldn't read the config file at {}\n{err}",
+                    config_path.display()
+                ))
+            }
+        },
+        None => IncompleteConfig::from_defaults(),
+    };
+    if let Err(errors) = environment::try_apply_overrides(&mut incomplete_config) {
+        return Err(errors.join("\n"));
+    }
+    Ok((incomplete_config, config_path))
+}
+pub fn set_config_path_override(path: PathBuf) {
+    let _ = CONFIG_PATH_OVERRIDE.set(path);
+}
 pub fn get_config_path() -> Result<PathBuf, ()> {
-    let config_folder_path = match get_config_folder_path() {
-        Ok(config_folder_path) => config_folder_path,
-        Err(()) => return Err(()),
+    //An explicit path from --config or CF_DNS_SYNC_CONFIG always wins, even if the file doesn't exist yet
+    let explicit_path = match CONFIG_PATH_OVERRIDE.get() {
+        Some(path) => Some(path.clone()),
+        None => match std::env::var_os("CF_DNS_SYNC_CONFIG") {
+            Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+            _ => None,
+        },
     };
-    let config_path = config_folder_path.join("config.json");
-    Ok(config_path)
+    if let Some(config_path) = explicit_path {
+        return Ok(config_path);
+    }
+    let candidates = get_config_folder_candidates();
+    for folder_path in candidates.iter() {
+        for file_name in CONFIG_FILE_NAMES {
+            let config_path = folder_path.join(file_name);
+            if config_path.is_file() {
+                return Ok(config_path);
+            }
+        }
+    }
+    //No config exists anywhere yet, so a new one goes into the first candidate folder, which is created when it is saved
+    match candidates.first() {
+        Some(folder_path) => Ok(folder_path.join("config.json")),
+        None => Err(()),
+    }
+}
+//Only called before writing, so that commands which just read never create folders
+pub fn create_parent_folder(path: &Path) -> Result<(), ()> {
+    match path.parent() {
+        Some(folder_path) if !folder_path.as_os_str().is_empty() => {
+            create_folder(folder_path, None)
+        }
+        _ => Ok(()),
+    }
 }
 pub fn get_config_folder_path() -> Result<PathBuf, ()> {
-    let cargo_path = match home::cargo_home() {
-        Ok(cargo_path) => cargo_path,
-        Err(err) => panic!("Couldn't find cargo path{}", format_err(err)),
-    };
-    let user_path = match cargo_path.parent() {
-        Some(path) => path,
-        None => &cargo_path,
-    };
-    let config_path: PathBuf;
-    if user_path == cargo_path {
-        config_path = cargo_path.to_path_buf();
-    } else {
-        config_path = user_path.join(".config");
+    let config_path = get_config_path()?;
+    match config_path.parent() {
+        Some(folder_path) if !folder_path.as_os_str().is_empty() => Ok(folder_path.to_path_buf()),
+        _ => Ok(PathBuf::from("./")),
     }
-
-    let folder_path = config_path.join("cf_dns_sync");
-    if !folder_path.is_dir() {
-        if let Err(_) = create_folder(&folder_path, None) {
-            return Err(());
+}
+fn get_config_folder_candidates() -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = vec![];
+    if let Some(xdg_config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+        let xdg_config_home = PathBuf::from(xdg_config_home);
+        //The XDG spec says relative paths should be ignored
+        if xdg_config_home.is_absolute() {
+            candidates.push(xdg_config_home.join("cf_dns_sync"));
         }
     }
-    Ok(folder_path)
+    if let Some(home_path) = home_dir() {
+        candidates.push(home_path.join(".config").join("cf_dns_sync"));
+    }
+    //Older versions derived the folder from the cargo home, which differs when CARGO_HOME is customized
+    if let Ok(cargo_path) = home::cargo_home() {
+        let legacy_path = match cargo_path.parent() {
+            Some(user_path) => user_path.join(".config").join("cf_dns_sync"),
+            None => cargo_path.join("cf_dns_sync"),
+        };
+        if CONFIG_FILE_NAMES
+            .iter()
+            .any(|file_name| legacy_path.join(file_name).is_file())
+        {
+            candidates.push(legacy_path);
+        }
+    }
+    if cfg!(unix) {
+        candidates.push(PathBuf::from("/etc/cf_dns_sync"));
+    }
+    let mut unique_candidates: Vec<PathBuf> = vec![];
+    for candidate in candidates {
+        if !unique_candidates.contains(&candidate) {
+            unique_candidates.push(candidate);
+        }
+    }
+    unique_candidates
 }
+//The logs go into the user's state folder, since the config folder (like /etc/cf_dns_sync) often isn't writable by the daemon
 pub fn get_log_folder() -> String {
-    let config_folder_path = match get_config_folder_path() {
-        Ok(config_folder_path) => config_folder_path,
-        Err(()) => Path::new("./").into(),
+    let folder_path = match std::env::var_os("XDG_STATE_HOME").map(PathBuf::from) {
+        Some(state_home) if state_home.is_absolute() => state_home.join("cf_dns_sync"),
+        _ => match home_dir() {
+            Some(home_path) => home_path.join(".local").join("state").join("cf_dns_sync"),
+            None => PathBuf::from("./"),
+        },
     };
-    match config_folder_path.to_str() {
+    match folder_path.to_str() {
         Some(string) => string.into(),
         None => "./".into(),
     }
@@ -568,19 +1362,98 @@ pub fn get_incomplete_config() -> Result<(IncompleteConfig, PathBuf, String), st
         Err(err) => return Err(err),
         _ => {}
     };
-    incomplete_config = match serde_json::from_str(&config_file_contents) {
-        Ok(incomplete_config) => incomplete_config,
+    //A migration is only reported once the migrated config is saved, see save_to_file
+    (incomplete_config, _) = match parse_config_file(&config_path, &config_file_contents) {
+        Ok(result) => result,
         Err(err) => {
-            panic!(
-                "It looks like your config.json is not formatted corectly. Here's the path to the config file: {}{}",
-                format_err(&err),
-                config_path.clone().to_str().unwrap()
+            println!(
+                "Couldn't read the config file at {}\n{err}",
+                config_path.display()
             );
+            process::exit(1);
         }
     };
     Ok((incomplete_config, config_path, config_file_contents))
 }
+//Parses the contents of a config file in the format of its extension, upgrading older layouts to the current version
+pub fn parse_config_file(
+    config_path: &Path,
+    contents: &str,
+) -> Result<(IncompleteConfig, Option<MigrationReport>), String> {
+    let format = ConfigFormat::from_path(config_path);
+    let mut value: Value = match config_file::parse(contents, format) {
+        Ok(value) => value,
+        Err(err) => {
+            return Err(format!(
+                "It looks like your config file is not formatted correctly as {}:\n{err}",
+                format.name()
+            ))
+        }
+    };
+    let migration_report = migrations::migrate(&mut value)?;
+    let incomplete_config = match &migration_report {
+        None => config_file::parse(contents, format),
+        //If the migrated config is invalid, the original text is parsed as well, so that the error points at a line
+        Some(_) => serde_json::from_value(value).map_err(|err| {
+            match config_file::parse::<IncompleteConfig>(contents, format) {
+                Err(located_err) => located_err,
+                Ok(_) => err.to_string(),
+            }
+        }),
+    };
+    match incomplete_config {
+        Ok(incomplete_config) => Ok((incomplete_config, migration_report)),
+        Err(err) => Err(format!(
+            "Some settings in your config file have the wrong type or are missing:\n{err}"
+        )),
+    }
+}
+fn report_migration(report: &MigrationReport, config_path: &Path, log_config: &LogConfig) {
+    let mut message = format!(
+        "Migrated the config file from version {} to version {}:",
+        report.from_version, report.to_version
+    );
+    for description in report.descriptions.iter() {
+        message.push_str(&format!("\n - {description}"));
+    }
+    log_to_file_and_console(&message, LogType::Warn, log_config);
+    let mut backup_name = config_path.as_os_str().to_owned();
+    backup_name.push(format!(".v{}.bak", report.from_version));
+    let backup_path = PathBuf::from(backup_name);
+    if backup_path.exists() {
+        return;
+    }
+    match fs::copy(config_path, &backup_path) {
+        Ok(_) => log_to_file_and_console(
+            &format!(
+                "A backup of the previous config file was saved to {}",
+                backup_path.display()
+            ),
+            LogType::Warn,
+            log_config,
+        ),
+        Err(err) => log_to_file_and_console(
+            &format!(
+                "Couldn't back up the previous config file{}",
+                format_err(err)
+            ),
+            LogType::Warn,
+            log_config,
+        ),
+    }
+}
 pub fn get_session_number(log_config: &LogConfig) -> Option<i32> {
+    if !log_config.separate_logs_by_session {
+        return find_session_number(log_config);
+    }
+    //A running process keeps its session, even though the config is reloaded before every sync
+    if let Some(session_number) = CURRENT_SESSION_NUMBER.get() {
+        return Some(*session_number);
+    }
+    let session_number = find_session_number(log_config)?;
+    Some(*CURRENT_SESSION_NUMBER.get_or_init(|| session_number))
+}
+fn find_session_number(log_config: &LogConfig) -> Option<i32> {
     let folder_path = Path::new(&log_config.log_folder_path);
     let folder_path = folder_path.join("logs");
     if folder_path.is_dir() {
@@ -626,21 +1499,9 @@ pub fn get_session_number(log_config: &LogConfig) -> Option<i32> {
                     log_config,
                 ),
             };
-            if log_name.len() < 12 {
-                continue;
-            }
-            let session_indices: Vec<(usize, _)> = log_name.match_indices("session").collect();
-            if session_indices.len() > 1 {
-                continue;
-            } else if session_indices.len() == 0 {
-                continue;
-            }
-            if session_indices[0].0 != 0 {
-                continue;
-            }
-            let log_number = *match &log_name[7..log_name.len() - 4].parse::<i32>() {
-                Ok(num) => num,
-                Err(_) => continue,
+            let log_number = match log_rotation::parse_session_number(&log_name) {
+                Some(num) => num,
+                None => continue,
             };
             if Some(log_number) > highest_value {
                 highest_value = Some(log_number);
@@ -660,40 +1521,59 @@ pub fn get_session_number(log_config: &LogConfig) -> Option<i32> {
         return Some(1);
     }
 }
-pub fn log_to_console(string: &str, log_type: LogType, log_config: &LogConfig) -> String {
-    let time_string = get_time(log_config.display.date, log_config.display.time);
-    let mut log_string = "".to_string();
-    if log_config.display.log_type {
-        log_string = format!(
-            "[{}] ",
-            match log_type {
-                LogType::Log => "LOG",
-                LogType::Warn => "WARN",
-                LogType::Error => "ERROR",
-            }
-        );
-    }
-    let string = format!("{time_string}{log_string}{string}");
-    let should_print_colored: bool;
-    if cfg!(windows) {
-        match windows_major_version_number() {
-            Ok(version_number) => {
-                if version_number >= 11 {
-                    should_print_colored = true;
-                } else {
-                    should_print_colored = false;
-                }
-            }
-            Err(()) => should_print_colored = false,
+//Structured information attached to a log line. It is only visible in the json log format.
+#[derive(Default)]
+pub struct LogDetails<'a> {
+    pub event: Option<&'a str>,
+    pub record_name: Option<&'a str>,
+    pub record_id: Option<&'a str>,
+    pub old_content: Option<&'a str>,
+    pub new_content: Option<&'a str>,
+    pub duration: Option<std::time::Duration>,
+}
+impl<'a> LogDetails<'a> {
+    pub fn event(event: &'a str) -> Self {
+        LogDetails {
+            event: Some(event),
+            ..Default::default()
         }
-    } else {
-        should_print_colored = true;
     }
-    if should_print_colored {
+    pub fn record(mut self, record: &'a DNSRecord) -> Self {
+        self.record_name = Some(&record.name);
+        self.record_id = Some(&record.id);
+        self.old_content = Some(&record.content);
+        self
+    }
+    pub fn old_content(mut self, old_content: &'a str) -> Self {
+        self.old_content = Some(old_content);
+        self
+    }
+    pub fn new_content(mut self, new_content: &'a str) -> Self {
+        self.new_content = Some(new_content);
+        self
+    }
+    pub fn duration(mut self, duration: std::time::Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+}
+pub fn log_to_console(string: &str, log_type: LogType, log_config: &LogConfig) -> String {
+    log_event_to_console(string, log_type, &LogDetails::default(), log_config)
+}
+fn log_event_to_console(
+    string: &str,
+    log_type: LogType,
+    details: &LogDetails,
+    log_config: &LogConfig,
+) -> String {
+    let string = format_log_line(string, log_type, details, &log_config.console);
+    if log_config.console.format != LogFormat::Json && should_print_colored() {
         let colored_string = match log_type {
             LogType::Log => string.white(),
             LogType::Warn => string.yellow(),
             LogType::Error => string.red(),
+            LogType::Debug => string.cyan(),
+            LogType::Trace => string.bright_black(),
         };
         println!("{colored_string}");
     } else {
@@ -701,25 +1581,90 @@ pub fn log_to_console(string: &str, log_type: LogType, log_config: &LogConfig) -
     }
     string
 }
-pub fn log_to_file_and_console(string: &str, log_type: LogType, log_config: &LogConfig) {
-    match log_type {
-        LogType::Log => {
-            if !log_config.show.logs {
-                return;
+fn format_log_line(
+    string: &str,
+    log_type: LogType,
+    details: &LogDetails,
+    sink: &SinkConfig,
+) -> String {
+    if sink.format == LogFormat::Json {
+        let mut json = serde_json::Map::new();
+        json.insert(
+            "timestamp".into(),
+            format_now("%Y-%m-%dT%H:%M:%S%.3f%:z", &sink.display.timezone).into(),
+        );
+        json.insert(
+            "level".into(),
+            match log_type {
+                LogType::Log => "info",
+                LogType::Warn => "warn",
+                LogType::Error => "error",
+                LogType::Debug => "debug",
+                LogType::Trace => "trace",
             }
-        }
-        LogType::Warn => {
-            if !log_config.show.warnings {
-                return;
+            .into(),
+        );
+        json.insert("event".into(), details.event.unwrap_or("message").into());
+        json.insert("message".into(), string.into());
+        let optional_fields = [
+            ("record_name", details.record_name),
+            ("record_id", details.record_id),
+            ("old_content", details.old_content),
+            ("new_content", details.new_content),
+        ];
+        for (key, value) in optional_fields {
+            if let Some(value) = value {
+                json.insert(key.into(), value.into());
             }
         }
-        LogType::Error => {
-            if !log_config.show.errors {
-                return;
-            }
+        if let Some(duration) = details.duration {
+            json.insert("duration_ms".into(), (duration.as_millis() as u64).into());
         }
+        return Value::Object(json).to_string();
     }
-    let string = log_to_console(string, log_type, log_config);
+    format_text_line(string, log_type, &sink.display)
+}
+fn format_text_line(string: &str, log_type: LogType, display: &DisplayConfig) -> String {
+    let time_string = get_time(display);
+    let mut log_string = "".to_string();
+    if display.log_type {
+        log_string = format!(
+            "[{}] ",
+            match log_type {
+                LogType::Log => "LOG",
+                LogType::Warn => "WARN",
+                LogType::Error => "ERROR",
+                LogType::Debug => "DEBUG",
+                LogType::Trace => "TRACE",
+            }
+        );
+    }
+    format!("{time_string}{log_string}{string}")
+}
+//What a log line looks like with the current display settings, shown while configuring them
+pub fn preview_log_line(display: &DisplayConfig) -> String {
+    format_text_line("Successfully set ip for example.com", LogType::Log, display)
+}
+pub fn log_to_file_and_console(string: &str, log_type: LogType, log_config: &LogConfig) {
+    log_event(string, log_type, &LogDetails::default(), log_config);
+}
+pub fn log_event(string: &str, log_type: LogType, details: &LogDetails, log_config: &LogConfig) {
+    if log_type.verbosity() <= get_console_level(log_config) {
+        log_event_to_console(string, log_type, details, log_config);
+    }
+    if log_type.verbosity() > get_file_level(log_config) {
+        return;
+    }
+    if log_config.outputs.syslog {
+        log_sinks::send_to_syslog(string, log_type, details, log_config);
+    }
+    if log_config.outputs.journald {
+        log_sinks::send_to_journald(string, log_type, details, log_config);
+    }
+    if !log_config.outputs.file {
+        return;
+    }
+    let string = format_log_line(string, log_type, details, &log_config.file);
     let folder_path = Path::new(&log_config.log_folder_path);
     let folder_path = folder_path.join("logs");
     if !folder_path.is_dir() {
@@ -740,12 +1685,13 @@ pub fn log_to_file_and_console(string: &str, log_type: LogType, log_config: &Log
     };
     let log_name = format!("session{}", session_number);
     let file_path = folder_path.join(format!("{log_name}.txt"));
+    log_rotation::rotate_if_needed(&file_path, log_config);
     if let Err(_) = write_to_file(&file_path, string, Some(log_config)) {}
 }
 pub fn format_err(err: impl Debug) -> String {
     format!(". Here's the error:\n-------\n{:#?}", err)
 }
-pub fn update_dns_list(config: &mut Config, config_path: &PathBuf) {
+pub fn update_dns_list(config: &mut Config, config_path: &Path) {
     let mut first_time = false;
     loop {
         if !first_time {
@@ -824,6 +1770,12 @@ pub fn update_dns_list(config: &mut Config, config_path: &PathBuf) {
             }
         }
 
+        if let Some(sync_record_names) = &config.sync_record_names {
+            for record in new_dns_records.iter_mut() {
+                record.sync = Some(sync_record_names.contains(&record.name));
+            }
+            new_record_references.clear();
+        }
         //Ask the user whether or not the new records should be synced if running in terminal
         if new_record_references.len() > 0 && is_terminal() {
             let mut records: Vec<DNSRecord> = vec![];
@@ -857,9 +1809,28 @@ pub fn update_dns_list(config: &mut Config, config_path: &PathBuf) {
                 }
             }
         }
+        //Fetching can take long while cloudflare can't be reached, so the file is only locked to be written. It's read
+        //again first, in case the dashboard, the tui or configure changed it in the meantime.
+        let _config_file_lock = lock_config_file();
+        if let Ok((mut current_config, _)) = read_config_file() {
+            if config.sync_record_names.is_none() {
+                for record in new_dns_records.iter_mut() {
+                    let current_sync = current_config
+                        .dns_config
+                        .iter()
+                        .find(|current_record| current_record.id == record.id)
+                        .and_then(|current_record| current_record.sync);
+                    if let Some(sync) = current_sync {
+                        record.sync = Some(sync);
+                    }
+                }
+            }
+            current_config.log_config.session_number = config.log_config.session_number;
+            *config = current_config;
+        }
         config.dns_config = new_dns_records;
         //Save new dns list
-        match config.save_to_json(&config_path) {
+        match config.save_to_file(config_path) {
             Ok(()) => log_to_file_and_console(
                 "Saved config successfully",
                 LogType::Log,
@@ -912,16 +1883,23 @@ pub fn create_selection_list(records: &Vec<DNSRecord>) -> ((Vec<String>, Vec<Str
     ((multiselected, ids), defaults)
 }
 fn get_dns_record_list(config: &Config) -> Result<String, ()> {
-    match ureq::get(&format!(
+    let url = format!(
         "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
         &config.authentication.zone_id
-    ))
-    .set("X-Auth-Email", &config.authentication.email)
-    .set("X-Auth-Key", &config.authentication.api_key)
-    .set("Content-Type", "application/json")
-    .call()
+    );
+    log_request("GET", &url, None, &config.log_config);
+    let request_start = std::time::Instant::now();
+    match ureq::get(&url)
+        .set("X-Auth-Email", &config.authentication.email)
+        .set("X-Auth-Key", &config.authentication.api_key)
+        .set("Content-Type", "application/json")
+        .call()
     {
         Ok(result) => {
+            metrics::with_metrics(|metrics| {
+                metrics.observe_api_request("list_records", request_start.elapsed())
+            });
+            let status = result.status();
             let result_string = match result.into_string() {
                 Ok(string) => string,
                 Err(err) => {
@@ -934,21 +1912,36 @@ fn get_dns_record_list(config: &Config) -> Result<String, ()> {
                     return Err(());
                 }
             };
+            log_response(
+                "GET",
+                &url,
+                Some(status),
+                &result_string,
+                request_start,
+                &config.authentication,
+                &config.log_config,
+            );
             let formatted_result_string =
                 jsonformat::format(&result_string, jsonformat::Indentation::Tab);
             match formatted_result_string.find("\"success\": true") {
-                Some(_) => log_to_file_and_console(
-                    "Successfully obtained DNS records",
-                    LogType::Log,
-                    &config.log_config,
-                ),
+                Some(_) => {
+                    health::set_credentials_valid(true);
+                    log_event(
+                        "Successfully obtained DNS records",
+                        LogType::Log,
+                        &LogDetails::event("dns_records_fetched"),
+                        &config.log_config,
+                    )
+                }
                 None => {
-                    log_to_file_and_console(
+                    report_api_errors(None, &result_string);
+                    log_event(
                         &format!(
                             "The cloudflare request was unsuccessful. Here's the result: {}",
                             formatted_result_string
                         ),
                         LogType::Warn,
+                        &LogDetails::event("api_error"),
                         &config.log_config,
                     );
                     return Err(());
@@ -957,11 +1950,27 @@ fn get_dns_record_list(config: &Config) -> Result<String, ()> {
             Ok(result_string)
         }
         Err(err) => {
-            log_to_file_and_console(
-                &format!("Couldn't send the list DNS request{}", format_err(err)),
+            metrics::with_metrics(|metrics| {
+                metrics.observe_api_request("list_records", request_start.elapsed())
+            });
+            let failed_request = read_failed_response(
+                "GET",
+                &url,
+                err,
+                request_start,
+                &config.authentication,
+                &config.log_config,
+            );
+            log_event(
+                &format!(
+                    "Couldn't send the list DNS request{}",
+                    format_err(&failed_request.error)
+                ),
                 LogType::Error,
+                &LogDetails::event("api_error"),
                 &config.log_config,
             );
+            report_failed_request(&failed_request);
             log_to_file_and_console("Retrying...", LogType::Error, &config.log_config);
             return Err(());
         }
@@ -1067,7 +2076,7 @@ fn convert_val_to_dns_record(val: &Value, config: &Config) -> Result<Option<DNSR
         },
     }))
 }
-fn is_terminal() -> bool {
+pub fn is_terminal() -> bool {
     let mut stdin_exists = false;
     let mut stdout_exists = false;
     let mut stderr_exists = false;
@@ -1118,17 +2127,36 @@ fn set_ip(
         &authentication.zone_id
     );
     request.push_str(&id);
+    let body = ureq::json!({
+      "name": name,
+      "content": ip,
+    });
+    log_request("PATCH", &request, Some(&body), log_config);
+    let request_start = std::time::Instant::now();
     match ureq::patch(&request)
         .set("X-Auth-Email", &authentication.email)
         .set("X-Auth-Key", &authentication.api_key)
         .set("Content-Type", "application/json")
-        .send_json(ureq::json!({
-          "name": name,
-          "content": ip,
-        })) {
+        .send_json(body)
+    {
         Ok(result) => {
+            metrics::with_metrics(|metrics| {
+                metrics.observe_api_request("update_record", request_start.elapsed())
+            });
+            let status = result.status();
             let result_string = match result.into_string() {
-                Ok(string) => jsonformat::format(&string, jsonformat::Indentation::Tab),
+                Ok(string) => {
+                    log_response(
+                        "PATCH",
+                        &request,
+                        Some(status),
+                        &string,
+                        request_start,
+                        authentication,
+                        log_config,
+                    );
+                    jsonformat::format(&string, jsonformat::Indentation::Tab)
+                }
                 Err(err) => {
                     log_to_file_and_console(
                         &format!(
@@ -1142,45 +2170,179 @@ fn set_ip(
                 }
             };
             match result_string.find("\"success\": true") {
-                Some(_) => {}
+                Some(_) => health::set_credentials_valid(true),
                 None => return Err(CustomError::UnsuccessfullCloudflareRequest(result_string)),
             }
         }
-        Err(err) => return Err(CustomError::UReqRequstFailed(err)),
+        Err(err) => {
+            metrics::with_metrics(|metrics| {
+                metrics.observe_api_request("update_record", request_start.elapsed())
+            });
+            return Err(CustomError::UReqRequstFailed(read_failed_response(
+                "PATCH",
+                &request,
+                err,
+                request_start,
+                authentication,
+                log_config,
+            )));
+        }
     };
     Ok(())
 }
-fn get_time(display_date: bool, display_time: bool) -> String {
-    let local: DateTime<Local> = Local::now();
-    let date = format!(
-        "{}/{}/{}",
-        convert_to_double_digits(local.day()),
-        convert_to_double_digits(local.month()),
-        convert_to_double_digits(local.year() as u32)
-    );
-    let time = format!(
-        "{}:{}:{}",
-        convert_to_double_digits(local.hour()),
-        convert_to_double_digits(local.minute()),
-        convert_to_double_digits(local.second())
+//Cloudflare answers most failed requests with an error status, but the error codes are in the body
+fn report_failed_request(failed_request: &FailedRequest) {
+    match failed_request.status {
+        Some(status) => report_api_errors(Some(status), &failed_request.body),
+        None => metrics::with_metrics(|metrics| metrics.count_api_error("transport")),
+    }
+}
+//Counts the error codes of a cloudflare response like {"success": false, "errors": [{"code": 9103, ...}]}
+fn report_api_errors(status: Option<u16>, body: &str) {
+    let codes: Vec<String> = match serde_json::from_str::<Value>(body) {
+        Ok(value) => value["errors"]
+            .as_array()
+            .map(|errors| {
+                errors
+                    .iter()
+                    .filter_map(|error| error["code"].as_i64())
+                    .map(|code| code.to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Err(_) => vec![],
+    };
+    health::check_api_errors(status, &codes);
+    metrics::with_metrics(|metrics| match (codes.is_empty(), status) {
+        (true, Some(status)) => metrics.count_api_error(&format!("http_{status}")),
+        (true, None) => metrics.count_api_error("unknown"),
+        (false, _) => {
+            for code in codes.iter() {
+                metrics.count_api_error(code);
+            }
+        }
+    });
+}
+//The credentials are only sent as headers, so they are never part of the logged url or body
+fn log_request(method: &str, url: &str, body: Option<&Value>, log_config: &LogConfig) {
+    log_to_file_and_console(&format!("{method} {url}"), LogType::Debug, log_config);
+    if let Some(body) = body {
+        log_to_file_and_console(
+            &format!("{method} {url} request body: {body}"),
+            LogType::Trace,
+            log_config,
+        );
+    }
+}
+fn log_response(
+    method: &str,
+    url: &str,
+    status: Option<u16>,
+    body: &str,
+    request_start: std::time::Instant,
+    authentication: &AuthenticationConfig,
+    log_config: &LogConfig,
+) {
+    if !is_log_type_enabled(LogType::Debug, log_config) {
+        return;
+    }
+    let status = match status {
+        Some(status) => status.to_string(),
+        None => "no status".to_string(),
+    };
+    log_to_file_and_console(
+        &format!(
+            "{method} {url} returned {status} after {} ms",
+            request_start.elapsed().as_millis()
+        ),
+        LogType::Debug,
+        log_config,
     );
-    if display_date && display_time {
-        format!("[{date} {time}] ")
-    } else if display_date && !display_time {
-        format!("[{date}] ")
-    } else if !display_date && display_time {
-        format!("[{time}] ")
+    let body = redact(body, authentication);
+    //The whole body is only logged at trace, since listing the DNS records returns every record of the zone
+    if is_log_type_enabled(LogType::Trace, log_config) {
+        log_to_file_and_console(
+            &format!("{method} {url} response body: {body}"),
+            LogType::Trace,
+            log_config,
+        );
     } else {
-        "".to_string()
+        let shortened: String = body.chars().take(500).collect();
+        let ellipsis = if shortened.len() < body.len() {
+            "..."
+        } else {
+            ""
+        };
+        log_to_file_and_console(
+            &format!("{method} {url} response body: {shortened}{ellipsis}"),
+            LogType::Debug,
+            log_config,
+        );
     }
 }
-fn convert_to_double_digits(num: u32) -> String {
-    if num >= 10 {
-        num.to_string()
-    } else {
-        format!("0{}", num.to_string())
+//The body of an error response can only be read once, so it's logged here and kept for report_failed_request
+fn read_failed_response(
+    method: &str,
+    url: &str,
+    err: ureq::Error,
+    request_start: std::time::Instant,
+    authentication: &AuthenticationConfig,
+    log_config: &LogConfig,
+) -> FailedRequest {
+    let error = err.to_string();
+    match err {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            log_response(
+                method,
+                url,
+                Some(status),
+                &body,
+                request_start,
+                authentication,
+                log_config,
+            );
+            FailedRequest {
+                error,
+                status: Some(status),
+                body,
+            }
+        }
+        ureq::Error::Transport(transport) => {
+            log_to_file_and_console(
+                &format!(
+                    "{method} {url} failed ({}) after {} ms",
+                    transport.kind(),
+                    request_start.elapsed().as_millis()
+                ),
+                LogType::Debug,
+                log_config,
+            );
+            FailedRequest {
+                error,
+                status: None,
+                body: String::new(),
+            }
+        }
     }
 }
+//Cloudflare doesn't echo the api key, but the account email can be part of a response
+fn redact(string: &str, authentication: &AuthenticationConfig) -> String {
+    let mut string = string.to_string();
+    for secret in [&authentication.api_key, &authentication.email] {
+        if !secret.is_empty() {
+            string = string.replace(secret.as_str(), "[REDACTED]");
+        }
+    }
+    string
+}
+fn get_time(display: &DisplayConfig) -> String {
+    let format = get_timestamp_format(display);
+    if format.is_empty() {
+        return "".to_string();
+    }
+    format!("[{}] ", format_now(&format, &display.timezone))
+}
 fn write_to_file(
     file_path: &Path,
     contents: String,
@@ -1299,6 +2461,63 @@ fn create_folder(folder_path: &Path, log_config: Option<&LogConfig>) -> Result<(
         }
     }
 }
+#[derive(Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+impl std::str::FromStr for ColorChoice {
+    type Err = ();
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(()),
+        }
+    }
+}
+static COLOR_CHOICE: OnceLock<ColorChoice> = OnceLock::new();
+static SHOULD_PRINT_COLORED: OnceLock<bool> = OnceLock::new();
+pub fn set_color_choice(choice: ColorChoice) {
+    let _ = COLOR_CHOICE.set(choice);
+}
+//Decided on the first colored line and then reused, since detecting the windows version is expensive
+fn should_print_colored() -> bool {
+    *SHOULD_PRINT_COLORED.get_or_init(|| {
+        let should_print_colored = match COLOR_CHOICE.get().copied().unwrap_or(ColorChoice::Auto) {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => detect_color_support(),
+        };
+        //colored reads the same variables on its own, this makes sure it agrees with the decision
+        colored::control::set_override(should_print_colored);
+        should_print_colored
+    })
+}
+//CLICOLOR_FORCE wins over NO_COLOR, which wins over CLICOLOR and the terminal check, like in most other programs
+fn detect_color_support() -> bool {
+    if std::env::var("CLICOLOR_FORCE").is_ok_and(|value| !value.is_empty() && value != "0") {
+        return true;
+    }
+    if std::env::var("NO_COLOR").is_ok_and(|value| !value.is_empty()) {
+        return false;
+    }
+    if std::env::var("CLICOLOR").is_ok_and(|value| value == "0") {
+        return false;
+    }
+    if !atty::is(atty::Stream::Stdout) || std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        return false;
+    }
+    if cfg!(windows) {
+        return match windows_major_version_number() {
+            Ok(version_number) => version_number >= 11,
+            Err(()) => false,
+        };
+    }
+    true
+}
 fn windows_major_version_number() -> Result<u32, ()> {
     let mut sys = System::new_all();
     sys.refresh_all();
@@ -0,0 +1,729 @@
+use std::{fs, net::ToSocketAddrs, path::Path, process};
+
+use serde_json::Value;
+
+use crate::{
+    chat::ChatService,
+    config_file::{self, ConfigFormat},
+    control,
+    email::{self, SmtpSecurity},
+    environment, migrations,
+    notifications::{self, Notification, QuietHoursConfig},
+    unending_process::{
+        get_config_path, is_valid_timestamp_format, parse_config_file, AuthenticationConfig,
+        IncompleteConfig, LogTimezone,
+    },
+};
+
+//The most cloudflare lists in one page of DNS records
+const RECORDS_PER_PAGE: u32 = 100;
+
+struct Report {
+    problems: usize,
+    warnings: usize,
+}
+impl Report {
+    fn ok(&self, message: &str) {
+        println!("[OK] {message}");
+    }
+    fn warning(&mut self, message: &str) {
+        self.warnings += 1;
+        println!("[WARNING] {message}");
+    }
+    fn problem(&mut self, message: &str) {
+        self.problems += 1;
+        println!("[PROBLEM] {message}");
+    }
+}
+//Checks the config file, the credentials and the log folder without changing anything, and exits with 1 if there are problems
+pub fn validate() {
+    let mut report = Report {
+        problems: 0,
+        warnings: 0,
+    };
+    let config = match check_config_file(&mut report) {
+        Some(config) => config,
+        None => finish(report),
+    };
+    check_values(&config, &mut report);
+    match &config.authentication {
+        Some(authentication) => {
+            if check_credentials(authentication, &mut report) {
+                check_records(&config, authentication, &mut report);
+            }
+        }
+        None => report.problem("There are no credentials, run the configure command or set CF_DNS_SYNC_EMAIL, CF_DNS_SYNC_API_KEY and CF_DNS_SYNC_ZONE_ID"),
+    }
+    check_log_folder(&config, &mut report);
+    finish(report);
+}
+fn finish(report: Report) -> ! {
+    println!();
+    if report.problems == 0 {
+        println!("The configuration is valid ({} warnings)", report.warnings);
+        process::exit(0);
+    }
+    println!(
+        "Found {} problems and {} warnings",
+        report.problems, report.warnings
+    );
+    process::exit(1);
+}
+fn check_config_file(report: &mut Report) -> Option<IncompleteConfig> {
+    let config_path = match get_config_path() {
+        Ok(config_path) => config_path,
+        Err(()) => {
+            report.problem("Couldn't determine where the config file is");
+            return None;
+        }
+    };
+    if !config_path.exists() {
+        if environment::has_authentication() {
+            report.ok(&format!(
+                "There is no config file at {}, using the environment variables only",
+                config_path.display()
+            ));
+            let mut config = IncompleteConfig::from_defaults();
+            check_environment(&mut config, report);
+            return Some(config);
+        }
+        report.problem(&format!(
+            "There is no config file at {}",
+            config_path.display()
+        ));
+        return None;
+    }
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            report.problem(&format!(
+                "Couldn't read the config file at {}: {err}",
+                config_path.display()
+            ));
+            return None;
+        }
+    };
+    let format = ConfigFormat::from_path(&config_path);
+    let mut config = match parse_config_file(&config_path, &contents) {
+        Ok((config, migration_report)) => {
+            report.ok(&format!(
+                "The config file at {} is valid {}",
+                config_path.display(),
+                format.name()
+            ));
+            if let Some(migration_report) = migration_report {
+                report.warning(&format!(
+                    "The config file is version {} and will be migrated to version {} the next time it is loaded",
+                    migration_report.from_version, migration_report.to_version
+                ));
+            }
+            config
+        }
+        Err(err) => {
+            report.problem(&format!(
+                "The config file at {} is invalid\n{err}",
+                config_path.display()
+            ));
+            return None;
+        }
+    };
+    check_unknown_fields(&contents, format, report);
+    check_environment(&mut config, report);
+    if config.environment_overrides {
+        report.ok("CF_DNS_SYNC_* environment variables override some values of the config file");
+    }
+    Some(config)
+}
+//An invalid variable is left out, so the rest of the config can still be checked
+fn check_environment(config: &mut IncompleteConfig, report: &mut Report) {
+    if let Err(errors) = environment::try_apply_overrides(config) {
+        for error in errors {
+            report.problem(&error);
+        }
+    }
+}
+fn check_unknown_fields(contents: &str, format: ConfigFormat, report: &mut Report) {
+    let mut value: Value = match config_file::parse(contents, format) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    if migrations::migrate(&mut value).is_err() {
+        return;
+    }
+    //The version is only read by the migrations, so IncompleteConfig doesn't know about it
+    if let Some(config) = value.as_object_mut() {
+        config.remove("version");
+    }
+    let mut unknown_fields: Vec<String> = vec![];
+    let result: Result<IncompleteConfig, _> = serde_ignored::deserialize(value, |path| {
+        unknown_fields.push(path.to_string());
+    });
+    if result.is_err() {
+        return;
+    }
+    for field in unknown_fields {
+        match find_line(contents, &field, format) {
+            Some(line) => report.problem(&format!("Unknown field {field} on line {line}")),
+            None => report.problem(&format!("Unknown field {field}")),
+        }
+    }
+}
+//Finds the first line where the field is defined, so unknown fields can be pointed at. The field is a path like
+//log_config.console.color or dns_config.2.comment, and every key of it has to match, not only the last one.
+pub(crate) fn find_line(contents: &str, field: &str, format: ConfigFormat) -> Option<usize> {
+    //Which element of a list the field is in isn't tracked, the first element that has it is found
+    let field: Vec<&str> = field
+        .split('.')
+        .filter(|key| key.parse::<usize>().is_err())
+        .collect();
+    match format {
+        ConfigFormat::Json => find_json_line(contents, &field),
+        ConfigFormat::Toml => find_toml_line(contents, &field),
+        ConfigFormat::Yaml => find_yaml_line(contents, &field),
+    }
+}
+fn is_inside(path: &[String], field: &[&str]) -> bool {
+    path.len() >= field.len()
+        && field
+            .iter()
+            .zip(path)
+            .all(|(key, path_key)| key == path_key)
+}
+fn split_toml_key(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|key| key.trim().trim_matches(['"', '\'']).to_string())
+        .collect()
+}
+fn find_toml_line(contents: &str, field: &[&str]) -> Option<usize> {
+    let mut table: Vec<String> = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let path = if line.starts_with('[') {
+            //[table] and [[array of tables]]
+            let header = line
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or_default();
+            table = split_toml_key(header);
+            table.clone()
+        } else {
+            match line.split_once('=') {
+                Some((key, _)) => [table.clone(), split_toml_key(key)].concat(),
+                None => continue,
+            }
+        };
+        if is_inside(&path, field) {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+fn find_yaml_line(contents: &str, field: &[&str]) -> Option<usize> {
+    //The keys the current line is nested in, with their indentation
+    let mut parents: Vec<(usize, String)> = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let mut rest = line.trim_start();
+        if rest.is_empty() || rest.starts_with('#') {
+            continue;
+        }
+        let mut indentation = line.len() - rest.len();
+        //The key of a list item is indented by the dash and the spaces after it
+        while let Some(after_dash) = rest.strip_prefix('-') {
+            let item = after_dash.trim_start();
+            if item.len() == after_dash.len() && !after_dash.is_empty() {
+                break;
+            }
+            indentation += rest.len() - item.len();
+            rest = item;
+        }
+        let key = match rest.split_once(':') {
+            Some((key, value)) if value.is_empty() || value.starts_with([' ', '\t']) => {
+                key.trim().trim_matches(['"', '\''])
+            }
+            _ => continue,
+        };
+        parents.retain(|(parent_indentation, _)| *parent_indentation < indentation);
+        let mut path: Vec<String> = parents.iter().map(|(_, key)| key.clone()).collect();
+        path.push(key.to_string());
+        if is_inside(&path, field) {
+            return Some(index + 1);
+        }
+        parents.push((indentation, key.to_string()));
+    }
+    None
+}
+fn find_json_line(contents: &str, field: &[&str]) -> Option<usize> {
+    //The key every open object or list was opened under, None for the root and for objects in lists
+    let mut containers: Vec<Option<String>> = vec![];
+    let mut last_string: Option<String> = None;
+    let mut key: Option<String> = None;
+    let mut line = 1;
+    let mut characters = contents.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '"' => {
+                let mut string = String::new();
+                while let Some(character) = characters.next() {
+                    match character {
+                        '\\' => {
+                            characters.next();
+                        }
+                        '"' => break,
+                        character => string.push(character),
+                    }
+                }
+                last_string = Some(string);
+            }
+            ':' => {
+                if let Some(string) = last_string.take() {
+                    let mut path: Vec<String> = containers.iter().flatten().cloned().collect();
+                    path.push(string.clone());
+                    if is_inside(&path, field) {
+                        return Some(line);
+                    }
+                    key = Some(string);
+                }
+            }
+            '{' | '[' => containers.push(key.take()),
+            '}' | ']' => {
+                containers.pop();
+                key = None;
+            }
+            ',' => {
+                last_string = None;
+                key = None;
+            }
+            '\n' => line += 1,
+            _ => {}
+        }
+    }
+    None
+}
+fn check_values(config: &IncompleteConfig, report: &mut Report) {
+    if config.seconds_to_wait_per_restart == 0 {
+        report.problem("seconds_to_wait_per_restart is 0, which would send requests to cloudflare without any pause");
+    }
+    if let Some(authentication) = &config.authentication {
+        if !authentication.email.contains('@') {
+            report.warning(&format!(
+                "The email {} doesn't look like a mail address",
+                authentication.email
+            ));
+        }
+        if authentication.api_key.trim().is_empty() {
+            report.problem("The API key is empty");
+        }
+        if authentication.zone_id.trim().is_empty() {
+            report.problem("The zone id is empty");
+        }
+    }
+    for (sink_name, sink) in [
+        ("console", &config.log_config.console),
+        ("file", &config.log_config.file),
+    ] {
+        if LogTimezone::parse(&sink.display.timezone).is_none() {
+            report.problem(&format!(
+                "The {sink_name} timezone {} isn't known, local time would be used",
+                sink.display.timezone
+            ));
+        }
+        if let Some(format) = &sink.display.timestamp_format {
+            if !is_valid_timestamp_format(format) {
+                report.problem(&format!(
+                    "The {sink_name} timestamp format {format} isn't a valid strftime format"
+                ));
+            }
+        }
+    }
+    match &config.sync_record_names {
+        //CF_DNS_SYNC_RECORDS replaces the sync choices of the config file, its names are looked up in the zone by check_records
+        Some(names) => {
+            if names.is_empty() {
+                report
+                    .warning("No records are selected to be synced, CF_DNS_SYNC_RECORDS is empty");
+            }
+        }
+        None => {
+            for record in config.dns_config.iter() {
+                if record.sync == Some(true) && record.record_type != "A" {
+                    report.problem(&format!(
+                        "The record {} is synced but is of type {}, only A records can be synced",
+                        record.name, record.record_type
+                    ));
+                }
+            }
+            let synced_records = config
+                .dns_config
+                .iter()
+                .filter(|record| record.sync == Some(true))
+                .count();
+            if synced_records == 0 {
+                report.warning("No records are selected to be synced");
+            }
+        }
+    }
+    for webhook in config.notifications.webhooks.iter() {
+        let host = notifications::get_host(&webhook.url);
+        if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+            report.problem(&format!(
+                "The webhook url for {host} must start with http:// or https://"
+            ));
+        }
+        //Sending ignores the case of the method, so validating does too
+        if !["GET", "POST", "PUT", "PATCH"]
+            .iter()
+            .any(|method| webhook.method.eq_ignore_ascii_case(method))
+        {
+            report.problem(&format!(
+                "The webhook method {} for {host} isn't GET, POST, PUT or PATCH",
+                webhook.method
+            ));
+        }
+        if webhook.events.is_empty() {
+            report.warning(&format!("The webhook for {host} has no events"));
+        }
+        //A template with a custom Content-Type doesn't have to be json
+        let is_json = webhook
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .is_none_or(|(_, value)| value.contains("json"));
+        let body = notifications::render_body(webhook, &Notification::test(vec![]));
+        if is_json && serde_json::from_str::<Value>(&body).is_err() {
+            report.problem(&format!(
+                "The body template of the webhook for {host} doesn't produce valid json"
+            ));
+        }
+    }
+    if let Some(email) = &config.notifications.email {
+        if email.host.trim().is_empty() {
+            report.problem("The SMTP host is empty");
+        }
+        if email.to.is_empty() {
+            report.problem("The email settings have no recipients");
+        }
+        if let Err(err) = email::build_message(email, &Notification::test(vec![])) {
+            report.problem(&format!("The email settings are invalid, {err}"));
+        }
+        if email.username.is_some() && email.password.is_none() {
+            report.warning("The SMTP username is set but there is no password");
+        }
+        if email.security == SmtpSecurity::None && email.username.is_some() {
+            report.warning(
+                "The SMTP password would be sent without encryption, since security is none",
+            );
+        }
+        if email.max_per_hour == 0 {
+            report.warning("max_per_hour is 0, so no emails would be sent");
+        }
+    }
+    for chat in config.notifications.chat.iter() {
+        let name = format!(
+            "{} chat for {}",
+            chat.service.name(),
+            notifications::get_host(&chat.url)
+        );
+        //A bare ntfy topic is sent to ntfy.sh
+        let is_topic = chat.service == ChatService::Ntfy && !chat.url.contains("://");
+        if !is_topic && !chat.url.starts_with("http://") && !chat.url.starts_with("https://") {
+            report.problem(&format!(
+                "The url of the {name} must start with http:// or https://"
+            ));
+        }
+        if chat.service == ChatService::Matrix {
+            if chat.room_id.is_none() {
+                report.problem(&format!("The {name} has no room_id"));
+            }
+            if chat.token.is_none() {
+                report.problem(&format!("The {name} has no access token"));
+            }
+        }
+        if chat.events.is_empty() {
+            report.warning(&format!("The {name} has no events"));
+        }
+    }
+    for (kind, hooks) in [
+        ("pre_update", &config.hooks.pre_update),
+        ("post_update", &config.hooks.post_update),
+    ] {
+        for hook in hooks.iter() {
+            if hook.command.trim().is_empty() {
+                report.problem(&format!("A {kind} hook has no command"));
+            }
+            if hook.timeout_seconds == 0 {
+                report.problem(&format!(
+                    "The {kind} hook {} has a timeout of 0 seconds, so it would always be stopped",
+                    hook.command
+                ));
+            }
+        }
+    }
+    for (name, enabled, listen) in [
+        ("metrics", config.metrics.enabled, &config.metrics.listen),
+        ("health check", config.health.enabled, &config.health.listen),
+        (
+            "dashboard",
+            config.dashboard.enabled,
+            &config.dashboard.listen,
+        ),
+    ] {
+        if enabled && listen.to_socket_addrs().is_err() {
+            report.problem(&format!(
+                "The {name} address {listen} isn't a host and port like 127.0.0.1:9469"
+            ));
+        }
+    }
+    if config.health.enabled && config.health.stale_after_intervals == 0 {
+        report.problem("health.stale_after_intervals is 0, so /readyz would fail between syncs");
+    }
+    if config.dashboard.enabled {
+        if config
+            .dashboard
+            .password
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+        {
+            report.problem("The dashboard has no password, so it wouldn't be served");
+        }
+        if control::get_loopback(&config.dashboard.listen) == Some(false) {
+            report.warning(&format!(
+                "The dashboard is served on {} without https, so the password can be read on the network. Put it behind a reverse proxy with https.",
+                config.dashboard.listen
+            ));
+        }
+    }
+    if config.mqtt.enabled {
+        if config.mqtt.host.trim().is_empty() {
+            report.problem("The MQTT broker has no host");
+        }
+        if config.mqtt.port == 0 {
+            report.problem("The MQTT broker port is 0");
+        }
+        if config.mqtt.username.is_some() && config.mqtt.password.is_none() {
+            report.warning("The MQTT broker has a username but no password");
+        }
+        let mut prefixes = vec![("topic_prefix", &config.mqtt.topic_prefix)];
+        if config.mqtt.discovery {
+            prefixes.push(("discovery_prefix", &config.mqtt.discovery_prefix));
+        }
+        for (name, prefix) in prefixes {
+            if prefix.is_empty() || prefix.contains(['+', '#']) {
+                report.problem(&format!(
+                    "The MQTT {name} \"{prefix}\" is empty or has a + or #, which can't be published to"
+                ));
+            }
+        }
+    }
+    if let Some(listen) = &config.control.listen {
+        match control::get_loopback(listen) {
+            Some(true) => {}
+            Some(false) => report.problem(&format!(
+                "The control api address {listen} isn't a loopback address like 127.0.0.1:9470, so the api wouldn't be served"
+            )),
+            None => report.problem(&format!(
+                "The control api address {listen} isn't a host and port like 127.0.0.1:9470"
+            )),
+        }
+        if config
+            .control
+            .token
+            .as_ref()
+            .is_none_or(|token| token.is_empty())
+        {
+            report.warning(&format!(
+                "The control api on {listen} only answers status and records, since control.token isn't set"
+            ));
+        }
+    }
+    if let Some(quiet_hours) = &config.notifications.quiet_hours {
+        for time in [&quiet_hours.start, &quiet_hours.end] {
+            if QuietHoursConfig::parse_time(time).is_none() {
+                report.problem(&format!(
+                    "The quiet hours time {time} isn't written like 22:00, so there are no quiet hours"
+                ));
+            }
+        }
+        if LogTimezone::parse(&quiet_hours.timezone).is_none() {
+            report.warning(&format!(
+                "The quiet hours timezone {} isn't known, local time would be used",
+                quiet_hours.timezone
+            ));
+        }
+    }
+}
+fn cloudflare_get(url: &str, authentication: &AuthenticationConfig) -> Result<Value, String> {
+    let response = match ureq::get(url)
+        .set("X-Auth-Email", &authentication.email)
+        .set("X-Auth-Key", &authentication.api_key)
+        .set("Content-Type", "application/json")
+        .call()
+    {
+        Ok(response) => response,
+        //Cloudflare explains what went wrong in the body of error responses
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => return Err(format!("The request couldn't be sent: {err}")),
+    };
+    let json: Value = match response.into_json() {
+        Ok(json) => json,
+        Err(err) => return Err(format!("The response isn't valid json: {err}")),
+    };
+    if json.get("success") == Some(&Value::Bool(true)) {
+        return Ok(json);
+    }
+    let errors: Vec<String> = match json.get("errors").and_then(|errors| errors.as_array()) {
+        Some(errors) => errors
+            .iter()
+            .map(|error| {
+                format!(
+                    "{} (code {})",
+                    error
+                        .get("message")
+                        .and_then(|message| message.as_str())
+                        .unwrap_or("Unknown error"),
+                    error.get("code").unwrap_or(&Value::Null)
+                )
+            })
+            .collect(),
+        None => vec![],
+    };
+    if errors.is_empty() {
+        Err("Cloudflare reported the request as unsuccessful".to_string())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+fn check_credentials(authentication: &AuthenticationConfig, report: &mut Report) -> bool {
+    let url = format!(
+        "https://api.cloudflare.com/client/v4/zones/{}",
+        authentication.zone_id
+    );
+    match cloudflare_get(&url, authentication) {
+        Ok(json) => {
+            let zone_name = json
+                .get("result")
+                .and_then(|result| result.get("name"))
+                .and_then(|name| name.as_str())
+                .unwrap_or("unknown");
+            report.ok(&format!(
+                "The credentials are valid and have access to the zone {zone_name}"
+            ));
+            true
+        }
+        Err(err) => {
+            report.problem(&format!(
+                "The credentials or the zone id were rejected by cloudflare: {err}"
+            ));
+            false
+        }
+    }
+}
+fn check_records(
+    config: &IncompleteConfig,
+    authentication: &AuthenticationConfig,
+    report: &mut Report,
+) {
+    if let Some(names) = &config.sync_record_names {
+        check_record_names(names, authentication, report);
+        return;
+    }
+    for record in config.dns_config.iter() {
+        if record.sync != Some(true) {
+            continue;
+        }
+        let url = format!(
+            "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
+            authentication.zone_id, record.id
+        );
+        match cloudflare_get(&url, authentication) {
+            Ok(_) => report.ok(&format!("The synced record {} exists", record.name)),
+            Err(err) => report.problem(&format!(
+                "The synced record {} ({}) couldn't be found: {err}",
+                record.name, record.id
+            )),
+        }
+    }
+}
+//The names of CF_DNS_SYNC_RECORDS are matched against every record of the zone, one page of records at a time
+fn check_record_names(
+    names: &[String],
+    authentication: &AuthenticationConfig,
+    report: &mut Report,
+) {
+    let mut records: Vec<Value> = vec![];
+    let mut page = 1;
+    loop {
+        let url = format!(
+            "https://api.cloudflare.com/client/v4/zones/{}/dns_records?page={page}&per_page={RECORDS_PER_PAGE}",
+            authentication.zone_id
+        );
+        let json = match cloudflare_get(&url, authentication) {
+            Ok(json) => json,
+            Err(err) => {
+                report.problem(&format!(
+                    "The DNS records of the zone couldn't be listed: {err}"
+                ));
+                return;
+            }
+        };
+        records.extend(json["result"].as_array().cloned().unwrap_or_default());
+        let total_pages = json["result_info"]["total_pages"].as_u64().unwrap_or(1);
+        if page >= total_pages {
+            break;
+        }
+        page += 1;
+    }
+    for name in names {
+        let record_types: Vec<&str> = records
+            .iter()
+            .filter(|record| record["name"].as_str() == Some(name.as_str()))
+            .filter_map(|record| record["type"].as_str())
+            .collect();
+        if record_types.contains(&"A") {
+            report.ok(&format!("The synced record {name} exists"));
+        } else if record_types.is_empty() {
+            report.problem(&format!(
+                "The record {name} from CF_DNS_SYNC_RECORDS doesn't exist in the zone"
+            ));
+        } else {
+            report.problem(&format!(
+                "The record {name} from CF_DNS_SYNC_RECORDS is of type {}, only A records can be synced",
+                record_types.join(", ")
+            ));
+        }
+    }
+}
+fn check_log_folder(config: &IncompleteConfig, report: &mut Report) {
+    let log_folder_path = Path::new(&config.log_config.log_folder_path);
+    let logs_path = log_folder_path.join("logs");
+    //The logs folder is created when the first line is logged, so its parent must be writable if it doesn't exist yet
+    let folder_path = if logs_path.is_dir() {
+        logs_path
+    } else if log_folder_path.is_dir() {
+        log_folder_path.to_path_buf()
+    } else {
+        report.problem(&format!(
+            "The log folder {} doesn't exist",
+            log_folder_path.display()
+        ));
+        return;
+    };
+    let test_path = folder_path.join(".cf_dns_sync_write_test");
+    match fs::write(&test_path, "") {
+        Ok(()) => {
+            let _ = fs::remove_file(&test_path);
+            report.ok(&format!(
+                "The log folder {} is writable",
+                folder_path.display()
+            ));
+        }
+        Err(err) => report.problem(&format!(
+            "The log folder {} isn't writable: {err}",
+            folder_path.display()
+        )),
+    }
+}